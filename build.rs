@@ -1,8 +1,13 @@
 extern crate pkg_config;
 
 use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
 
 fn main() {
+    build_info();
+
     if cfg!(feature = "dox") {
         return;
     }
@@ -20,3 +25,70 @@ fn main() {
             .unwrap();
     }
 }
+
+// For the About dialog, see macros::build_info()
+// Anything we can't find out is "unknown", it's not worth failing the build over.
+fn build_info() {
+    println!("cargo:rerun-if-changed=build.rs");
+    println!("cargo:rerun-if-changed=Cargo.lock");
+
+    let target = env::var("TARGET").unwrap_or_else(|_| "unknown".to_string());
+    println!("cargo:rustc-env=TCSSH_BUILD_TARGET={}", target);
+
+    // the C libraries, as pkg-config sees them
+    println!(
+        "cargo:rustc-env=TCSSH_BUILD_X11={}",
+        pkg_config_version("x11")
+    );
+    println!(
+        "cargo:rustc-env=TCSSH_BUILD_GTK={}",
+        pkg_config_version("gtk+-3.0")
+    );
+
+    // the crates, which are "*" in Cargo.toml, so go by what Cargo.lock picked.
+    let lock = env::var("CARGO_MANIFEST_DIR")
+        .ok()
+        .and_then(|dir| fs::read_to_string(Path::new(&dir).join("Cargo.lock")).ok())
+        .unwrap_or_default();
+    for &(krate, var) in &[
+        ("gtk", "TCSSH_BUILD_GTK_CRATE"),
+        ("trust-dns-resolver", "TCSSH_BUILD_TRUST_DNS"),
+        ("x11", "TCSSH_BUILD_X11_CRATE"),
+    ] {
+        println!("cargo:rustc-env={}={}", var, locked_version(&lock, krate));
+    }
+}
+
+fn pkg_config_version(lib: &str) -> String {
+    match Command::new("pkg-config")
+        .arg("--modversion")
+        .arg(lib)
+        .output()
+    {
+        Ok(ref out) if out.status.success() => {
+            String::from_utf8_lossy(&out.stdout).trim().to_string()
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+// Cargo.lock has
+//     [[package]]
+//     name = "gtk"
+//     version = "0.5.0"
+fn locked_version(lock: &str, krate: &str) -> String {
+    let name = format!("name = \"{}\"", krate);
+    let mut lines = lock.lines();
+    while let Some(line) = lines.next() {
+        if line.trim() != name {
+            continue;
+        }
+        if let Some(version) = lines.next() {
+            let version = version.trim();
+            if version.starts_with("version = ") {
+                return version["version = ".len()..].trim_matches('"').to_string();
+            }
+        }
+    }
+    "unknown".to_string()
+}
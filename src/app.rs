@@ -1,8 +1,14 @@
+use gtk::CheckMenuItem;
+use libc;
+use std::borrow::Cow;
 use std::cell::RefCell;
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
+use std::fs;
+use std::path::PathBuf;
 use std::process;
 use std::rc::Rc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
 use structopt::StructOpt;
 
 use crate::cluster;
@@ -11,7 +17,13 @@ use crate::er::Result;
 use crate::evaluate;
 use crate::g::GtkStuff;
 use crate::getopt;
+use crate::host;
+use crate::log;
+use crate::macros;
+use crate::probe;
+use crate::recent;
 use crate::retile;
+use crate::secret;
 use crate::send_text;
 use crate::server;
 use crate::text2x11;
@@ -25,11 +37,16 @@ pub struct App {
     getopt: getopt::Getopt,
     pub servers: BTreeMap<String, server::Server>,
     pub dead_servers: Vec<String>,
+    pub dead_menu_items: Vec<(String, CheckMenuItem)>, // (connect_string, item) see keep_dead_in_menu
     pub xdisplay: x::XDisplay,
     pub gtkstuff: Option<GtkStuff>,
     pub text2x11: Option<text2x11::Text2X11>,
 
     pub internal_activate_autoquit: bool,
+    // no console, see --batch and --no-console
+    headless: bool,
+    preview_pending: Option<String>, // preview_send, shown but not sent yet
+    pub recent_tags: Vec<String>,    // most recent first, see note_recent_tags()
     font_w: u32,
     font_h: u32,
     me: String,
@@ -55,19 +72,30 @@ impl App {
         let mut app = App {
             cluster: Default::default(),
             config: Default::default(),
-            getopt: getopt::Getopt::from_args(), // parses CLI --args
+            getopt: getopt::Getopt::from_env_args(), // parses CLI --args
             servers: BTreeMap::new(),
             dead_servers: Vec::new(),
+            dead_menu_items: Vec::new(),
             xdisplay: Default::default(),
             gtkstuff: Default::default(),
             text2x11: Default::default(),
             internal_activate_autoquit: false,
+            headless: false,
+            preview_pending: None,
+            recent_tags: Vec::new(),
             font_w: 0,
             font_h: 0,
             me: me.into(),
             events: VecDeque::with_capacity(4),
         };
 
+        // Before reading config, checking for xterm, or opening the display,
+        // so it works anywhere --help does.
+        if app.getopt.examples {
+            print!("{}", examples());
+            process::exit(0);
+        }
+
         // Populate app.config by reading config file which is
         // either specified on CLI --config_file=foo
         // or default ~/.tcssh/config or even ~/.clusterssh/config
@@ -80,22 +108,35 @@ impl App {
         // If there was an --arg it should override config file value.
         app.getopt.override_config_with_args(&mut app.config)?;
 
-        if app.getopt.dump_config {
-            config::dump_config(&app.config);
+        macros::set_debug(macros::parse_debug(&app.getopt.debug)?);
+
+        if app.getopt.dump_config || app.getopt.dump_config_commented {
+            config::dump_config(&app.config, app.getopt.dump_config_commented);
             app.exit_prog();
         }
 
+        // Before opening the display, so it works without one.
+        if let Some(ref host_str) = app.getopt.print_command {
+            println!("{}", server::print_command(host_str, &app.config, &app.me)?);
+            process::exit(0);
+        }
+
         Ok(Rc::new(RefCell::new(app)))
     }
 
     pub fn run(&mut self, rself: &Rapp) -> Result<()> {
-        self.xdisplay = x::XDisplay::new()?;
+        self.xdisplay = x::XDisplay::new(x::x_retries(self.getopt.x_retries))?;
 
         if let Some(ref evaluate) = self.getopt.evaluate {
             evaluate::evaluate_commands(evaluate, &self.config);
             self.exit_prog();
         }
 
+        // before get_font_size(), so the tiling fits the font the terminals get.
+        if let Some(font) = font_for_screen(&self.config, &self.getopt, &self.xdisplay) {
+            self.config.terminal.font = Cow::Owned(font);
+        }
+
         // I'd like to write the next 3 lines as one
         // (self.font_w, self.font_h) = self.get_font_size()?;
         // but the above yields E0070 "left-hand ... not valid"
@@ -109,12 +150,20 @@ impl App {
 
         self.cluster.get_cluster_entries(&mut self.config)?;
         self.cluster.get_tag_entries(&mut self.config)?;
+        set_color_tags(&mut self.config, &self.cluster);
 
         if self.getopt.list.is_some() {
             self.handle_list();
             self.exit_prog();
         }
 
+        self.recent_tags = match self.recent_file() {
+            Some(recent_file) => recent::read(&recent_file),
+            None => Vec::new(),
+        };
+        let given = self.getopt.hosts.clone();
+        self.note_recent_tags(&given);
+
         if self.getopt.hosts.is_empty() {
             if let Some(hosts) = self.cluster.get_tag("default") {
                 self.getopt.hosts.extend_from_slice(hosts);
@@ -123,9 +172,38 @@ impl App {
 
         self.resolve_names(true)?;
 
-        let g = GtkStuff::create_windows(&self.config, rself)?;
+        if self.getopt.probe {
+            probe::probe_hosts(
+                &mut self.getopt.hosts,
+                &self.config,
+                Duration::from_millis(self.config.misc.probe_timeout_ms),
+                &probe::TcpConnector,
+            );
+        }
+
+        // --batch, no console. main() skips gtk::main() and exit_prog(),
+        // so the terminals outlive us.
+        if self.getopt.batch {
+            return self.run_batch();
+        }
+
+        let g = match GtkStuff::create_windows(&self.config, rself) {
+            Ok(g) => g,
+            Err(e) => {
+                if !self.getopt.no_console {
+                    return Err(e);
+                }
+                // --no-console, the same as --batch from here on.
+                eprintln!(
+                    "Warn: {}, opening the terminals without a console, nothing will broadcast to them",
+                    e
+                );
+                return self.run_batch();
+            }
+        };
 
         g.create_menubar(self, rself);
+        g.setup_autohide(self, rself);
         g.change_main_window_title(self);
         g.capture_map_events();
 
@@ -139,28 +217,74 @@ impl App {
             &self.config,
             &mut self.internal_activate_autoquit,
             &self.me,
+            self.getopt.progress,
+            true,
+            false,
         )?;
 
         g.build_hosts_menu(self, rself);
 
         self.gtkstuff = Some(g);
 
-        if self.config.misc.window_tiling {
-            self.retile_hosts(false, false)?;
+        if self.config.misc.window_tiling && self.config.misc.fast_startup {
+            // fast_startup, skip show_console()'s hide and count down this once,
+            // which can flash the console before the terminals appear.
+            // The WM may put a terminal over it, until the next retile.
+            if !retile::retile_hosts(self, false)? {
+                self.xdisplay.flush();
+                if let Some(ref mut g) = self.gtkstuff {
+                    g.show_main_window();
+                }
+            }
+        } else if self.config.misc.window_tiling {
+            self.retile_hosts(false, false, retile::RetileScope::All)?;
         } else {
             self.show_console()?;
         }
 
+        // Nothing to open, so let the user pick.
+        // If they cancel then they're left with the usual empty console.
+        if self.getopt.hosts.is_empty() && self.config.misc.open_dialog_when_empty {
+            if let Some(ref g) = self.gtkstuff {
+                g.open_add_hosts_dialog();
+            }
+        }
+
+        Ok(())
+    }
+
+    fn run_batch(&mut self) -> Result<()> {
+        self.headless = true;
+        server::open_client_windows(
+            &self.getopt.hosts,
+            &mut self.servers,
+            &self.config,
+            &mut self.internal_activate_autoquit,
+            &self.me,
+            self.getopt.progress,
+            true,
+            false,
+        )?;
+        if self.config.misc.window_tiling {
+            self.retile_hosts(false, false, retile::RetileScope::All)?;
+        }
+        self.xdisplay.flush();
         Ok(())
     }
 
+    pub fn is_headless(&self) -> bool {
+        self.headless
+    }
+
     pub fn show_console(&mut self) -> Result<()> {
         self.xdisplay.flush();
 
-        self.sleep(200);
+        self.sleep(self.config.tcssh.console_sleep_ms);
         if let Some(ref mut gtkstuff) = self.gtkstuff {
-            if self.servers.is_empty() {
+            if self.servers.is_empty() || self.config.misc.console_redraw_direct {
                 // There are no servers/xterms, then go ahead and show it right away.
+                // Same for console_redraw_mode=direct, where the hide and count down
+                // below just makes compositing WMs blink the console.
                 gtkstuff.show_main_window();
             } else {
                 // This seems odd, we're show_console(), but doing the opposite.
@@ -209,6 +333,7 @@ impl App {
                     self.handle_event_show_console(counter);
                 }
                 Some(Event::AddHosts(to_open)) => {
+                    self.note_recent_tags(&to_open);
                     self.getopt.hosts = to_open;
                     if let Err(e) = self.resolve_names(false) {
                         eprintln!("Failed top resolve_names {:?}", e);
@@ -219,15 +344,22 @@ impl App {
                         &self.config,
                         &mut self.internal_activate_autoquit,
                         &self.me,
+                        self.getopt.progress,
+                        true,
+                        true,
                     ) {
                         eprintln!("Failed top open windows {:?}", e);
                     } else if let Some(ref g) = self.gtkstuff {
                         // reproduce g.build_hosts_menu() here due to borrowing.
-                        for (ref server_key, ref mut server) in self.servers.iter_mut() {
+                        server::revive_menu_items(&mut self.servers, &mut self.dead_menu_items);
+                        let order_by_given = self.config.misc.order_by_given;
+                        for (server_key, server) in
+                            server::in_order_mut(&mut self.servers, order_by_given)
+                        {
                             g.build_host_menu(server_key, server, rapp);
                         }
                         g.change_main_window_title(self);
-                        let _ = self.retile_hosts(false, false);
+                        let _ = self.retile_hosts(false, false, retile::RetileScope::All);
                     }
                 }
                 None => return,
@@ -289,30 +421,51 @@ impl App {
         }
     }
 
-    pub fn resolve_names(&mut self, run_external: bool) -> Result<()> {
+    // initial is false for Hosts -> Add Host(s), see config's run_external_cluster
+    pub fn resolve_names(&mut self, initial: bool) -> Result<()> {
         // There are a few places which call this, so it seems
         // a bit messy to have the non-main callers stuff their
         // data into self.getopt.hosts, but it just makes borrowing easier).
-        self.getopt.hosts = self
-            .cluster
-            .resolve_clusters(&mut self.getopt.hosts, self.config.misc.use_all_a_records)?;
+        let hosts = std::mem::replace(&mut self.getopt.hosts, Vec::new());
+        self.getopt.hosts = host::split_host_args(hosts);
 
-        if run_external {
-            if let Some(cmd) = &self.config.misc.external_cluster_command {
-                match cluster::get_external_clusters(cmd, &self.getopt.hosts) {
-                    Ok(new_hosts) => self.getopt.hosts = new_hosts,
-                    Err(e) => eprintln!("Error running external_cluster command: {:?}", e), // no change to self.getopt.hosts
-                }
-            }
+        if self.config.misc.suggest_tags {
+            self.cluster.print_tag_suggestions(&self.getopt.hosts);
         }
-        let hosts = &mut self.getopt.hosts;
 
-        hosts.retain(|host| !host.is_empty()); // in place, preservers order
+        self.getopt.hosts = self.cluster.resolve_clusters(
+            &mut self.getopt.hosts,
+            self.config.misc.use_all_a_records,
+            self.config.misc.resolver_use_libc_fallback,
+            self.config.misc.max_tag_recursion,
+        )?;
 
-        if self.config.misc.unique_servers {
-            hosts.sort_unstable();
-            hosts.dedup();
+        if self.config.misc.run_external_cluster.runs(initial) {
+            run_external_cluster(
+                &mut self.getopt.hosts,
+                &self.config.misc.external_cluster_command,
+            );
+        }
+        if let Some(domain) = &self.config.misc.default_domain {
+            // after tag expansion, so only the hosts are touched, not the tags.
+            self.cluster
+                .add_default_domain(&mut self.getopt.hosts, domain);
+        }
+        post_process_hosts(&mut self.getopt.hosts, self.config.misc.unique_servers);
+        if self.getopt.shuffle {
+            let seed = self.getopt.seed.unwrap_or_else(|| {
+                SystemTime::now()
+                    .duration_since(UNIX_EPOCH)
+                    .map(|d| d.as_secs() as u32)
+                    .unwrap_or(0)
+            });
+            shuffle_hosts(&mut self.getopt.hosts, seed);
         }
+        // last, so it sees (and can reorder) the hosts as they'd be opened.
+        run_host_filter(
+            &mut self.getopt.hosts,
+            &self.config.misc.host_filter_command,
+        );
         Ok(())
     }
 
@@ -325,7 +478,13 @@ impl App {
         Ok((x.0, x.1))
     }
 
-    pub fn retile_hosts(&mut self, force: bool, raise: bool) -> Result<()> {
+    // scope picks which servers are tiled, see retile::RetileScope.
+    pub fn retile_hosts(
+        &mut self,
+        force: bool,
+        raise: bool,
+        scope: retile::RetileScope,
+    ) -> Result<()> {
         let console_shown = if !self.config.misc.window_tiling && !force {
             for (_, ref mut server) in self.servers.iter().rev() {
                 self.xdisplay.map_window(server.wid);
@@ -333,7 +492,7 @@ impl App {
             self.xdisplay.flush();
             false
         } else {
-            retile::retile_hosts(self, raise)?
+            retile::retile_hosts_in(self, raise, scope)?
         };
         if !console_shown {
             // console maintains its own state so we don't really need
@@ -346,39 +505,332 @@ impl App {
     }
 
     pub fn send_resizemove(&self, wid: Wid, x: u32, y: u32, w: u32, h: u32) -> Result<()> {
-        self.xdisplay.change_property(wid, x, y, w, h)?;
+        self.xdisplay
+            .change_property(wid, x, y, w, h, self.config.misc.size_hint_flags)?;
         self.xdisplay.configure_window(wid, x, y, w, h)?;
-        if self.getopt.debug {
-            println!(
-                "at {}:{} send_resizemove x={:4} y={:4} w={:4} h={:4} wid={}",
-                file!(),
-                line!(),
-                x,
-                y,
-                w,
-                h,
-                wid
-            );
-        }
+        server::note_geometry(&self.servers, wid, (x, y, w, h));
+        log!(
+            macros::DEBUG_RETILE,
+            "send_resizemove x={:4} y={:4} w={:4} h={:4} wid={}",
+            x,
+            y,
+            w,
+            h,
+            wid
+        );
         Ok(())
     }
 
     // handle paste events, send text to all active servers.
     pub fn send_text(&mut self, text: &str) {
-        send_text::send_text(self, text);
+        let n_targets = send_text::send_text(self, text);
+        if self.config.tcssh.send_flash {
+            if let Some(ref g) = self.gtkstuff {
+                g.flash_send_indicator(n_targets);
+            }
+        }
+    }
+
+    // Every Send menu item comes through here. With preview_send the first activation
+    // only shows what would be sent to whom, in the history, and activating it again
+    // sends it. Returns true when it's time to send.
+    fn previewed<F: FnOnce(&Self) -> String>(&mut self, preview: F) -> bool {
+        if !self.config.tcssh.preview_send {
+            return true;
+        }
+        let preview = preview(self);
+        if self.preview_pending.as_ref() != Some(&preview) {
+            if let Some(ref g) = self.gtkstuff {
+                g.show_preview(&preview);
+            }
+            self.preview_pending = Some(preview);
+            return false;
+        }
+        self.forget_preview();
+        true
+    }
+
+    // Whatever was previewed has to be previewed again before it's sent,
+    // e.g. once the active sessions change.
+    pub fn forget_preview(&mut self) {
+        if self.preview_pending.take().is_some() {
+            if let Some(ref g) = self.gtkstuff {
+                g.clear_preview();
+            }
+        }
+    }
+
+    // The Send menu's text items, macros and all.
+    pub fn send_text_previewed(&mut self, text: &str) {
+        if self.previewed(|app| send_text::preview(&app.servers, &app.config.macros, text)) {
+            self.send_text(text);
+        }
+    }
+
+    // e.g. Ctrl-C to all active servers, from Send -> Special Keys.
+    // label is what preview_send shows for it.
+    pub fn send_keysym(&mut self, label: &str, keysym: u32, state: u32) {
+        if !self.previewed(|app| send_text::preview_label(&app.servers, &format!("<{}>", label))) {
+            return;
+        }
+        let n_targets = send_text::send_keysym(self, keysym, state);
+        if self.config.tcssh.send_flash {
+            if let Some(ref g) = self.gtkstuff {
+                g.flash_send_indicator(n_targets);
+            }
+        }
+    }
+
+    // Clipboard text, sanitized and/or bracketed per the config. The Return for
+    // "Paste and Enter" goes after the end marker so the shell runs the paste.
+    pub fn send_paste(&mut self, text: &str, enter: bool) {
+        let text = self.paste_text(text, enter);
+        self.send_text(&text);
+    }
+
+    // Send -> Paste and Enter, the Enter unless paste_appends_newline=no
+    pub fn send_paste_previewed(&mut self, text: &str) {
+        let text = self.paste_text(text, self.config.tcssh.paste_appends_newline);
+        self.send_text_previewed(&text);
+    }
+
+    fn paste_text(&self, text: &str, enter: bool) -> String {
+        let mut text = if self.config.tcssh.sanitize_paste {
+            send_text::sanitize(text)
+        } else {
+            String::from(text)
+        };
+        if self.config.tcssh.bracketed_paste {
+            text = send_text::bracket_paste(&text);
+        }
+        if enter {
+            text.push('\n');
+        }
+        text
+    }
+
+    // Send -> Password, types what password_command prints, then Return,
+    // into every active session. See secret.rs for the trade-offs.
+    pub fn send_secret(&mut self) {
+        let cmd = match self.config.tcssh.password_command {
+            Some(ref cmd) => cmd.clone(),
+            None => return,
+        };
+        // the password isn't known until the command's run, and isn't for showing anyway.
+        if !self.previewed(|app| send_text::preview_label(&app.servers, "<password>\\n")) {
+            return;
+        }
+        // like run_external_cluster(), the command needs the default SIGCHLD handling.
+        let capture = || secret::read_secret(&cmd, &secret::CommandCapture);
+        let result = if wait_children::is_our_sig_handler_installed() {
+            wait_children::without_sig_chld_handler(capture).and_then(|r| r)
+        } else {
+            capture()
+        };
+        let mut secret = match result {
+            Ok(secret) => secret,
+            Err(e) => {
+                eprintln!("Error running password_command: {}", e);
+                return;
+            }
+        };
+        let sent = send_text::send_secret(self, &secret);
+        secret::wipe(&mut secret);
+        match sent {
+            Ok(n_targets) => {
+                if self.config.tcssh.send_flash {
+                    if let Some(ref g) = self.gtkstuff {
+                        g.flash_send_indicator(n_targets);
+                    }
+                }
+            }
+            Err(e) => eprintln!("Not sending the password: {}", e),
+        }
+    }
+
+    // Re-read the config, cluster and tag files, e.g. after editing ~/.tcssh/clusters
+    // Existing sessions are left alone.
+    //
+    // Takes effect immediately: clusters and tags (for Add Host(s), but not its list),
+    //   macros, title_format, send_flash, order, retiling and sleep settings.
+    // Takes effect on the next connect: terminal_*, comms (ssh_args etc), auto_close.
+    // Needs a restart: key_*, opacity (but see File -> Opacity), history size, show_history, console_geometry and console_position.
+    //
+    // --args still win over the files, like they do at startup.
+    pub fn reload_config(&mut self) -> Result<()> {
+        let mut config = config::Config::default();
+        self.getopt.setup(&mut config)?;
+        config.keep_runtime_state(&self.config)?;
+        self.getopt.override_config_with_args(&mut config)?;
+
+        let mut cluster = cluster::Cluster::default();
+        cluster.get_cluster_entries(&mut config)?;
+        cluster.get_tag_entries(&mut config)?;
+        set_color_tags(&mut config, &cluster);
+        if let Some(font) = font_for_screen(&config, &self.getopt, &self.xdisplay) {
+            config.terminal.font = Cow::Owned(font);
+        }
+
+        // Only swap in once everything parsed, so a typo leaves us as we were.
+        self.config = config;
+        self.cluster = cluster;
+        if let Some(ref g) = self.gtkstuff {
+            g.change_main_window_title(self);
+        }
+        Ok(())
+    }
+
+    // Hosts -> Save as cluster…, the open sessions, in the order they were opened.
+    pub fn save_as_cluster(&mut self, name: &str) -> Result<()> {
+        let hosts: Vec<String> = server::in_order(&self.servers, true)
+            .iter()
+            .map(|(_, server)| server.connect_string.clone())
+            .collect();
+        self.cluster.save_cluster(&mut self.config, name, &hosts)
+    }
+
+    // Where the last retile (or load_layout()) put each server, see server::export_layout().
+    pub fn export_layout(&self) -> String {
+        server::export_layout(&self.servers)
+    }
+
+    // Puts the servers back where export_layout() had them, without retiling.
+    // Servers which aren't open (anymore) are skipped. Returns how many were moved.
+    pub fn import_layout(&self, layout: &str) -> Result<usize> {
+        let mut n_moved = 0;
+        for (server_key, (x, y, w, h)) in server::parse_layout(layout) {
+            if let Some(server) = self.servers.get(&server_key) {
+                self.xdisplay.map_window(server.wid);
+                self.send_resizemove(server.wid, x, y, w, h)?;
+                n_moved += 1;
+            }
+        }
+        self.xdisplay.flush();
+        Ok(n_moved)
+    }
+
+    // Hosts -> Save layout…, to $CONFIG_DIR/layouts/<name>
+    pub fn save_layout(&mut self, name: &str) -> Result<()> {
+        let layout_file = self.layout_file(name)?;
+        if let Some(dir) = layout_file.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&layout_file, self.export_layout())?;
+        Ok(())
+    }
+
+    // Hosts -> Load layout…
+    pub fn load_layout(&mut self, name: &str) -> Result<()> {
+        let layout_file = self.layout_file(name)?;
+        let layout = fs::read_to_string(&layout_file)
+            .map_err(|e| format!("{} {}", layout_file.to_string_lossy(), e))?;
+        if self.import_layout(&layout)? == 0 {
+            return Err(format!("None of the sessions in layout {} are open", name).into());
+        }
+        Ok(())
+    }
+
+    fn layout_file(&mut self, name: &str) -> Result<PathBuf> {
+        if name.is_empty() || name.contains('/') || name.starts_with('.') {
+            return Err(format!("Invalid layout name '{}'", name).into());
+        }
+        match self.config.tcssh.get_config_dir() {
+            Some(mut layout_file) => {
+                layout_file.push("layouts");
+                layout_file.push(name);
+                Ok(layout_file)
+            }
+            None => Err("No ~/.tcssh or ~/.clusterssh directory for layouts".into()),
+        }
+    }
+
+    fn recent_file(&mut self) -> Option<PathBuf> {
+        self.config.tcssh.get_config_dir().map(|mut recent_file| {
+            recent_file.push("recent");
+            recent_file
+        })
+    }
+
+    // The tags among hosts go to the top of recent_tags, and $CONFIG_DIR/recent,
+    // for the next run's Add Host(s) dialog.
+    fn note_recent_tags(&mut self, hosts: &[String]) {
+        let max = usize::from(self.config.menu.max_recent_tags);
+        if max == 0 {
+            return;
+        }
+        let tags: Vec<&str> = hosts
+            .iter()
+            .map(String::as_str)
+            .filter(|host| self.cluster.get_tag(host).is_some())
+            .collect();
+        if !recent::record(&mut self.recent_tags, &tags, max) {
+            return;
+        }
+        let recent_tags = self.recent_tags.clone();
+        if let Some(recent_file) = self.recent_file() {
+            if let Err(e) = recent::write(&recent_file, &recent_tags) {
+                eprintln!("Warn: Failed to save recent tags. {}", e);
+            }
+        }
+    }
+
+    // File -> Opacity, see Tcssh::set_opacity() for the clamping.
+    pub fn adjust_opacity(&mut self, delta: f64) {
+        let opacity = if self.config.tcssh.transparent {
+            self.config.tcssh.opacity
+        } else {
+            1.0
+        };
+        self.set_opacity(opacity + delta);
+    }
+
+    pub fn set_opacity(&mut self, opacity: f64) {
+        self.config.tcssh.set_opacity(opacity);
+        let opacity = self.config.tcssh.opacity;
+        if let Some(ref mut g) = self.gtkstuff {
+            g.set_opacity(opacity);
+        }
+    }
+
+    // Send -> To Host…
+    pub fn send_text_to(&mut self, wid: Wid, text: &str) {
+        if !self.previewed(|app| send_text::preview_to(&app.servers, &app.config.macros, wid, text))
+        {
+            return;
+        }
+        let n_targets = send_text::send_text_to(self, wid, text);
+        if self.config.tcssh.send_flash {
+            if let Some(ref g) = self.gtkstuff {
+                g.flash_send_indicator(n_targets);
+            }
+        }
     }
 
     pub fn send_variable_text(&mut self) {
+        if !self.previewed(|app| send_text::preview_label(&app.servers, "<random number>")) {
+            return;
+        }
         send_text::send_variable_text(self);
     }
 
-    pub fn send_event(&self, wid: Wid, state: u32, keycode: u32) {
-        if self.xdisplay.send_event(wid, state, keycode).is_err() {
-            eprintln!("Error sending event to {}", wid);
+    pub fn minimize_all(&self) {
+        for server in self.servers.values() {
+            self.xdisplay.iconify_window(server.wid);
+        }
+        self.xdisplay.flush();
+    }
+
+    pub fn restore_all(&self) {
+        // same order as the tail of retile_hosts() to get overlaps correct
+        for server in self.servers.values().rev() {
+            self.xdisplay.deiconify_window(server.wid);
+            self.xdisplay.raise_window(server.wid);
         }
+        self.xdisplay.flush();
     }
 
     pub fn toggle_active_state(&mut self) {
+        self.forget_preview();
         for (_, ref mut server) in self.servers.iter_mut() {
             // server.set_active( ! server.active ); // Borrow checker rejects this.. sigh.
             let tmp = !server.active;
@@ -387,12 +839,49 @@ impl App {
     }
 
     pub fn set_all_active(&mut self) {
+        self.forget_preview();
         for (_, ref mut server) in self.servers.iter_mut() {
             server.set_active(true);
         }
     }
 
+    // Only the servers opened as one of tag's members are left active.
+    // Members which aren't open are skipped. None if there is no such tag.
+    // Expanded like the command line's tags, so members of nested tags count too.
+    // But without DNS, a member is matched as it was given.
+    pub fn set_active_by_tag(&mut self, tag: &str) -> Option<usize> {
+        self.cluster.get_tag(tag)?;
+        let members = self
+            .cluster
+            .resolve_clusters(
+                &mut vec![tag.to_string()],
+                false,
+                false,
+                self.config.misc.max_tag_recursion,
+            )
+            .ok()?;
+        self.forget_preview();
+        let n_active = server::set_active_by_members(&mut self.servers, &members);
+        log!(macros::DEBUG_CLUSTER, "tag {} activated {}", tag, n_active);
+        Some(n_active)
+    }
+
+    // Only the server the last retile put at (row, col), counting from 0, is left active.
+    // Positions outside the grid change nothing, and give false.
+    pub fn activate_grid_position(&mut self, row: u32, col: u32) -> bool {
+        let server_key = match server::at_grid_position(&self.servers, row, col) {
+            Some(server_key) => server_key.clone(),
+            None => return false,
+        };
+        self.forget_preview();
+        for (key, server) in self.servers.iter_mut() {
+            server.set_active(*key == server_key);
+        }
+        true
+    }
+
     pub fn set_half_inactive(&mut self) {
+        self.forget_preview();
         let mut half: usize = (self.servers.len() + 1) / 2;
         for (_, ref mut server) in self.servers.iter_mut() {
             server.set_active(false);
@@ -424,17 +913,22 @@ impl App {
             &self.config,
             &mut self.internal_activate_autoquit,
             &self.me,
+            self.getopt.progress,
+            self.config.misc.run_action_on_readd,
+            false,
         ) {
             eprintln!("Failed top open windows {:?}", e);
             // Show
         }
         if let Some(ref g) = self.gtkstuff {
             // reproduce g.build_hosts_menu() here due to borrowing.
-            for (ref server_key, ref mut server) in self.servers.iter_mut() {
+            server::revive_menu_items(&mut self.servers, &mut self.dead_menu_items);
+            let order_by_given = self.config.misc.order_by_given;
+            for (server_key, server) in server::in_order_mut(&mut self.servers, order_by_given) {
                 g.build_host_menu(server_key, server, rapp);
             }
             g.change_main_window_title(self);
-            let _ = self.retile_hosts(false, false);
+            let _ = self.retile_hosts(false, false, retile::RetileScope::All);
         }
     }
 
@@ -459,6 +953,9 @@ impl retile::RetileApp<x::XDisplay> for App {
     fn get_servers(&self) -> &BTreeMap<String, server::Server> {
         &self.servers
     }
+    fn get_servers_mut(&mut self) -> &mut BTreeMap<String, server::Server> {
+        &mut self.servers
+    }
     fn get_font_wh(&self) -> (u32, u32) {
         (self.font_w, self.font_h)
     }
@@ -479,3 +976,363 @@ impl retile::RetileApp<x::XDisplay> for App {
         self.sleep(ms);
     }
 }
+
+// For color_by=tag, child.rs can't see the cluster, so give it each host's tag.
+fn set_color_tags(config: &mut config::Config, cluster: &cluster::Cluster) {
+    if config.terminal.color_by_tag {
+        config.dynamic.color_tags = cluster.first_tags();
+    } else {
+        config.dynamic.color_tags.clear();
+    }
+}
+
+// font_overrides' font for this X screen's size, unless --font picked one.
+fn font_for_screen(
+    config: &config::Config,
+    getopt: &getopt::Getopt,
+    xdisplay: &x::XDisplay,
+) -> Option<String> {
+    if getopt.font.is_some() {
+        return None;
+    }
+    config
+        .terminal
+        .font_for_screen(xdisplay.width_in_pixels, xdisplay.height_in_pixels)
+        .map(String::from)
+}
+
+// Part of resolve_names(), kept clear of gtk and X so it can be tested.
+fn run_external_cluster(hosts: &mut Vec<String>, cmd: &Option<PathBuf>) {
+    if let Some(cmd) = cmd {
+        let result = if wait_children::is_our_sig_handler_installed() {
+            // Hosts -> Add Host(s) with run_external_cluster=always
+            wait_children::without_sig_chld_handler(|| cluster::get_external_clusters(cmd, hosts))
+                .and_then(|r| r)
+        } else {
+            cluster::get_external_clusters(cmd, hosts)
+        };
+        match result {
+            Ok(new_hosts) => *hosts = new_hosts,
+            Err(e) => eprintln!("Error running external_cluster command: {:?}", e), // no change to hosts
+        }
+    }
+}
+
+fn run_host_filter(hosts: &mut Vec<String>, cmd: &Option<PathBuf>) {
+    if let Some(cmd) = cmd {
+        let result = if wait_children::is_our_sig_handler_installed() {
+            // Hosts -> Add Host(s)
+            wait_children::without_sig_chld_handler(|| cluster::filter_hosts(cmd, hosts))
+                .and_then(|r| r)
+        } else {
+            cluster::filter_hosts(cmd, hosts)
+        };
+        match result {
+            Ok(new_hosts) => *hosts = new_hosts,
+            Err(e) => eprintln!("Error running host_filter_command: {}", e), // no change to hosts
+        }
+    }
+}
+
+// The tail of resolve_names(), kept clear of gtk and X so it can be tested.
+fn post_process_hosts(hosts: &mut Vec<String>, unique: bool) {
+    hosts.retain(|host| !host.is_empty()); // in place, preservers order
+
+    if unique {
+        hosts.sort_unstable();
+        hosts.dedup();
+    }
+}
+
+// For --shuffle, Fisher-Yates with libc's rand like child.rs's pick_color(),
+// but rand_r() so nothing else calling rand() can change the order for a seed.
+fn shuffle_hosts(hosts: &mut [String], seed: u32) {
+    let mut state: libc::c_uint = seed;
+    for i in (1..hosts.len()).rev() {
+        let rand = unsafe { libc::rand_r(&mut state) } as usize;
+        let j = rand / ((libc::RAND_MAX as usize / (i + 1)) + 1);
+        hosts.swap(i, j);
+    }
+}
+
+// For --examples, the longer form of --help
+fn examples() -> String {
+    format!(
+        r#"{version}
+
+Open an xterm per host, and type into all of them from the console:
+    tcssh host1.example.com bob@host2.example.com host3.example.com:2222
+
+Clusters and tags, see also --cluster-file and --tag-file:
+    $ cat ~/.tcssh/clusters
+    web web1.example.com web2.example.com web3.example.com
+    $ cat ~/.tcssh/tags
+    db1.example.com web
+    $ tcssh web         # web[123] and db1
+    $ tcssh web[0:2]    # just the first two
+    $ tcssh bob@web     # as bob, on all of them
+
+Connect to every IP a name resolves to:
+    tcssh --use-all-a-records web.example.com
+
+Use mosh, telnet etc instead of ssh, via a symlink or --comms:
+    ln -s tcssh tcmosh ; tcmosh host1 host2
+    tcssh --comms custom --comms-command 'kubectl exec -it %h -- bash' web-0 web-1
+
+If the xterms close too quickly to read any error:
+    tcmosh --evaluate ::1
+or to see what would be run, without running it:
+    tcmosh --print-command ::1
+
+Run a command in each session as it starts:
+    tcssh -a 'tail -f /var/log/syslog' web
+or just open them and exit, leaving no console to type into:
+    tcssh --batch -a 'sudo apt upgrade' web
+or only do that when the console can't start, e.g. gtk can't open the display:
+    tcssh --no-console web
+
+Macros, expanded in pasted text and in -a (see macro_* in --dump-config):
+    %s  the server name, %h  the local hostname, %u  the username,
+    %n  a newline, %v  the version, %%  a literal %
+    tcssh -a 'echo I am %u on %s' web
+
+See everything that can be configured in ~/.tcssh/config:
+    tcssh --dump-config
+"#,
+        version = macros::VERSION_LONG
+    )
+}
+
+#[cfg(test)]
+fn test_app() -> App {
+    App {
+        cluster: Default::default(),
+        config: Default::default(),
+        getopt: getopt::Getopt::from_iter(&["tcssh"]),
+        servers: BTreeMap::new(),
+        dead_servers: Vec::new(),
+        dead_menu_items: Vec::new(),
+        xdisplay: Default::default(),
+        gtkstuff: Default::default(),
+        text2x11: Default::default(),
+        internal_activate_autoquit: false,
+        headless: false,
+        preview_pending: None,
+        recent_tags: Vec::new(),
+        font_w: 0,
+        font_h: 0,
+        me: "tcssh".into(),
+        events: VecDeque::new(),
+    }
+}
+
+#[test]
+fn test_set_active_by_tag() {
+    let filename = crate::tmpnam::tmpnam().unwrap();
+    std::fs::write(&filename, "db db1 bob@db2 db3\nweb web1\nall db web\n").unwrap();
+    let mut app = test_app();
+    app.cluster.read_cluster_file(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+
+    for (key, host_str, givenname) in [
+        ("db1", "db1", "db1"),
+        ("db2", "bob@db2", "db2"),
+        ("web1", "web1", "web1"),
+        ("mail1", "mail1", "mail1"),
+    ]
+    .iter()
+    {
+        let server = server::Server {
+            active: true,
+            connect_string: host_str.to_string(),
+            givenname: givenname.to_string(),
+            ..Default::default()
+        };
+        app.servers.insert(key.to_string(), server);
+    }
+    let active = |app: &App| -> Vec<&str> {
+        app.servers
+            .iter()
+            .filter(|(_, s)| s.active)
+            .map(|(key, _)| key.as_str())
+            .collect()
+    };
+
+    // db3 isn't open, so it's skipped
+    assert_eq!(app.set_active_by_tag("db"), Some(2));
+    assert_eq!(active(&app), vec!["db1", "db2"]);
+
+    // all's members are tags, so their members count
+    assert_eq!(app.set_active_by_tag("all"), Some(3));
+    assert_eq!(active(&app), vec!["db1", "db2", "web1"]);
+
+    assert_eq!(app.set_active_by_tag("mail"), None);
+    assert_eq!(active(&app), vec!["db1", "db2", "web1"]);
+}
+
+#[test]
+fn test_previewed() {
+    let mut app = test_app();
+    for (key, wid) in [("a", 1), ("b", 2)].iter() {
+        let server = server::Server {
+            active: true,
+            wid: *wid,
+            ..Default::default()
+        };
+        app.servers.insert(key.to_string(), server);
+    }
+    let preview = |app: &App| send_text::preview(&app.servers, &app.config.macros, "ls\n");
+    assert!(app.previewed(preview));
+
+    app.config.tcssh.preview_send = true;
+    assert!(!app.previewed(preview));
+    assert!(app.previewed(preview));
+    // previewed again after it's sent
+    assert!(!app.previewed(preview));
+
+    // and after the active sessions change, even back to the way they were
+    app.toggle_active_state();
+    app.toggle_active_state();
+    assert!(!app.previewed(preview));
+    assert!(app.previewed(preview));
+
+    // a different item isn't sent on the first go
+    assert!(!app.previewed(preview));
+    let to_b = |app: &App| send_text::preview_to(&app.servers, &app.config.macros, 2, "ls\n");
+    assert!(!app.previewed(to_b));
+    assert!(app.previewed(to_b));
+}
+
+#[test]
+fn test_examples() {
+    let text = examples();
+    assert!(text.starts_with(macros::VERSION_LONG));
+    assert!(text.contains("--use-all-a-records"));
+    assert!(text.contains("--evaluate"));
+}
+
+#[test]
+fn test_post_process_hosts() {
+    let given = || -> Vec<String> {
+        ["web2", "", "db1", "web2", "", "web1"]
+            .iter()
+            .map(|s| s.to_string())
+            .collect()
+    };
+
+    let mut hosts = given();
+    post_process_hosts(&mut hosts, false);
+    assert_eq!(hosts, vec!["web2", "db1", "web2", "web1"]);
+
+    let mut hosts = given();
+    post_process_hosts(&mut hosts, true);
+    assert_eq!(hosts, vec!["db1", "web1", "web2"]);
+
+    let mut hosts = vec![String::new()];
+    post_process_hosts(&mut hosts, true);
+    assert!(hosts.is_empty());
+}
+
+#[test]
+fn test_shuffle_hosts() {
+    let given: Vec<String> = ["h0", "h1", "h2", "h3", "h4"]
+        .iter()
+        .map(|s| s.to_string())
+        .collect();
+
+    let mut hosts = given.clone();
+    shuffle_hosts(&mut hosts, 42);
+    let mut again = given.clone();
+    shuffle_hosts(&mut again, 42);
+    assert_eq!(hosts, again);
+
+    let mut sorted = hosts.clone();
+    sorted.sort();
+    assert_eq!(sorted, given);
+
+    if cfg!(target_env = "gnu") {
+        // glibc's rand_r()
+        assert_eq!(hosts, vec!["h3", "h0", "h2", "h4", "h1"]);
+    }
+
+    let mut hosts = vec!["h0".to_string()];
+    shuffle_hosts(&mut hosts, 42);
+    assert_eq!(hosts, vec!["h0"]);
+    let mut hosts: Vec<String> = Vec::new();
+    shuffle_hosts(&mut hosts, 42);
+    assert!(hosts.is_empty());
+}
+
+#[test]
+fn test_run_external_cluster() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let cmd = crate::tmpnam::tmpnam().unwrap();
+    fs::write(&cmd, "#!/bin/sh\necho ext-$1\n").unwrap();
+    fs::set_permissions(&cmd, fs::Permissions::from_mode(0o700)).unwrap();
+    let cmd = Some(cmd);
+
+    let resolve = |run: config::RunExternalCluster, initial: bool| -> Vec<String> {
+        let mut hosts = vec!["web".to_string()];
+        if run.runs(initial) {
+            run_external_cluster(&mut hosts, &cmd);
+        }
+        hosts
+    };
+    let always = config::RunExternalCluster::Always;
+    let initial = config::RunExternalCluster::Initial;
+    let never = config::RunExternalCluster::Never;
+    let results = vec![
+        resolve(always, true),
+        resolve(always, false),
+        resolve(initial, true),
+        resolve(initial, false),
+        resolve(never, true),
+        resolve(never, false),
+    ];
+    fs::remove_file(cmd.as_ref().unwrap()).unwrap();
+
+    assert_eq!(results[0], vec!["ext-web"]);
+    assert_eq!(results[1], vec!["ext-web"]);
+    assert_eq!(results[2], vec!["ext-web"]);
+    assert_eq!(results[3], vec!["web"]);
+    assert_eq!(results[4], vec!["web"]);
+    assert_eq!(results[5], vec!["web"]);
+
+    // no command, no change
+    let mut hosts = vec!["web".to_string()];
+    run_external_cluster(&mut hosts, &None);
+    assert_eq!(hosts, vec!["web"]);
+}
+
+#[test]
+fn test_run_host_filter() {
+    use std::os::unix::fs::PermissionsExt;
+
+    // drops the db hosts, and reverses the rest
+    let cmd = crate::tmpnam::tmpnam().unwrap();
+    fs::write(
+        &cmd,
+        "#!/bin/sh\nread hosts\nfor h in $hosts; do case $h in db*) ;; *) out=\"$h $out\" ;; esac; done\necho $out\n",
+    )
+    .unwrap();
+    fs::set_permissions(&cmd, fs::Permissions::from_mode(0o700)).unwrap();
+    let cmd = Some(cmd);
+
+    let mut hosts: Vec<String> = vec!["web1", "db1", "web2"]
+        .into_iter()
+        .map(String::from)
+        .collect();
+    run_host_filter(&mut hosts, &cmd);
+    assert_eq!(hosts, vec!["web2", "web1"]);
+
+    // a non-0 exit leaves the hosts alone
+    fs::write(cmd.as_ref().unwrap(), "#!/bin/sh\necho db1\nexit 3\n").unwrap();
+    run_host_filter(&mut hosts, &cmd);
+    assert_eq!(hosts, vec!["web2", "web1"]);
+    fs::remove_file(cmd.as_ref().unwrap()).unwrap();
+
+    // no command, no change
+    run_host_filter(&mut hosts, &None);
+    assert_eq!(hosts, vec!["web2", "web1"]);
+}
@@ -14,6 +14,8 @@
 // lead to deadlock.
 
 use libc;
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::ffi::CStr;
 use std::ffi::CString;
 use std::io;
@@ -22,6 +24,7 @@ use std::ptr;
 
 use crate::config;
 use crate::host::Host;
+use crate::log;
 use crate::macros;
 
 // One shared lifetime... seems like all these annotations
@@ -37,14 +40,23 @@ pub struct Child<'a> {
     pub host_str: &'a str,
     pub host: &'a Host<'a>,
     pub given_server_name: &'a str,
+    pub geometry: Option<&'a str>,
     pub pipenm: &'a Path,
     pub server_key: &'a str,
     pub me: &'a str,
+    pub run_command: bool, // false when re-adding, with run_action_on_readd=no
 }
 
 impl<'a> Child<'a> {
     // divergent function. It does not return
     pub fn handle_fork(&self) -> ! {
+        let cmd = self.build_command();
+        log!(macros::DEBUG_FORK, "{} runs sh -c {}", self.server_key, cmd);
+        exec(&cmd);
+    }
+
+    // Also used by --print-command, see server::print_command()
+    pub fn build_command(&self) -> String {
         let mut cmd = String::with_capacity(1024);
 
         cmd += self.config.terminal.terminal_name.as_ref();
@@ -56,7 +68,12 @@ impl<'a> Child<'a> {
             } else {
                 cmd += "-fg \\#000000 -bg ";
             }
-            pick_color(&mut cmd, &self.host.hostname);
+            let seed = color_seed(
+                self.config.terminal.color_by_tag,
+                &self.config.dynamic.color_tags,
+                &self.host.hostname,
+            );
+            pick_color(&mut cmd, seed);
             cmd += " ";
         }
 
@@ -64,17 +81,38 @@ impl<'a> Child<'a> {
             cmd += &args;
             cmd += " ";
         }
+        if let Some(ref class) = self.config.terminal.class {
+            // already checked by Terminal::set_class(), so no quoting needed.
+            cmd += "-class ";
+            cmd += class;
+            cmd += " ";
+        }
         cmd += &self.config.terminal.allow_send_events;
         cmd += " ";
+        if let Some(geometry) = self.geometry {
+            // already checked against STRICT_GEOMETRY, so no quoting needed.
+            cmd += "-geometry ";
+            cmd += geometry;
+            cmd += " ";
+        }
         cmd += &self.config.terminal.title_opt;
         cmd += " '";
+        let title_start = cmd.len();
         if let Some(ref title) = self.config.dynamic.title {
             cmd += title;
         }
         cmd += ": ";
-        cmd += &self.host_str; // host_str is untouched from cmd line mimic-ing perl cssh.
-                               // This allows the user to inject ' or ` etc into our cmd, which is odd.
-                               // but trust the user to not shoot themselves in the foot.
+        // By default host_str is untouched from cmd line mimic-ing perl cssh.
+        // This allows the user to inject ' or ` etc into our cmd, which is odd.
+        // but trust the user to not shoot themselves in the foot,
+        // unless they've asked us not to via escape_host_strings=yes
+        let escape = self.config.tcssh.escape_host_strings;
+        if escape {
+            cmd += &shell_escape(self.host_str);
+        } else {
+            cmd += &self.host_str;
+        }
+        let title = cmd[title_start..].to_string(); // already escaped for within '
 
         cmd += "' -font ";
         cmd += &self.config.terminal.font;
@@ -85,9 +123,42 @@ impl<'a> Child<'a> {
         cmd += self.comms;
         cmd += " '";
         cmd += self.comms_args;
+        if let config::CommsE::Sftp = self.config.comms.comms {
+            if let Some(ref batch_file) = self.config.comms.sftp_batch_file {
+                // quoted for helper.rs's sh -c, then again for this one.
+                cmd += " -b ";
+                cmd += &shell_escape(&shell_quote(&batch_file.to_string_lossy()));
+            }
+        }
+        let mut mux = config::RemoteMultiplexer::None;
+        let mut clear_motd = false;
+        if let config::CommsE::Ssh = self.config.comms.comms {
+            cmd += &self.config.comms.connect_timeout_opt();
+            cmd += &send_env_opts(&self.config.comms.ssh_send_env);
+            // e.g. web1%-oFoo=bar from a clusters file, just for this host.
+            // helper.rs's sh -c splits them like ssh_args, unless escape_host_strings.
+            for opt in self.host.ssh_options() {
+                cmd += " ";
+                if escape {
+                    cmd += &shell_escape(&shell_quote(opt));
+                } else {
+                    cmd += &shell_escape(opt);
+                }
+            }
+            mux = self.config.comms.remote_multiplexer;
+            if self.config.comms.suppress_motd {
+                // quiets ssh's own banners and warnings.
+                // tmux or screen take over the screen anyway, so no need to clear it.
+                cmd += " -o LogLevel=ERROR";
+                clear_motd = mux == config::RemoteMultiplexer::None;
+            }
+            if mux != config::RemoteMultiplexer::None || clear_motd {
+                cmd += " -t"; // ssh only allocates a tty without a remote command
+            }
+        }
         cmd += "' '";
 
-        if !self.command.is_empty() {
+        let command = if self.run_command && !self.command.is_empty() {
             // When run with --action (or -a, or a config value of command set) then perl cssh would
             // "Run the command in each session, e.g. C<-a 'grep foo /etc/bar'> to drop straight into a vi session."
             // This is passed as command line arguments twice,
@@ -101,10 +172,27 @@ impl<'a> Child<'a> {
                 self.given_server_name,
                 &self.host.username.and_then(|u| Some(String::from(u))),
             ) {
-                macros::Subst::None => cmd += self.command,
-                macros::Subst::Same { text } => cmd += &text,
-                macros::Subst::Diff { text } => cmd += &text,
+                macros::Subst::None => Cow::Borrowed(self.command),
+                macros::Subst::Same { text } => Cow::Owned(text),
+                macros::Subst::Diff { text } => Cow::Owned(text),
             }
+        } else {
+            Cow::Borrowed("")
+        };
+        let command = if clear_motd {
+            Cow::Owned(clear_motd_command(&command))
+        } else {
+            command
+        };
+        if mux == config::RemoteMultiplexer::None {
+            cmd += &command;
+        } else {
+            // mux_command quotes command, and we're within ''
+            cmd += &shell_escape(&mux_command(
+                mux,
+                &self.config.comms.remote_session_name,
+                &command,
+            ));
         }
 
         cmd += "' '";
@@ -112,23 +200,94 @@ impl<'a> Child<'a> {
         cmd += "' ";
         cmd += &self.pipenm.to_string_lossy();
         cmd += " ";
-        cmd += self.given_server_name;
+        if escape {
+            // helper.rs pastes the server name, user and port into a second 'sh -c',
+            // so quote them for that shell, then again for this one.
+            // A trailing == (failed to resolve) is left outside the quotes for helper to find.
+            let (name, resolve_failed) = if self.given_server_name.ends_with("==") {
+                (self.given_server_name.trim_end_matches("=="), "==")
+            } else {
+                (self.given_server_name, "")
+            };
+            cmd += "'";
+            cmd += &shell_escape(&shell_quote(name));
+            cmd += resolve_failed;
+            cmd += "'";
+        } else {
+            cmd += self.given_server_name;
+        }
         cmd += " '";
-        if let Some(u) = self.host.username {
-            cmd += u;
-        } else if let Some(u) = &self.config.dynamic.username {
-            cmd += u;
+        let username = self
+            .host
+            .username
+            .or_else(|| self.config.dynamic.username.as_ref().map(String::as_str));
+        if let Some(u) = username {
+            if escape {
+                cmd += &shell_escape(&shell_quote(u));
+            } else {
+                cmd += u;
+            }
+        }
+        cmd += "' '";
+        if let Some(p) = effective_port(self.host, self.config) {
+            if escape {
+                cmd += &shell_escape(&shell_quote(&p));
+            } else {
+                cmd += &p;
+            }
+        }
+        cmd += "' '";
+        if let config::CommsE::Mosh = self.config.comms.comms {
+            cmd += &self.config.comms.mosh_server;
+            cmd += "' '";
+            cmd += &self.config.comms.mosh_port_range;
+        } else {
+            cmd += "' '";
+        }
+        cmd += "' '";
+        if let config::CommsE::Telnet = self.config.comms.comms {
+            cmd += &self.config.comms.telnet_login_opt;
+        }
+        cmd += "' '";
+        if self.config.tcssh.dynamic_titles {
+            // same as the -title above, helper.rs sets it again as the session changes.
+            cmd += &title;
         }
         cmd += "' '";
-        if let Some(p) = self.host.port {
-            cmd += p;
-        } else if let Some(p) = &self.config.misc.port {
-            cmd += p;
+        if self.config.dynamic.wait_all {
+            cmd += "yes";
         }
+        cmd += "' '";
+        cmd += &self.config.misc.session_timeout; // already checked, so no quoting needed.
+        cmd += "' '";
+        cmd += &shell_escape(self.config.comms.port_opt());
         cmd += "'";
+        cmd
+    }
+}
 
-        exec(&cmd);
+// The port to connect to, a host's own :port always wins over --port or the
+// config's port, otherwise none, leaving it to ssh_config etc.
+// Also used by --evaluate and --probe, so they test what we'd really run.
+pub fn effective_port(host: &Host, config: &config::Config) -> Option<String> {
+    host.port
+        .map(String::from)
+        .or_else(|| config.misc.port.clone())
+}
+
+// What pick_color() hashes, the hostname, or with color_by=tag the host's tag
+// so they all get the same color. Hosts in no tag still go by hostname.
+fn color_seed<'b>(
+    color_by_tag: bool,
+    color_tags: &'b BTreeMap<String, String>,
+    hostname: &'b str,
+) -> &'b str {
+    if color_by_tag {
+        if let Some(tag) = color_tags.get(hostname) {
+            return tag;
+        }
     }
+    hostname
 }
 
 // pick a color for xterm text.
@@ -157,6 +316,78 @@ fn pick_color(cmd: &mut String, hostname: &str) {
     }
 }
 
+// ssh_send_env's names as ssh options, each with a leading space.
+// Names go unquoted through two shells, so only env var names (and ssh's * ? wildcards) are passed.
+fn send_env_opts(names: &[String]) -> String {
+    let mut opts = String::new();
+    for name in names {
+        if name.is_empty()
+            || !name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '*' || c == '?')
+        {
+            eprintln!("Warn: Ignoring ssh_send_env name {}", name);
+            continue;
+        }
+        opts += " -o SendEnv=";
+        opts += name;
+    }
+    opts
+}
+
+// remote_multiplexer's command for ssh to run, wrapping command (from -a) if any.
+// Attaches to session if it's there, else creates it (running command).
+// command goes as one quoted word, so all of e.g. "cd /tmp; bash" runs in the session.
+fn mux_command<'b>(
+    mux: config::RemoteMultiplexer,
+    session: &str,
+    command: &'b str,
+) -> Cow<'b, str> {
+    // tmux hands its command to sh -c, screen execs its own, so give it an sh -c.
+    let (attach, run) = match mux {
+        config::RemoteMultiplexer::None => return Cow::Borrowed(command),
+        config::RemoteMultiplexer::Tmux => ("tmux new -A -s ", " "),
+        config::RemoteMultiplexer::Screen => ("screen -xRR -S ", " sh -c "),
+    };
+    let mut s = String::from(attach);
+    s += session;
+    if !command.is_empty() {
+        s += run;
+        s += &shell_quote(command);
+    }
+    Cow::Owned(s)
+}
+
+// suppress_motd, the remote login's motd can't be turned off from here (short of
+// ~/.hushlogin on every host), so clear it away before the command (or shell).
+// helper.rs puts this in "", so \$SHELL is left for the remote end.
+fn clear_motd_command(command: &str) -> String {
+    if command.is_empty() {
+        String::from(r"clear; exec \$SHELL -l")
+    } else {
+        format!("clear; {}", command)
+    }
+}
+
+// Escape s for use within a single quoted sh string, 'it'\''s' => it's
+// Nothing else is special between single quotes, so only ' needs work.
+pub fn shell_escape(s: &str) -> Cow<'_, str> {
+    if s.contains('\'') {
+        Cow::Owned(s.replace('\'', r"'\''"))
+    } else {
+        Cow::Borrowed(s)
+    }
+}
+
+// Quote s as a single sh word, e.g. a`b => 'a`b'
+fn shell_quote(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('\'');
+    out += &shell_escape(s);
+    out.push('\'');
+    out
+}
+
 // perl's exec($foo) calls 'sh -c' implicitly, if it sees that $foo contains a shell meta character
 // So we call "sh -c" explicitly.
 pub fn exec(command: &str) -> ! {
@@ -177,3 +408,278 @@ pub fn exec(command: &str) -> ! {
 
     panic!(format!("execlp failed {}", io::Error::last_os_error()));
 }
+
+// ssh -x, with no command, to host's hostname.
+#[cfg(test)]
+fn test_child<'a>(config: &'a config::Config, host: &'a Host<'a>) -> Child<'a> {
+    Child {
+        config,
+        comms: "ssh",
+        comms_args: "-x",
+        command: "",
+        auto_close: "5",
+        host_str: host.parse_string,
+        host,
+        given_server_name: host.hostname,
+        geometry: None,
+        pipenm: Path::new("/tmp/pipe"),
+        server_key: host.hostname,
+        me: "tcssh",
+        run_command: true,
+    }
+}
+
+#[test]
+fn test_shell_escape() {
+    let tests = [
+        ("host", "host"),
+        ("it's", r"it'\''s"),
+        ("`reboot`", "`reboot`"), // harmless inside single quotes
+        ("''", r"'\'''\''"),
+        ("$(id);x", "$(id);x"),
+    ];
+    for (input, expected) in tests.iter() {
+        assert_eq!(&shell_escape(input), expected);
+    }
+}
+
+#[test]
+fn test_shell_quote() {
+    assert_eq!(shell_quote("host"), "'host'");
+    assert_eq!(shell_quote("a`b`"), "'a`b`'");
+    assert_eq!(shell_quote("it's"), r"'it'\''s'");
+    assert_eq!(shell_quote(""), "''");
+}
+
+#[test]
+fn test_build_command_run_command() {
+    let config = config::Config::default();
+    let host = crate::host::parse("bob@host").unwrap();
+    let mut child = Child {
+        command: "uptime",
+        ..test_child(&config, &host)
+    };
+    assert!(child
+        .build_command()
+        .contains(" --helper  ssh '-x' 'uptime' '5' /tmp/pipe host 'bob' "));
+
+    child.run_command = false; // e.g. re-adding with run_action_on_readd=no
+    assert!(child
+        .build_command()
+        .contains(" --helper  ssh '-x' '' '5' /tmp/pipe host 'bob' "));
+}
+
+#[test]
+fn test_build_command_sftp_batch_file() {
+    use std::path::PathBuf;
+
+    let mut config = config::Config::default();
+    config.comms.comms = config::CommsE::Sftp;
+    let host = crate::host::parse("host").unwrap();
+    let build = |config: &config::Config| -> String {
+        Child {
+            comms: "sftp",
+            comms_args: "",
+            ..test_child(config, &host)
+        }
+        .build_command()
+    };
+    assert!(build(&config).contains(" --helper  sftp '' '' '5' "));
+
+    config.comms.sftp_batch_file = Some(PathBuf::from("/home/bob/push it.batch"));
+    assert!(
+        build(&config).contains(r#" --helper  sftp ' -b '\''/home/bob/push it.batch'\''' '' '5' "#)
+    );
+
+    config.comms.comms = config::CommsE::Ssh; // only for sftp
+    assert!(!build(&config).contains(" -b "));
+}
+
+#[test]
+fn test_build_command_ssh_options() {
+    let mut config = config::Config::default();
+    config.comms.comms = config::CommsE::Ssh;
+    let host = crate::host::parse("web1%-oFoo=bar%-4").unwrap();
+    let build = |config: &config::Config| test_child(config, &host).build_command();
+    assert!(build(&config).contains(" --helper  ssh '-x -oFoo=bar -4' '' '5' /tmp/pipe web1 "));
+
+    config.tcssh.escape_host_strings = true;
+    assert!(build(&config).contains(r"ssh '-x '\''-oFoo=bar'\'' '\''-4'\''' '' '5' "));
+
+    config.comms.comms = config::CommsE::Telnet; // only for ssh
+    assert!(build(&config).contains(" --helper  ssh '-x' '' '5' "));
+}
+
+#[test]
+fn test_build_command_class() {
+    let mut config = config::Config::default();
+    config.terminal.colorize = false;
+    let host = crate::host::parse("host").unwrap();
+    let build = |config: &config::Config| test_child(config, &host).build_command();
+    assert!(build(&config).starts_with("xterm -xrm '*.VT100.allowSendEvents:true' -T "));
+
+    config.terminal.set_class("TcsshTerm").unwrap();
+    assert!(build(&config)
+        .starts_with("xterm -class TcsshTerm -xrm 'TcsshTerm.VT100.allowSendEvents:true' -T "));
+
+    assert!(config.terminal.set_class("Tc; rm").is_err());
+    assert_eq!(config.terminal.class, Some("TcsshTerm".to_string()));
+}
+
+#[test]
+fn test_send_env_opts() {
+    let names = vec!["LANG".to_string(), "LC_*".to_string()];
+    assert_eq!(send_env_opts(&names), " -o SendEnv=LANG -o SendEnv=LC_*");
+    assert_eq!(send_env_opts(&[]), "");
+    assert_eq!(send_env_opts(&["A;rm".to_string()]), "");
+}
+
+#[test]
+fn test_mux_command() {
+    use config::RemoteMultiplexer;
+
+    assert_eq!(mux_command(RemoteMultiplexer::None, "main", "uptime"), "uptime");
+    assert_eq!(mux_command(RemoteMultiplexer::None, "main", ""), "");
+    assert_eq!(
+        mux_command(RemoteMultiplexer::Tmux, "main", ""),
+        "tmux new -A -s main"
+    );
+    assert_eq!(
+        mux_command(RemoteMultiplexer::Tmux, "main", "top -d 5"),
+        "tmux new -A -s main 'top -d 5'"
+    );
+    assert_eq!(
+        mux_command(RemoteMultiplexer::Tmux, "main", "it's"),
+        r"tmux new -A -s main 'it'\''s'"
+    );
+    assert_eq!(
+        mux_command(RemoteMultiplexer::Screen, "work", ""),
+        "screen -xRR -S work"
+    );
+    assert_eq!(
+        mux_command(RemoteMultiplexer::Screen, "work", "top -d 5"),
+        "screen -xRR -S work sh -c 'top -d 5'"
+    );
+}
+
+#[test]
+fn test_build_command_remote_multiplexer() {
+    let mut config = config::Config::default();
+    config.comms.comms = config::CommsE::Ssh;
+    config.comms.remote_multiplexer = config::RemoteMultiplexer::Tmux;
+    let host = crate::host::parse("host").unwrap();
+    let child = Child {
+        command: "uptime",
+        ..test_child(&config, &host)
+    };
+    assert!(child
+        .build_command()
+        .contains(r" --helper  ssh '-x -t' 'tmux new -A -s main '\''uptime'\''' '5' "));
+}
+
+#[test]
+fn test_build_command_remote_multiplexer_two_actions() {
+    use structopt::StructOpt;
+
+    let mut config = config::Config::default();
+    config.comms.comms = config::CommsE::Ssh;
+    config.comms.remote_multiplexer = config::RemoteMultiplexer::Tmux;
+    crate::getopt::Getopt::from_iter(&["tcssh", "-a", "cd /tmp", "-a", "bash", "host"])
+        .override_config_with_args(&mut config)
+        .unwrap();
+    let host = crate::host::parse("host").unwrap();
+    let child = Child {
+        command: &config.comms.command,
+        ..test_child(&config, &host)
+    };
+    // both run inside tmux, not just the first
+    assert!(child
+        .build_command()
+        .contains(" 'tmux new -A -s main '\\''cd /tmp\nbash'\\''' '5' "));
+}
+
+#[test]
+fn test_build_command_suppress_motd() {
+    let mut config = config::Config::default();
+    config.comms.comms = config::CommsE::Ssh;
+    config.comms.suppress_motd = true;
+    let mut mux_config = config::Config::default();
+    mux_config.comms.comms = config::CommsE::Ssh;
+    mux_config.comms.suppress_motd = true;
+    mux_config.comms.remote_multiplexer = config::RemoteMultiplexer::Tmux;
+    let host = crate::host::parse("host").unwrap();
+    let mut child = test_child(&config, &host);
+    assert!(child
+        .build_command()
+        .contains(r" ssh '-x -o LogLevel=ERROR -t' 'clear; exec \$SHELL -l' '5' "));
+
+    child.command = "uptime";
+    assert!(child
+        .build_command()
+        .contains(" ssh '-x -o LogLevel=ERROR -t' 'clear; uptime' '5' "));
+
+    child.config = &mux_config;
+    assert!(child
+        .build_command()
+        .contains(r" ssh '-x -o LogLevel=ERROR -t' 'tmux new -A -s main '\''uptime'\''' '5' "));
+}
+
+#[test]
+fn test_effective_port() {
+    let mut config = config::Config::default();
+    let host = crate::host::parse("web1").unwrap();
+    let host_port = crate::host::parse("web1:2222").unwrap();
+    assert_eq!(effective_port(&host, &config), None);
+    assert_eq!(
+        effective_port(&host_port, &config),
+        Some("2222".to_string())
+    );
+    config.misc.port = Some("2200".to_string()); // --port or port=
+    assert_eq!(effective_port(&host, &config), Some("2200".to_string()));
+    assert_eq!(
+        effective_port(&host_port, &config),
+        Some("2222".to_string())
+    );
+}
+
+#[test]
+fn test_build_command_port() {
+    // the helper's port arg, after the user's
+    fn port_of(config: &config::Config, comms: &str, host_str: &str) -> String {
+        let host = crate::host::parse(host_str).unwrap();
+        let child = Child {
+            comms,
+            comms_args: "",
+            run_command: false,
+            ..test_child(config, &host)
+        };
+        let cmd = child.build_command();
+        let after = &cmd[cmd.find("/tmp/pipe web1 '' '").unwrap() + 19..];
+        after[..after.find('\'').unwrap()].to_string()
+    }
+    let mut config = config::Config::default();
+    for (comms_e, comms) in vec![
+        (config::CommsE::Ssh, "ssh"),
+        (config::CommsE::Telnet, "telnet"),
+    ] {
+        config.comms.comms = comms_e;
+        config.misc.port = None;
+        assert_eq!(port_of(&config, comms, "web1"), "");
+        assert_eq!(port_of(&config, comms, "web1:2222"), "2222");
+        config.misc.port = Some("2200".to_string()); // --port or port=
+        assert_eq!(port_of(&config, comms, "web1"), "2200");
+        assert_eq!(port_of(&config, comms, "web1:2222"), "2222");
+    }
+}
+
+#[test]
+fn test_color_seed() {
+    let mut color_tags = BTreeMap::new();
+    color_tags.insert("web1".to_string(), "web".to_string());
+    color_tags.insert("web2".to_string(), "web".to_string());
+
+    assert_eq!(color_seed(false, &color_tags, "web1"), "web1");
+    assert_eq!(color_seed(true, &color_tags, "web1"), "web");
+    assert_eq!(color_seed(true, &color_tags, "web2"), "web");
+    assert_eq!(color_seed(true, &color_tags, "db1"), "db1"); // in no tag
+}
@@ -1,4 +1,4 @@
-// This mod does 3 things
+// This mod does 4 things
 //
 // 1)
 // Clusters allow you to make short name aliases for one or more boxes.
@@ -40,25 +40,42 @@
 //     it should output a list of tags (space separated on a single line)
 //     it can resolve."
 //
+// 4)
+// Once the hosts are fully resolved, host_filter_command may filter or reorder them.
+// It reads them on stdin (space separated on a single line) and prints the hosts
+// to open, see filter_hosts().
+//
 
 use regex::Regex;
 use std::collections::hash_map::Entry;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
+use std::fs::{self, OpenOptions};
+use std::io::Write;
 use std::path::Path;
-use std::process::Command;
+use std::process::{Command, Stdio};
 
 use crate::config;
 use crate::er::Result;
+use crate::host;
 use crate::is_xfile::IsExecutableFile;
+use crate::log;
+use crate::macros;
 use crate::reader;
 use crate::resolver;
 use crate::wait_children;
 
 lazy_static! {
     static ref USER_HOST: Regex = Regex::new(r"^(.*?)@(.*)$").expect("Regex error USER_HOST");
-    static ref IPV4: Regex = Regex::new(r"^(\d{1,3}\.?){4}$").expect("Regex error IPV4");
+    // four 0-255 octets, exactly three dots
+    static ref IPV4: Regex =
+        Regex::new(r"^((25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)\.){3}(25[0-5]|2[0-4]\d|1\d\d|[1-9]?\d)$")
+            .expect("Regex error IPV4");
     static ref IPV6: Regex =
         Regex::new(r"^([0-9a-f]{0,4}:){2,7}(:|[0-9a-f]{1,4})$").expect("Regex error IPV6");
+    // [user@]host[:port][=geometry] where host has no dots, see add_default_domain()
+    static ref SHORT_HOST: Regex = Regex::new(r"^((?:.*?@)?)([\w-]+)((?::\d+)?(?:=.*)?(?:%.*)?)$").expect("Regex error SHORT_HOST");
+    static ref TAG_SLICE: Regex =
+        Regex::new(r"^(.+)\[(\d*):(\d*)\]$").expect("Regex error TAG_SLICE");
 }
 
 type NeedDns = HashMap<String, Vec<Option<String>>>;
@@ -111,9 +128,65 @@ impl Cluster {
         Ok(())
     }
 
-    fn read_cluster_file(&mut self, filename: &Path) -> Result<()> {
+    // Hosts -> Save as cluster…, append "name host1 host2 ..." to config_dir/clusters
+    // and register it, so it's usable right away. e.g. from Add Host(s).
+    pub fn save_cluster(
+        &mut self,
+        config: &mut config::Config,
+        name: &str,
+        hosts: &[String],
+    ) -> Result<()> {
+        match config.tcssh.get_config_dir() {
+            Some(mut cluster_file) => {
+                cluster_file.push("clusters");
+                self.save_cluster_to(&cluster_file, name, hosts)
+            }
+            None => Err("No ~/.tcssh or ~/.clusterssh directory to save clusters in".into()),
+        }
+    }
+
+    fn save_cluster_to(&mut self, filename: &Path, name: &str, hosts: &[String]) -> Result<()> {
+        if name.is_empty() || name.contains(|c: char| c.is_whitespace() || c == '#' || c == '=') {
+            return Err(format!("Invalid cluster name '{}'", name).into());
+        }
+        if hosts.is_empty() {
+            return Err("No sessions to save as a cluster".into());
+        }
+        if let Some(existing) = self.tags.get(name) {
+            if existing.as_slice() == hosts {
+                return Ok(()); // already saved, don't repeat it.
+            }
+            // read_cluster_file() would append to the existing tag, not what was asked for.
+            return Err(format!(
+                "Cluster {} already exists with different hosts, not saving",
+                name
+            )
+            .into());
+        }
+
+        let needs_newline = match fs::read(filename) {
+            Ok(contents) => !contents.is_empty() && !contents.ends_with(b"\n"),
+            Err(_) => false,
+        };
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(filename)?;
+        let line = format!(
+            "{}{} {}\n",
+            if needs_newline { "\n" } else { "" },
+            name,
+            hosts.join(" ")
+        );
+        file.write_all(line.as_bytes())?;
+
+        self.register_tag(name.to_string(), hosts.to_vec(), false);
+        Ok(())
+    }
+
+    pub fn read_cluster_file(&mut self, filename: &Path) -> Result<()> {
         if filename.exists() {
-            reader::read_file(filename, false, |key, value| {
+            reader::read_file(filename, false, |_, key, value| {
                 let tags: Vec<String> = value
                     .split_whitespace()
                     .map(std::string::ToString::to_string)
@@ -130,7 +203,7 @@ impl Cluster {
 
     fn read_tag_file(&mut self, filename: &Path) -> Result<()> {
         if filename.exists() {
-            reader::read_file(filename, false, |key, value| {
+            reader::read_file(filename, false, |_, key, value| {
                 let tags: Vec<String> = value
                     .split_whitespace()
                     .map(std::string::ToString::to_string)
@@ -177,6 +250,25 @@ impl Cluster {
         self.tags.get(host)
     }
 
+    // For color_by=tag, each host's smallest tag which lists it, so web1 is
+    // colored as 'web' rather than as 'all'. Ties go to the first by name.
+    // Keyed by hostname, without any user@ or :port
+    pub fn first_tags(&self) -> BTreeMap<String, String> {
+        let mut first_tags = BTreeMap::new();
+        let mut tags = self.list_tags();
+        tags.sort_by_key(|tag| self.tags[tag].len()); // stable, so still by name within a size
+        for tag in tags {
+            for member in &self.tags[&tag] {
+                if let Some(h) = host::parse(member) {
+                    first_tags
+                        .entry(h.hostname.to_string())
+                        .or_insert_with(|| tag.clone());
+                }
+            }
+        }
+        first_tags
+    }
+
     pub fn list_tags(&self) -> Vec<String> {
         let mut v: Vec<String> = self
             .tags
@@ -187,16 +279,77 @@ impl Cluster {
         v
     }
 
+    // For suggest_tags=yes
+    // Tags which look like a typo of 'host', closest first.
+    pub fn suggest_tags(&self, host: &str) -> Vec<&str> {
+        let host = match USER_HOST.captures(host) {
+            Some(cap) => cap.get(2).map_or(host, |h| h.as_str()),
+            None => host,
+        };
+        if host.is_empty() || self.tags.contains_key(host) {
+            return Vec::new();
+        }
+        // 'web-prdo' should find 'web-prod', but 'db' shouldn't find every two letter tag.
+        let max_distance = std::cmp::max(1, host.chars().count() / 4);
+        let mut found: Vec<(usize, &str)> = self
+            .tags
+            .keys()
+            .filter_map(|tag| {
+                let distance = levenshtein(host, tag);
+                if distance <= max_distance {
+                    Some((distance, tag.as_str()))
+                } else if host.len() >= 3 && tag.contains(host) {
+                    Some((max_distance + 1, tag.as_str())) // substring matches after typos
+                } else {
+                    None
+                }
+            })
+            .collect();
+        found.sort();
+        found.into_iter().map(|(_, tag)| tag).collect()
+    }
+
+    // Print "did you mean" for hosts which are not tags, and which don't resolve.
+    // DNS is only asked about hosts which are near a tag, so this is cheap for most.
+    pub fn print_tag_suggestions(&self, hosts: &[String]) {
+        use std::net::ToSocketAddrs;
+        for host in hosts {
+            let suggestions = self.suggest_tags(host);
+            if suggestions.is_empty() {
+                continue;
+            }
+            let name = match USER_HOST.captures(host) {
+                Some(cap) => cap.get(2).map_or(host.as_str(), |h| h.as_str()),
+                None => host.as_str(),
+            };
+            if IPV4.is_match(name) || IPV6.is_match(name) || (name, 0).to_socket_addrs().is_ok() {
+                continue;
+            }
+            eprintln!(
+                "Unknown host or tag '{}', did you mean {}?",
+                name,
+                suggestions.join(" or ")
+            );
+        }
+    }
+
     pub fn resolve_clusters(
         &mut self,
         hosts: &mut Vec<String>,
         use_all_a_records: bool,
+        libc_fallback: bool,
+        max_tag_recursion: u32,
     ) -> Result<Vec<String>> {
         // perl cssh appends to @servers while iterating over @servers.
         // In rust we cannot mutate a Vec if we're iterating over it.
         // So iterate over one Vec, while appending to another 'more_hosts'.
         let mut more_hosts = Vec::new();
 
+        // tcssh only, e.g. 'web[0:10]' is the first ten hosts of tag 'web'.
+        // Those are expanded on their own, and take the slice's place among the hosts.
+        // Being fully expanded already, the rest of this passes them through as is.
+        self.resolve_slices(hosts, use_all_a_records, libc_fallback, max_tag_recursion)?;
+
         // perl cssh hits the network for DNS lookups serially,
         // we do it concurrently.  To do so, we altered the algorithm.
         // Specifically we loop first calling get_tag() for each host.
@@ -222,6 +375,10 @@ impl Cluster {
         // resolution, at the cost of people (mis)using IPs as tags.
         let mut need_dns = NeedDns::new();
 
+        // Which tag each expanded host came from, so a runaway expansion
+        // can name the tags involved, see runaway_expansion().
+        let mut parents = HashMap::new();
+
         // In the most common case (use_all_a_records=false, and no tags),
         // the host strings are not cloned.  We pass a ref to filter(),
         // and _resolve_clusters() only allocates new strings if we're
@@ -231,7 +388,11 @@ impl Cluster {
         let mut out: Vec<String> = hosts
             .drain(..)
             .filter(|host| {
-                self._resolve_clusters(host, use_all_a_records, &mut more_hosts, &mut need_dns)
+                let expanded_from = more_hosts.len();
+                let keep =
+                    self._resolve_clusters(host, use_all_a_records, &mut more_hosts, &mut need_dns);
+                note_parents(&mut parents, host, &more_hosts[expanded_from..]);
+                keep
             })
             .collect();
 
@@ -239,10 +400,10 @@ impl Cluster {
         // then 'more_hosts' now contains 'foo', 'bar', ...
         // and we go about calling _resolve_clusters on 'foo', 'bar', ...
         // expanding until nothing is left to expand.
-        let mut sanity_check = 128;
+        let mut depth = 0;
         while !more_hosts.is_empty() {
-            sanity_check -= 1;
-            if sanity_check <= 0 {
+            depth += 1;
+            if depth > max_tag_recursion {
                 // This is not a limit on the number of hosts,
                 // it is a limit on infinite tag expansion
                 // e.g.
@@ -250,16 +411,22 @@ impl Cluster {
                 //    foo bar
                 //    bar foo
                 //    $ tcssh foo
-                eprintln!("excessive cluster resolution detected. Ending loop");
+                eprintln!(
+                    "{}",
+                    runaway_expansion(&parents, &more_hosts[0], max_tag_recursion)
+                );
                 break;
             }
             let mut tmp = more_hosts;
             more_hosts = Vec::new();
 
             for host in tmp.drain(..) {
+                let expanded_from = more_hosts.len();
                 if self._resolve_clusters(&host, use_all_a_records, &mut more_hosts, &mut need_dns)
                 {
                     out.push(host);
+                } else {
+                    note_parents(&mut parents, &host, &more_hosts[expanded_from..]);
                 }
             }
         }
@@ -269,44 +436,140 @@ impl Cluster {
         // then we have some look ups to do.
         if !need_dns.is_empty() {
             let mut resolver = resolver::ResolverWrapper::new()?;
+            self.resolve_need_dns(&mut resolver, &need_dns, libc_fallback, &mut out);
+        }
+        Ok(out)
+    }
 
-            let hosts = need_dns
-                .keys()
-                .map(std::string::ToString::to_string)
-                .collect();
-            let mut out2 = Vec::new();
-            let mut register_tags2 = Vec::new();
-            resolver.resolve(
-                hosts, // get DNS for these hosts, and pass them to the closures below
-                |host, ips| {
-                    if handle_ip_resolution(&host, &ips, &mut out, &need_dns) {
-                        // register_tag is only useful if someone
-                        // uses the menu option "Add Host(s) or Cluster(s)"
-                        // and they request a previously resolved tag.
-                        self.register_tag(host, ips, true);
-                    }
-                },
-                |host, _err_str| {
-                    // error resolving host
-                    // Maybe 'host' is an alias in ~/.cssh/config
-                    // in which case, pass it through.
-                    let ips = Vec::new();
-                    if handle_ip_resolution(&host, &ips, &mut out2, &need_dns) {
-                        register_tags2.push(host);
-                    }
-                },
-            );
-            // join results from both closures
-            if !out2.is_empty() {
-                out.append(&mut out2);
+    fn resolve_need_dns<R: resolver::Resolve>(
+        &mut self,
+        resolver: &mut R,
+        need_dns: &NeedDns,
+        libc_fallback: bool,
+        out: &mut Vec<String>,
+    ) {
+        let hosts = need_dns
+            .keys()
+            .map(std::string::ToString::to_string)
+            .collect();
+        let mut no_records = Vec::new();
+        let mut failed = Vec::new();
+        resolver.resolve(
+            hosts, // get DNS for these hosts, and pass them to the closures below
+            |host, ips| {
+                log!(macros::DEBUG_DNS, "{} resolved to {:?}", host, ips);
+                if ips.is_empty() {
+                    no_records.push(host);
+                } else if handle_ip_resolution(&host, &ips, out, need_dns) {
+                    // register_tag is only useful if someone
+                    // uses the menu option "Add Host(s) or Cluster(s)"
+                    // and they request a previously resolved tag.
+                    self.register_tag(host, ips, true);
+                }
+            },
+            |host, err_str| {
+                log!(macros::DEBUG_DNS, "{} did not resolve: {}", host, err_str);
+                failed.push(host);
+            },
+        );
+        // error resolving host, maybe it's only in /etc/hosts (see resolver_use_libc_fallback)
+        // or an alias in ~/.ssh/config in which case, pass it through.
+        no_records.append(&mut failed);
+        for host in no_records {
+            let ips = if libc_fallback {
+                resolver.resolve_libc(&host)
+            } else {
+                Vec::new()
+            };
+            log!(macros::DEBUG_DNS, "{} resolved by libc to {:?}", host, ips);
+            if handle_ip_resolution(&host, &ips, out, need_dns) {
+                self.register_tag(host, ips, true);
             }
-            if !register_tags2.is_empty() {
-                for host in register_tags2 {
-                    self.register_tag(host, Vec::new(), true);
+        }
+    }
+
+    // For default_domain, 'user@web1' becomes 'user@web1.example.com'
+    // Hosts with a dot, IPs, and (unexpanded) tags are left alone.
+    pub fn add_default_domain(&self, hosts: &mut [String], domain: &str) {
+        for host in hosts.iter_mut() {
+            let long = match SHORT_HOST.captures(host) {
+                Some(cap) => {
+                    let name = &cap[2];
+                    if IPV4.is_match(name) || IPV6.is_match(name) || self.get_tag(name).is_some() {
+                        continue;
+                    }
+                    format!("{}{}.{}{}", &cap[1], name, domain, &cap[3])
+                }
+                None => continue,
+            };
+            *host = long;
+        }
+    }
+
+    // Replaces each 'tag[start:end]' in hosts with what that slice expands to.
+    // Like python, 0 based, end is exclusive, and either end can be left out.
+    fn resolve_slices(
+        &mut self,
+        hosts: &mut Vec<String>,
+        use_all_a_records: bool,
+        libc_fallback: bool,
+        max_tag_recursion: u32,
+    ) -> Result<()> {
+        if !hosts.iter().any(|host| host.ends_with(']')) {
+            return Ok(()); // the common case, no slices.
+        }
+        let mut out = Vec::with_capacity(hosts.len());
+        for host in hosts.drain(..) {
+            let (tag, start, end) = match self.parse_slice(&host) {
+                Some(slice) => slice,
+                None => {
+                    out.push(host);
+                    continue;
                 }
+            };
+            log!(
+                macros::DEBUG_CLUSTER,
+                "slice {:?}:{:?} of {}",
+                start,
+                end,
+                tag
+            );
+            let mut expanded = self.resolve_clusters(
+                &mut vec![tag.clone()],
+                use_all_a_records,
+                libc_fallback,
+                max_tag_recursion,
+            )?;
+            match slice_range(expanded.len(), start, end) {
+                Some(range) => out.extend(expanded.drain(range)),
+                None => eprintln!(
+                    "Ignoring {}[{}:{}] since the start is after the end",
+                    tag,
+                    start.unwrap_or(0),
+                    end.unwrap_or(0)
+                ),
             }
         }
-        Ok(out)
+        *hosts = out;
+        Ok(())
+    }
+
+    // 'user@tag[1:2]' gives ('user@tag', Some(1), Some(2)), if tag is a tag.
+    // Anything else, e.g. an IPv6 '[::1]' is left for the usual handling.
+    fn parse_slice(&self, host: &str) -> Option<(String, Option<usize>, Option<usize>)> {
+        let cap = TAG_SLICE.captures(host)?;
+        let tag = cap.get(1)?.as_str();
+        let name = match USER_HOST.captures(tag) {
+            Some(user_host) => user_host.get(2).map_or(tag, |h| h.as_str()),
+            None => tag,
+        };
+        self.get_tag(name)?;
+        // huge numbers are clamped later anyway
+        let bound = |i| match cap.get(i).map(|m| m.as_str()) {
+            Some("") | None => None,
+            Some(n) => Some(n.parse().unwrap_or(usize::max_value())),
+        };
+        Some((tag.to_string(), bound(2), bound(3)))
     }
 
     fn _resolve_clusters(
@@ -364,6 +627,7 @@ impl Cluster {
 
         if let Some(tags) = tags {
             if !tags.is_empty() {
+                log!(macros::DEBUG_CLUSTER, "tag {} expands to {:?}", host, tags);
                 for tag in tags {
                     // e.g.
                     //     $ cat ~/.tcssh/clusters
@@ -404,43 +668,71 @@ impl Cluster {
 // Execute a command with hosts as args, read its output,
 // and use those as the new set of hosts to use.
 pub fn get_external_clusters(p: &Path, hosts: &[String]) -> Result<(Vec<String>)> {
+    let output = run_host_command(p, hosts, None, "external_cluster_command")?;
+    Ok(output.trim_end().split(' ').map(String::from).collect())
+}
+
+// Pipe the resolved hosts, space separated, to host_filter_command and use
+// what it prints as the new set of hosts, e.g. sorted by ping latency.
+// Like get_external_clusters() a non-0 exit is an error, and the hosts are left alone.
+pub fn filter_hosts(p: &Path, hosts: &[String]) -> Result<Vec<String>> {
+    let input = format!("{}\n", hosts.join(" "));
+    let output = run_host_command(p, &[], Some(&input), "host_filter_command")?;
+    // whitespace, so one per line works too, e.g. from sort(1)
+    Ok(output.split_whitespace().map(String::from).collect())
+}
+
+// Runs get_external_clusters()' or filter_hosts()' command, with input on its
+// stdin if there is any, and returns what it prints. name is its config key.
+fn run_host_command(p: &Path, args: &[String], input: Option<&str>, name: &str) -> Result<String> {
     if !p.is_executable_file() {
-        return Err("external cluster command is not executable".into());
+        return Err(format!("{} is not executable", name).into());
     }
-    // TODO.. Figure out how to swap signal handlers
-    // so Menu "Add Host(s) or Cluster(s)" can call us.
+    // Menu "Add Host(s) or Cluster(s)" calls us via wait_children::without_sig_chld_handler(),
+    // see app::run_external_cluster() and app::run_host_filter()
     if wait_children::is_our_sig_handler_installed() {
         return Err("assertion failure. sig handler will interfere with spawned commands".into());
     }
     let mut command = Command::new(p);
-    command.args(hosts);
-    match command.output() {
-        Ok(output) => {
-            // treat no status as success, like perl cssh
-            if let Some(status) = output.status.code() {
-                if status != 0 {
-                    // status is already >>8 from the raw value.
-                    return Err(format!(
-                        "External command failure.\nCommand: [{} {}]\nReturn Code: [{}]",
-                        p.to_string_lossy(),
-                        hosts.join(" "),
-                        status
-                    )
-                    .into());
-                }
-            }
-            match std::str::from_utf8(&output.stdout) {
-                Ok(output) => Ok(output.trim_end().split(' ').map(String::from).collect()),
-                Err(e) => Err(format!(
-                    "ouput of external_cluster_command {} is not valid utf8. {}",
-                    p.to_string_lossy(),
-                    e
-                )
-                .into()),
+    command.args(args).stdout(Stdio::piped());
+    command.stdin(if input.is_some() {
+        Stdio::piped()
+    } else {
+        Stdio::null()
+    });
+    let mut child = command.spawn()?;
+    if let (Some(input), Some(mut stdin)) = (input, child.stdin.take()) {
+        // All in one write before reading any output, fine for a host list,
+        // which is well under a pipe's buffer. Dropped to close it.
+        // A command which exits without reading it all fails the write, which is fine.
+        let _ = stdin.write_all(input.as_bytes());
+    }
+    let output = child.wait_with_output()?;
+    // treat no status as success, like perl cssh
+    if let Some(status) = output.status.code() {
+        if status != 0 {
+            // status is already >>8 from the raw value.
+            let mut command_line = p.to_string_lossy().into_owned();
+            for arg in args {
+                command_line.push(' ');
+                command_line += arg;
             }
+            return Err(format!(
+                "{} failure.\nCommand: [{}]\nReturn Code: [{}]",
+                name, command_line, status
+            )
+            .into());
         }
-        Err(e) => Err(e.into()),
     }
+    String::from_utf8(output.stdout).map_err(|e| {
+        format!(
+            "output of {} {} is not valid utf8. {}",
+            name,
+            p.to_string_lossy(),
+            e
+        )
+        .into()
+    })
 }
 
 fn handle_ip_resolution(
@@ -497,3 +789,397 @@ fn handle_ip_resolution(
         }
     }
 }
+
+// 'user@tag' and 'tag' are the same tag, as far as cycles go.
+fn tag_name(host: &str) -> &str {
+    match USER_HOST.captures(host) {
+        Some(cap) => cap.get(2).map_or(host, |h| h.as_str()),
+        None => host,
+    }
+}
+
+// Remember 'tag' expanded to 'expanded', keeping the first tag seen for each host.
+fn note_parents(parents: &mut HashMap<String, String>, tag: &str, expanded: &[String]) {
+    for host in expanded {
+        parents
+            .entry(tag_name(host).to_string())
+            .or_insert_with(|| tag_name(tag).to_string());
+    }
+}
+
+// Walk back from 'host' to the tag the user asked for, or until a tag repeats.
+// e.g. "foo → bar → foo" for clusters 'foo bar' and 'bar foo'.
+fn runaway_expansion(parents: &HashMap<String, String>, host: &str, max: u32) -> String {
+    let mut chain = vec![tag_name(host)];
+    let mut cycle = false;
+    while let Some(parent) = parents.get(chain[chain.len() - 1]) {
+        cycle = chain.contains(&parent.as_str());
+        chain.push(parent);
+        if cycle {
+            break;
+        }
+    }
+    chain.reverse();
+    let what = if cycle {
+        "cyclic tags"
+    } else {
+        "tags still unexpanded"
+    };
+    format!(
+        "Error: {} after max_tag_recursion={} levels of tag expansion, ending loop: {}",
+        what,
+        max,
+        chain.join(" \u{2192} ")
+    )
+}
+
+// The range of a slice of 'len' hosts, None if the bounds are reversed.
+// Out of range bounds are clamped, python style.
+fn slice_range(
+    len: usize,
+    start: Option<usize>,
+    end: Option<usize>,
+) -> Option<std::ops::Range<usize>> {
+    if let (Some(start), Some(end)) = (start, end) {
+        if start > end {
+            return None;
+        }
+    }
+    let end = std::cmp::min(end.unwrap_or(len), len);
+    let start = std::cmp::min(start.unwrap_or(0), end);
+    Some(start..end)
+}
+
+// Plain edit distance, counted in chars.
+pub fn levenshtein(a: &str, b: &str) -> usize {
+    let b: Vec<char> = b.chars().collect();
+    let mut prev: Vec<usize> = (0..=b.len()).collect();
+    let mut cur = vec![0; b.len() + 1];
+    for (i, ca) in a.chars().enumerate() {
+        cur[0] = i + 1;
+        for (j, cb) in b.iter().enumerate() {
+            let substitute = prev[j] + if ca == *cb { 0 } else { 1 };
+            cur[j + 1] = std::cmp::min(substitute, std::cmp::min(prev[j + 1], cur[j]) + 1);
+        }
+        std::mem::swap(&mut prev, &mut cur);
+    }
+    prev[b.len()]
+}
+
+#[test]
+fn test_ipv4() {
+    for ok in &["255.255.255.255", "0.0.0.0", "10.0.0.1", "192.168.1.254"] {
+        assert!(IPV4.is_match(ok), "{}", ok);
+    }
+    for bad in &["256.0.0.1", "999.0.0.0", "1.2.3", "1.2.3.4.", "1..2.3"] {
+        assert!(!IPV4.is_match(bad), "{}", bad);
+    }
+}
+
+#[test]
+fn test_levenshtein() {
+    assert_eq!(levenshtein("", ""), 0);
+    assert_eq!(levenshtein("abc", ""), 3);
+    assert_eq!(levenshtein("", "abc"), 3);
+    assert_eq!(levenshtein("web-prod", "web-prod"), 0);
+    assert_eq!(levenshtein("web-prd", "web-prod"), 1);
+    assert_eq!(levenshtein("web-prdo", "web-prod"), 2);
+    assert_eq!(levenshtein("kitten", "sitting"), 3);
+    assert_eq!(levenshtein("héllo", "hello"), 1);
+}
+
+#[test]
+fn test_suggest_tags() {
+    let mut cluster = Cluster::default();
+    for tag in &["web-prod", "web-dev", "db-prod", "mail"] {
+        cluster
+            .tags
+            .insert(tag.to_string(), vec![format!("{}1", tag)]);
+    }
+    assert_eq!(cluster.suggest_tags("web-prd"), vec!["web-prod"]);
+    assert_eq!(cluster.suggest_tags("bob@web-porod"), vec!["web-prod"]);
+    assert_eq!(cluster.suggest_tags("prod"), vec!["db-prod", "web-prod"]);
+    assert!(cluster.suggest_tags("web-prod").is_empty()); // already a tag
+    assert!(cluster.suggest_tags("zz").is_empty());
+}
+
+#[cfg(test)]
+fn test_slice_cluster() -> Cluster {
+    let mut cluster = Cluster::default();
+    let hosts = ["h0", "h1", "h2", "h3", "h4"];
+    cluster.tags.insert(
+        "web".to_string(),
+        hosts.iter().map(|h| h.to_string()).collect(),
+    );
+    cluster
+}
+
+#[cfg(test)]
+fn resolve_test_slice(host: &str) -> Vec<String> {
+    let mut hosts = vec![host.to_string()];
+    test_slice_cluster()
+        .resolve_clusters(&mut hosts, false, false, 128)
+        .unwrap()
+}
+
+#[test]
+fn test_tag_slices() {
+    assert_eq!(resolve_test_slice("web[0:2]"), vec!["h0", "h1"]);
+    assert_eq!(resolve_test_slice("web[2:]"), vec!["h2", "h3", "h4"]);
+    assert_eq!(resolve_test_slice("web[:3]"), vec!["h0", "h1", "h2"]);
+    assert_eq!(resolve_test_slice("web[:]").len(), 5);
+    assert_eq!(resolve_test_slice("web[3:99]"), vec!["h3", "h4"]);
+    assert_eq!(resolve_test_slice("web[7:]"), Vec::<String>::new());
+    assert_eq!(resolve_test_slice("web[3:1]"), Vec::<String>::new());
+    assert_eq!(resolve_test_slice("bob@web[1:2]"), vec!["bob@h1"]);
+
+    // in the slice's place, not after the other hosts
+    let mut hosts = vec!["a".to_string(), "web[0:2]".to_string(), "b".to_string()];
+    let resolved = test_slice_cluster()
+        .resolve_clusters(&mut hosts, false, false, 128)
+        .unwrap();
+    assert_eq!(resolved, vec!["a", "h0", "h1", "b"]);
+
+    // not tags, so passed through as is
+    assert_eq!(resolve_test_slice("db[0:2]"), vec!["db[0:2]"]);
+    assert_eq!(resolve_test_slice("[::1]"), vec!["[::1]"]);
+}
+
+#[test]
+fn test_add_default_domain() {
+    let mut cluster = Cluster::default();
+    cluster
+        .tags
+        .insert("known_tag".to_string(), vec!["h0".to_string()]);
+    let mut hosts: Vec<String> = [
+        "web1",
+        "1.2.3.4",
+        "known_tag",
+        "user@db",
+        "db:2222",
+        "web2.elsewhere.com",
+        "[::1]",
+        "user@[fe80::1]:22",
+        "web3%-oFoo=bar",
+    ]
+    .iter()
+    .map(|h| h.to_string())
+    .collect();
+    cluster.add_default_domain(&mut hosts, "internal.example.com");
+    assert_eq!(
+        hosts,
+        vec![
+            "web1.internal.example.com",
+            "1.2.3.4",
+            "known_tag",
+            "user@db.internal.example.com",
+            "db.internal.example.com:2222",
+            "web2.elsewhere.com",
+            "[::1]",
+            "user@[fe80::1]:22",
+            "web3.internal.example.com%-oFoo=bar",
+        ]
+    );
+}
+
+#[test]
+fn test_first_tags() {
+    let mut cluster = Cluster::default();
+    cluster.tags.insert(
+        "web".to_string(),
+        vec!["web1".to_string(), "bob@web2:2222".to_string()],
+    );
+    cluster
+        .tags
+        .insert("all".to_string(), vec!["web1".to_string(), "db1".to_string()]);
+    let first_tags = cluster.first_tags();
+    assert_eq!(first_tags.get("web1").map(String::as_str), Some("web"));
+    assert_eq!(first_tags.get("web2").map(String::as_str), Some("web"));
+    assert_eq!(first_tags.get("db1").map(String::as_str), Some("all"));
+    assert_eq!(first_tags.get("mail1"), None);
+    // same size, so by name
+    cluster.tags.insert(
+        "prod".to_string(),
+        vec!["db1".to_string(), "web2".to_string()],
+    );
+    let first_tags = cluster.first_tags();
+    assert_eq!(first_tags.get("db1").map(String::as_str), Some("all"));
+    assert_eq!(first_tags.get("web2").map(String::as_str), Some("prod"));
+}
+
+#[test]
+fn test_save_cluster() {
+    let filename = crate::tmpnam::tmpnam().unwrap();
+    std::fs::write(&filename, "web web1 web2").unwrap(); // no trailing newline
+    let mut cluster = Cluster::default();
+    cluster.read_cluster_file(&filename).unwrap();
+
+    let hosts = vec!["db1".to_string(), "root@db2:2222".to_string()];
+    cluster.save_cluster_to(&filename, "db", &hosts).unwrap();
+    assert_eq!(cluster.get_tag("db"), Some(&hosts));
+    // identical, so not written twice
+    cluster.save_cluster_to(&filename, "db", &hosts).unwrap();
+    // name collision
+    assert!(cluster.save_cluster_to(&filename, "web", &hosts).is_err());
+    assert!(cluster.save_cluster_to(&filename, "d b", &hosts).is_err());
+    assert!(cluster.save_cluster_to(&filename, "none", &[]).is_err());
+
+    let contents = std::fs::read_to_string(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(contents, "web web1 web2\ndb db1 root@db2:2222\n");
+
+    let mut reread = Cluster::default();
+    let filename = crate::tmpnam::tmpnam().unwrap();
+    std::fs::write(&filename, contents).unwrap();
+    reread.read_cluster_file(&filename).unwrap();
+    std::fs::remove_file(&filename).unwrap();
+    assert_eq!(reread.get_tag("db"), Some(&hosts));
+}
+
+#[cfg(test)]
+struct EmptyResolver {
+    libc_asked: Vec<String>,
+}
+
+#[cfg(test)]
+impl resolver::Resolve for EmptyResolver {
+    // trust-dns finds nothing for host1, and errors for host2
+    fn resolve<F, G>(&mut self, hosts: Vec<String>, mut f: F, mut g: G)
+    where
+        F: FnMut(String, Vec<String>),
+        G: FnMut(String, String),
+    {
+        for host in hosts {
+            if host == "host1" {
+                f(host, Vec::new());
+            } else {
+                g(host, "no record found".to_string());
+            }
+        }
+    }
+
+    fn resolve_libc(&mut self, host: &str) -> Vec<String> {
+        self.libc_asked.push(host.to_string());
+        vec!["10.0.0.1".to_string(), "10.0.0.2".to_string()]
+    }
+}
+
+#[test]
+fn test_libc_fallback() {
+    let mut need_dns = NeedDns::new();
+    need_dns.insert("host1".to_string(), vec![None]);
+    need_dns.insert("host2".to_string(), vec![Some("bob".to_string())]);
+
+    let mut cluster = Cluster::default();
+    let mut resolver = EmptyResolver {
+        libc_asked: Vec::new(),
+    };
+    let mut out = Vec::new();
+    cluster.resolve_need_dns(&mut resolver, &need_dns, true, &mut out);
+    resolver.libc_asked.sort();
+    assert_eq!(resolver.libc_asked, vec!["host1", "host2"]);
+    out.sort();
+    assert_eq!(
+        out,
+        vec!["10.0.0.1", "10.0.0.2", "bob@10.0.0.1", "bob@10.0.0.2"]
+    );
+    assert_eq!(cluster.get_tag("host1").map(Vec::len), Some(2));
+
+    // resolver_use_libc_fallback=no, passed through as is
+    let mut cluster = Cluster::default();
+    let mut resolver = EmptyResolver {
+        libc_asked: Vec::new(),
+    };
+    let mut out = Vec::new();
+    cluster.resolve_need_dns(&mut resolver, &need_dns, false, &mut out);
+    assert!(resolver.libc_asked.is_empty());
+    out.sort();
+    assert_eq!(out, vec!["bob@host2", "host1"]);
+}
+
+#[test]
+fn test_max_tag_recursion() {
+    let mut cluster = Cluster::default();
+    cluster
+        .tags
+        .insert("foo".to_string(), vec!["bar".to_string()]);
+    cluster
+        .tags
+        .insert("bar".to_string(), vec!["foo".to_string(), "h0".to_string()]);
+    // cyclic, so stops at the limit, with the hosts found so far
+    let hosts = cluster
+        .resolve_clusters(&mut vec!["foo".to_string()], false, false, 4)
+        .unwrap();
+    assert_eq!(hosts, vec!["h0", "h0"]);
+
+    // t0 -> t1 -> ... -> t9 -> leaf, deep but finite
+    let mut cluster = Cluster::default();
+    for i in 0..10 {
+        let next = if i == 9 {
+            "leaf".to_string()
+        } else {
+            format!("t{}", i + 1)
+        };
+        cluster.tags.insert(format!("t{}", i), vec![next]);
+    }
+    let resolve = |cluster: &mut Cluster, max| {
+        cluster
+            .resolve_clusters(&mut vec!["bob@t0".to_string()], false, false, max)
+            .unwrap()
+    };
+    assert!(resolve(&mut cluster, 5).is_empty());
+    assert_eq!(resolve(&mut cluster, 10), vec!["bob@leaf"]);
+}
+
+#[test]
+fn test_runaway_expansion() {
+    let mut parents = HashMap::new();
+    note_parents(&mut parents, "foo", &["bar".to_string()]);
+    note_parents(&mut parents, "bob@bar", &["bob@foo".to_string()]);
+    assert_eq!(
+        runaway_expansion(&parents, "bob@bar", 4),
+        "Error: cyclic tags after max_tag_recursion=4 levels of tag expansion, ending loop: \
+         bar \u{2192} foo \u{2192} bar"
+    );
+
+    let mut parents = HashMap::new();
+    note_parents(&mut parents, "t0", &["t1".to_string()]);
+    note_parents(&mut parents, "t1", &["t2".to_string()]);
+    assert_eq!(
+        runaway_expansion(&parents, "t2", 2),
+        "Error: tags still unexpanded after max_tag_recursion=2 levels of tag expansion, \
+         ending loop: t0 \u{2192} t1 \u{2192} t2"
+    );
+}
+
+#[test]
+fn test_run_host_command() {
+    use std::os::unix::fs::PermissionsExt;
+
+    let script = crate::tmpnam::tmpnam().unwrap();
+    let write_script = |body: &str| {
+        std::fs::write(&script, format!("#!/bin/sh\n{}\n", body)).unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o700)).unwrap();
+    };
+    let hosts = vec!["web1".to_string(), "db1".to_string()];
+
+    write_script("echo \"$@ mail1\"");
+    assert_eq!(
+        get_external_clusters(&script, &hosts).unwrap(),
+        vec!["web1", "db1", "mail1"]
+    );
+
+    write_script("tr ' ' '\\n' | sort");
+    assert_eq!(filter_hosts(&script, &hosts).unwrap(), vec!["db1", "web1"]);
+
+    write_script("exit 3");
+    let e = filter_hosts(&script, &hosts).unwrap_err().to_string();
+    assert!(e.starts_with("host_filter_command failure."));
+    assert!(e.ends_with("Return Code: [3]"));
+
+    std::fs::remove_file(&script).unwrap();
+    assert_eq!(
+        filter_hosts(&script, &hosts).unwrap_err().to_string(),
+        "host_filter_command is not executable"
+    );
+}
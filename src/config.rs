@@ -7,7 +7,7 @@
 use dirs;
 use regex::Regex;
 use std::borrow::Cow;
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 use std::env;
 use std::path::Path;
 use std::path::PathBuf;
@@ -15,8 +15,9 @@ use std::str::FromStr;
 use std::thread;
 use std::time::Duration;
 
-use crate::er::Result;
+use crate::er::{Error, Result};
 use crate::host::STRICT_GEOMETRY;
+use crate::is_xfile;
 use crate::is_xfile::IsExecutableFile;
 use crate::reader;
 
@@ -24,6 +25,15 @@ lazy_static! {
     static ref TERM_SIZE: Regex = Regex::new(r"^(\d+)x(\d+)$").expect("Regex error TERM_SIZE");
     static ref SSH_CONFIG_META: Regex =
         Regex::new(r"[!*%?,]").expect("Regex error SSH_CONFIG_META");
+    static ref PORT_RANGE: Regex =
+        Regex::new(r"^(\d{1,5})(?::(\d{1,5}))?$").expect("Regex error PORT_RANGE");
+    // what timeout(1) and sleep(1) accept, e.g. 90 1.5 30m 2h
+    static ref DURATION: Regex =
+        Regex::new(r"^\d+(?:\.\d+)?[smhd]?$").expect("Regex error DURATION");
+    // xterm's -class, given via terminal_class/--class or within terminal_args/--term-args
+    static ref TERM_CLASS: Regex = Regex::new(r"^\w+$").expect("Regex error TERM_CLASS");
+    static ref TERM_ARGS_CLASS: Regex =
+        Regex::new(r"-class (\w+)").expect("Regex error TERM_ARGS_CLASS");
 }
 
 #[derive(Debug, Default)]
@@ -42,6 +52,7 @@ pub struct Config {
 #[derive(Debug, Clone)]
 pub enum CommsE {
     Console,
+    Custom, // custom_command template, e.g. "kubectl exec -it %h -- bash"
     Mosh,
     Rsh,
     Sftp,
@@ -50,23 +61,373 @@ pub enum CommsE {
     Invalid,
 }
 
+// What get_script_args() gives as comms for CommsE::Custom,
+// so helper.rs knows to expand the template (which it gets as comms_args).
+pub static CUSTOM_COMMS: &'static str = "custom";
+
+impl FromStr for CommsE {
+    type Err = Error;
+
+    // For --comms, so the names of the executables, not the arg0 aliases.
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "console" => Ok(CommsE::Console),
+            "custom" => Ok(CommsE::Custom),
+            "mosh" => Ok(CommsE::Mosh),
+            "rsh" => Ok(CommsE::Rsh),
+            "sftp" => Ok(CommsE::Sftp),
+            "ssh" => Ok(CommsE::Ssh),
+            "telnet" => Ok(CommsE::Telnet),
+            _ => Err(format!(
+                "Unknown comms {}, expected console, custom, mosh, rsh, sftp, ssh or telnet",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+// How pasted text is mapped before being turned into key events.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputEncoding {
+    Utf8,   // everything gdk knows a keysym for
+    Latin1, // only codepoints <= 255
+    Ascii,  // only codepoints <= 127
+}
+
+impl InputEncoding {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InputEncoding::Utf8 => "utf8",
+            InputEncoding::Latin1 => "latin1",
+            InputEncoding::Ascii => "ascii",
+        }
+    }
+}
+
+impl FromStr for InputEncoding {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf8" | "utf-8" => Ok(InputEncoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(InputEncoding::Latin1),
+            "ascii" => Ok(InputEncoding::Ascii),
+            _ => Err(format!("Unknown encoding {}, expected utf8, latin1 or ascii", s).into()),
+        }
+    }
+}
+
+// Which newly opened servers start out active (receiving keystrokes).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InitialActive {
+    All,   // like perl cssh
+    First, // only the first opened, in the order given
+    None,  // enable them via the Hosts menu
+}
+
+impl InitialActive {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            InitialActive::All => "all",
+            InitialActive::First => "first",
+            InitialActive::None => "none",
+        }
+    }
+
+    // i is the server's position among those opened together.
+    pub fn is_active(self, i: usize) -> bool {
+        match self {
+            InitialActive::All => true,
+            InitialActive::First => i == 0,
+            InitialActive::None => false,
+        }
+    }
+}
+
+impl FromStr for InitialActive {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "all" => Ok(InitialActive::All),
+            "first" => Ok(InitialActive::First),
+            "none" => Ok(InitialActive::None),
+            _ => Err(format!("Unknown initial_active {}, expected all, first or none", s).into()),
+        }
+    }
+}
+
+// What to do with a host string host::parse() can't make sense of, e.g. 'user@'
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OnParseError {
+    Skip,    // warn and carry on
+    Abort,   // like perl cssh, which died
+    Literal, // hand the whole string to ssh, and let it complain
+}
+
+impl OnParseError {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            OnParseError::Skip => "skip",
+            OnParseError::Abort => "abort",
+            OnParseError::Literal => "literal",
+        }
+    }
+}
+
+impl FromStr for OnParseError {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(OnParseError::Skip),
+            "abort" => Ok(OnParseError::Abort),
+            "literal" => Ok(OnParseError::Literal),
+            _ => Err(format!(
+                "Unknown on_parse_error {}, expected skip, abort or literal",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+// When Ctrl-D in the console quits, instead of being sent like any other key.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CtrlDQuits {
+    Empty,  // only once there are no servers, like perl cssh
+    Always, // like EOF to a shell, closing every session
+    Never,
+}
+
+impl CtrlDQuits {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            CtrlDQuits::Empty => "empty",
+            CtrlDQuits::Always => "always",
+            CtrlDQuits::Never => "never",
+        }
+    }
+
+    pub fn quits(self, n_servers: usize) -> bool {
+        match self {
+            CtrlDQuits::Empty => n_servers == 0,
+            CtrlDQuits::Always => true,
+            CtrlDQuits::Never => false,
+        }
+    }
+}
+
+impl FromStr for CtrlDQuits {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "empty" => Ok(CtrlDQuits::Empty),
+            "always" => Ok(CtrlDQuits::Always),
+            "never" => Ok(CtrlDQuits::Never),
+            _ => Err(format!(
+                "Unknown ctrl_d_quits {}, expected empty, always or never",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+// What ssh runs on the far end, so sessions survive a dropped connection.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RemoteMultiplexer {
+    None,
+    Tmux,
+    Screen,
+}
+
+impl RemoteMultiplexer {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RemoteMultiplexer::None => "none",
+            RemoteMultiplexer::Tmux => "tmux",
+            RemoteMultiplexer::Screen => "screen",
+        }
+    }
+}
+
+impl FromStr for RemoteMultiplexer {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "none" | "" => Ok(RemoteMultiplexer::None),
+            "tmux" => Ok(RemoteMultiplexer::Tmux),
+            "screen" => Ok(RemoteMultiplexer::Screen),
+            _ => Err(format!(
+                "Unknown remote_multiplexer {}, expected none, tmux or screen",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+// When resolve_names() runs external_cluster_command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RunExternalCluster {
+    Always,  // on startup and on Hosts -> Add Host(s)
+    Initial, // only on startup, like perl cssh
+    Never,
+}
+
+impl RunExternalCluster {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            RunExternalCluster::Always => "always",
+            RunExternalCluster::Initial => "initial",
+            RunExternalCluster::Never => "never",
+        }
+    }
+
+    // initial is true for the hosts given on startup (or --list)
+    pub fn runs(self, initial: bool) -> bool {
+        match self {
+            RunExternalCluster::Always => true,
+            RunExternalCluster::Initial => initial,
+            RunExternalCluster::Never => false,
+        }
+    }
+}
+
+impl FromStr for RunExternalCluster {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_ascii_lowercase().as_str() {
+            "always" => Ok(RunExternalCluster::Always),
+            "initial" => Ok(RunExternalCluster::Initial),
+            "never" => Ok(RunExternalCluster::Never),
+            _ => Err(format!(
+                "Unknown run_external_cluster {}, expected always, initial or never",
+                s
+            )
+            .into()),
+        }
+    }
+}
+
+// The flags of the WM_NORMAL_HINTS x::change_property() sends when retiling,
+// as in XSizeHints from Xutil.h
+//     USPosition  user specified x, y
+//     USSize      user specified width, height
+//     PPosition   program specified x, y
+//     PSize       program specified width, height
+//     PMinSize    min_width, min_height are set (to the new width, height)
+//     PMaxSize    max_width, max_height are set (to the new width, height)
+// perl cssh hard coded '1 | 2', aka USPosition | USSize
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct WmSizeHints {
+    pub flags: u32,
+}
+
+impl WmSizeHints {
+    pub const US_POSITION: u32 = 1;
+    pub const US_SIZE: u32 = 1 << 1;
+    pub const P_POSITION: u32 = 1 << 2;
+    pub const P_SIZE: u32 = 1 << 3;
+    pub const P_MIN_SIZE: u32 = 1 << 4;
+    pub const P_MAX_SIZE: u32 = 1 << 5;
+
+    const NAMES: [(&'static str, u32); 6] = [
+        ("USPosition", WmSizeHints::US_POSITION),
+        ("USSize", WmSizeHints::US_SIZE),
+        ("PPosition", WmSizeHints::P_POSITION),
+        ("PSize", WmSizeHints::P_SIZE),
+        ("PMinSize", WmSizeHints::P_MIN_SIZE),
+        ("PMaxSize", WmSizeHints::P_MAX_SIZE),
+    ];
+
+    pub fn has(self, flag: u32) -> bool {
+        self.flags & flag != 0
+    }
+
+    // e.g. "USPosition|USSize"
+    pub fn as_string(self) -> String {
+        WmSizeHints::NAMES
+            .iter()
+            .filter(|(_, flag)| self.has(*flag))
+            .map(|(name, _)| *name)
+            .collect::<Vec<_>>()
+            .join("|")
+    }
+}
+
+impl Default for WmSizeHints {
+    fn default() -> Self {
+        WmSizeHints {
+            flags: WmSizeHints::US_POSITION | WmSizeHints::US_SIZE,
+        }
+    }
+}
+
+impl FromStr for WmSizeHints {
+    type Err = Error;
+
+    // names separated by | or , e.g. "USPosition|USSize|PPosition|PSize"
+    fn from_str(s: &str) -> Result<Self> {
+        let mut flags = 0;
+        for name in s.split(|c| c == '|' || c == ',').map(str::trim) {
+            match WmSizeHints::NAMES
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+            {
+                Some((_, flag)) => flags |= flag,
+                None => {
+                    return Err(format!(
+                        "Unknown size_hint_flags {}, expected USPosition, USSize, \
+                         PPosition, PSize, PMinSize or PMaxSize",
+                        name
+                    )
+                    .into())
+                }
+            }
+        }
+        Ok(WmSizeHints { flags })
+    }
+}
+
 // Maybe turn this into an enum?
 #[derive(Debug, Clone)]
 pub struct Comms {
     pub comms: CommsE,
     pub command: Cow<'static, str>,
+    pub connect_timeout: Option<u32>, // seconds, see connect_timeout_opt()
     console: Cow<'static, str>,
     console_args: Cow<'static, str>,
+    pub console_command: Cow<'static, str>,
+    console_port_opt: Cow<'static, str>,
+    pub custom_command: Cow<'static, str>,
     mosh: Cow<'static, str>,
     mosh_args: Cow<'static, str>,
+    mosh_port_opt: Cow<'static, str>,
+    pub mosh_port_range: Cow<'static, str>,
+    pub mosh_server: Cow<'static, str>,
+    pub remote_multiplexer: RemoteMultiplexer,
+    pub remote_session_name: Cow<'static, str>,
     rsh: Cow<'static, str>,
     rsh_args: Cow<'static, str>,
+    rsh_port_opt: Cow<'static, str>,
     telnet: Cow<'static, str>,
     telnet_args: Cow<'static, str>,
+    pub telnet_login_opt: Cow<'static, str>,
     ssh: Cow<'static, str>,
     pub ssh_args: Cow<'static, str>,
+    ssh_port_opt: Cow<'static, str>,
+    pub ssh_send_env: Vec<String>,
     sftp: Cow<'static, str>,
     sftp_args: Cow<'static, str>,
+    pub sftp_batch_file: Option<PathBuf>,
+    sftp_port_opt: Cow<'static, str>,
+    pub suppress_motd: bool,
     //user: Cow<'static, str>,
 }
 
@@ -75,29 +436,103 @@ impl Default for Comms {
         Self {
             comms: CommsE::Invalid,
             command: Cow::Borrowed(""),
+            connect_timeout: None,
             console: Cow::Borrowed("console"),
             console_args: Cow::Borrowed(""),
+            console_command: Cow::Borrowed(""),
+            console_port_opt: Cow::Borrowed("-p "),
+            custom_command: Cow::Borrowed(""),
             mosh: Cow::Borrowed("mosh"),
             mosh_args: Cow::Borrowed(""),
+            // mosh's -p/--port is for mosh-server's UDP port, see mosh_port_range,
+            // so the ssh port goes to the ssh mosh runs. \ as it's split by sh.
+            mosh_port_opt: Cow::Borrowed(r"--ssh=ssh\ -p\ "),
+            mosh_port_range: Cow::Borrowed(""),
+            mosh_server: Cow::Borrowed(""),
+            remote_multiplexer: RemoteMultiplexer::None,
+            remote_session_name: Cow::Borrowed("main"),
             rsh: Cow::Borrowed("rsh"),
             rsh_args: Cow::Borrowed(""),
+            rsh_port_opt: Cow::Borrowed("-p "),
             telnet: Cow::Borrowed("telnet"),
             telnet_args: Cow::Borrowed(""),
+            telnet_login_opt: Cow::Borrowed(""),
             ssh: Cow::Borrowed("ssh"),
             ssh_args: Cow::Borrowed(""),
+            ssh_port_opt: Cow::Borrowed("-p "),
+            ssh_send_env: Vec::new(),
             sftp: Cow::Borrowed("sftp"),
             sftp_args: Cow::Borrowed(""),
+            sftp_batch_file: None,
+            sftp_port_opt: Cow::Borrowed("-P "), // sftp's -p preserves times
+            suppress_motd: false,
             //user: Cow::Borrowed(""),
         }
     }
 }
 
+impl Comms {
+    // connect_timeout as an ssh option, with a leading space like child::send_env_opts(),
+    // unless ssh_args already has its own ConnectTimeout. Other comms have no equivalent.
+    pub fn connect_timeout_opt(&self) -> String {
+        let in_ssh_args = self
+            .ssh_args
+            .to_ascii_lowercase()
+            .contains("connecttimeout");
+        match (&self.comms, self.connect_timeout) {
+            (CommsE::Ssh, Some(secs)) if !in_ssh_args => format!(" -o ConnectTimeout={}", secs),
+            _ => String::new(),
+        }
+    }
+
+    // tcssh's "-- args..", after the comms' own args so they come before [-l user] host.
+    // The templates have no args, %h etc go wherever the user wants them.
+    pub fn append_args(&mut self, extra: &str) -> Result<()> {
+        let args = match self.comms {
+            CommsE::Console if !self.console_command.is_empty() => None,
+            CommsE::Console => Some(&mut self.console_args),
+            CommsE::Mosh => Some(&mut self.mosh_args),
+            CommsE::Rsh => Some(&mut self.rsh_args),
+            CommsE::Sftp => Some(&mut self.sftp_args),
+            CommsE::Ssh => Some(&mut self.ssh_args),
+            CommsE::Telnet => Some(&mut self.telnet_args),
+            CommsE::Custom | CommsE::Invalid => None,
+        };
+        match args {
+            Some(args) => {
+                let args = args.to_mut();
+                if !args.is_empty() {
+                    args.push(' ');
+                }
+                args.push_str(extra);
+                Ok(())
+            }
+            None => Err("Args after -- need comms with args, not a template".into()),
+        }
+    }
+
+    // What goes before the port, for helper.rs to pass it to comms.
+    // telnet takes it after the host, and custom_command has %p, so neither use this.
+    pub fn port_opt(&self) -> &str {
+        match self.comms {
+            CommsE::Console => &self.console_port_opt,
+            CommsE::Mosh => &self.mosh_port_opt,
+            CommsE::Rsh => &self.rsh_port_opt,
+            CommsE::Sftp => &self.sftp_port_opt,
+            CommsE::Ssh => &self.ssh_port_opt,
+            CommsE::Custom | CommsE::Telnet | CommsE::Invalid => "",
+        }
+    }
+}
+
 // Every thing which was not in perl cssh's %default_config initialization
 // but was added dynamically by bits of code everywhere.
 #[derive(Debug, Clone, Default)]
 pub struct Dynamic {
     pub username: Option<String>, // TODO, no setters!
     pub title: Option<String>,    // from arg0
+    pub wait_all: bool,           // --wait-all
+    pub color_tags: BTreeMap<String, String>, // color_by=tag, see app::set_color_tags()
 }
 
 #[derive(Debug)]
@@ -105,10 +540,16 @@ pub struct Terminal {
     pub allow_send_events: Cow<'static, str>,
     pub args: Option<String>,
     pub bg_style_dark: bool,
+    pub class: Option<String>, // xterm -class, for WM rules
+    pub color_by_tag: bool,
     pub colorize: bool,
     pub decoration_height: u32,
     pub decoration_width: u32,
     pub font: Cow<'static, str>,
+    // (screen width, height, font), see font_for_screen()
+    pub font_overrides: Vec<(u32, u32, String)>,
+    pub grid_gap_x: u32, // space between tiled windows, on top of reserve_*
+    pub grid_gap_y: u32,
     pub reserve_bottom: u32,
     pub reserve_left: u32,
     pub reserve_right: u32,
@@ -127,12 +568,17 @@ impl Default for Terminal {
             allow_send_events: Cow::Borrowed("-xrm '*.VT100.allowSendEvents:true'"),
             args: None,
             bg_style_dark: true,
+            class: None,
+            color_by_tag: false,
             colorize: true,
             decoration_height: 10,
             decoration_width: 8,
             //font: Cow::Borrowed("9x15bold"),
             //font: Cow::Borrowed("8x16"),
             font: Cow::Borrowed("6x13"),
+            font_overrides: Vec::new(),
+            grid_gap_x: 0,
+            grid_gap_y: 0,
             reserve_bottom: 0,
             reserve_left: 5,
             reserve_right: 0,
@@ -147,6 +593,82 @@ impl Default for Terminal {
     }
 }
 
+impl Terminal {
+    // font_overrides, e.g. "3840x2160:10x20,1920x1080:6x13"
+    // The font may have its own :s (xft:Mono:size=12) but not commas.
+    fn set_font_overrides(&mut self, value: &str) {
+        self.font_overrides.clear();
+        for entry in value.split(',').map(str::trim).filter(|e| !e.is_empty()) {
+            let mut parts = entry.splitn(2, ':');
+            let size = parts.next().and_then(|size| TERM_SIZE.captures(size));
+            let wh = size.map(|cap| (cap[1].parse::<u32>(), cap[2].parse::<u32>()));
+            match (wh, parts.next()) {
+                (Some((Ok(w), Ok(h))), Some(font)) if !font.is_empty() => {
+                    self.font_overrides.push((w, h, font.to_string()))
+                }
+                _ => eprintln!("Warn: Ignoring font_overrides entry {}", entry),
+            }
+        }
+    }
+
+    fn font_overrides_str(&self) -> String {
+        let entries: Vec<String> = self
+            .font_overrides
+            .iter()
+            .map(|(w, h, font)| format!("{}x{}:{}", w, h, font))
+            .collect();
+        entries.join(",")
+    }
+
+    // The font for a screen of w x h pixels, see app::font_for_screen().
+    pub fn font_for_screen(&self, w: u32, h: u32) -> Option<&str> {
+        self.font_overrides
+            .iter()
+            .find(|(ow, oh, _)| (*ow, *oh) == (w, h))
+            .map(|(_, _, font)| font.as_str())
+    }
+
+    // xterm looks up its resources by class, so allowSendEvents has to follow a -class.
+    pub fn set_class(&mut self, class: &str) -> Result<()> {
+        if !TERM_CLASS.is_match(class) {
+            return Err(format!("Bad terminal class {}", class).into());
+        }
+        self.class = Some(class.to_string());
+        self.set_allow_send_events_class(class);
+        Ok(())
+    }
+
+    // terminal_size and --term-size, COLSxROWS e.g. 80x24, neither of them 0.
+    pub fn set_size(&mut self, size: &str) -> Result<()> {
+        let cols_rows = TERM_SIZE
+            .captures(size)
+            .map(|cap| (cap[1].parse::<u32>(), cap[2].parse::<u32>()));
+        match cols_rows {
+            Some((Ok(x), Ok(y))) if x != 0 && y != 0 => {
+                self.terminal_size_x = x;
+                self.terminal_size_y = y;
+                self.terminal_size = Cow::Owned(String::from(size));
+                Ok(())
+            }
+            _ => Err(format!("Bad terminal size {}, expected COLSxROWS e.g. 80x24", size).into()),
+        }
+    }
+
+    // --term-args may carry its own -class
+    pub fn set_args(&mut self, args: &str) {
+        self.args = Some(args.to_string());
+        if let Some(cap) = TERM_ARGS_CLASS.captures(args) {
+            if let Some(class) = cap.get(1) {
+                self.set_allow_send_events_class(class.as_str());
+            }
+        }
+    }
+
+    fn set_allow_send_events_class(&mut self, class: &str) {
+        self.allow_send_events = Cow::Owned(format!("-xrm '{}.VT100.allowSendEvents:true'", class));
+    }
+}
+
 #[derive(Debug)]
 pub struct Macros {
     pub enabled: bool, // perl cssh calls this config->{macros_enabled}, everything else was macro_*
@@ -204,6 +726,7 @@ pub struct Screen {
     pub reserve_bottom: u32,
     pub reserve_left: u32,
     pub reserve_right: u32,
+    pub reserve_for_console: u32, // tcssh only, on top of reserve_top
 }
 
 impl Default for Screen {
@@ -213,22 +736,40 @@ impl Default for Screen {
             reserve_bottom: 60,
             reserve_left: 0,
             reserve_right: 0,
+            reserve_for_console: 0,
         }
     }
 }
 
+impl Screen {
+    // What retiling keeps clear at the top, e.g. for a console docked there.
+    pub fn effective_reserve_top(&self) -> u32 {
+        self.reserve_top.saturating_add(self.reserve_for_console)
+    }
+}
+
 #[derive(Debug)]
 pub struct Keymap {
     pub use_hotkeys: bool,
     pub key_addhost: Cow<'static, str>,
+    pub key_clear: Cow<'static, str>, // tcssh only
     key_clientname: Cow<'static, str>,
     pub key_history: Cow<'static, str>,
+    pub key_hotkeys_enable: Cow<'static, str>, // tcssh only
     key_localname: Cow<'static, str>,
     key_macros_enable: Cow<'static, str>,
+    pub key_minimize_hosts: Cow<'static, str>, // tcssh only
+    pub key_opacity_down: Cow<'static, str>,   // tcssh only
+    pub key_opacity_up: Cow<'static, str>,     // tcssh only
     pub key_paste: Cow<'static, str>,
+    pub key_paste_enter: Cow<'static, str>,
     pub key_quit: Cow<'static, str>,
     pub key_raise_hosts: Cow<'static, str>,
+    pub key_reload_config: Cow<'static, str>, // tcssh only
+    pub key_restore_hosts: Cow<'static, str>, // tcssh only
     pub key_retile_hosts: Cow<'static, str>,
+    pub key_send_secret: Cow<'static, str>, // tcssh only
+    pub key_show_console: Cow<'static, str>,
     //key_username: Cow<'static, str>, // unused
     //mouse_paste: Cow<'static, str>, // unused
 }
@@ -238,14 +779,24 @@ impl Default for Keymap {
         Self {
             use_hotkeys: true,
             key_addhost: Cow::Borrowed("<Control><Shift>plus"),
+            key_clear: Cow::Borrowed(""), // unbound, <Alt>c is readline's capitalize-word
             key_clientname: Cow::Borrowed("<Alt>n"),
             key_history: Cow::Borrowed("<Alt>h"),
+            key_hotkeys_enable: Cow::Borrowed(""), // unbound, File -> Use Hotkeys is always there
             key_localname: Cow::Borrowed("<Alt>l"),
             key_macros_enable: Cow::Borrowed("<Alt>p"),
+            key_minimize_hosts: Cow::Borrowed(""), // unbound, so Meta-m still reaches the sessions
+            key_opacity_down: Cow::Borrowed(""),   // unbound, <Control>minus is ^_, readline's undo
+            key_opacity_up: Cow::Borrowed(""),
             key_paste: Cow::Borrowed("<Control>v"),
+            key_paste_enter: Cow::Borrowed(""), // unbound, <Control><Shift>v is the terminals' paste
             key_quit: Cow::Borrowed("<Alt>q"),
             key_raise_hosts: Cow::Borrowed("<Alt>i"),
+            key_reload_config: Cow::Borrowed(""), // unbound, File -> Reload config is enough
+            key_restore_hosts: Cow::Borrowed(""),
             key_retile_hosts: Cow::Borrowed("<Alt>r"),
+            key_send_secret: Cow::Borrowed(""),
+            key_show_console: Cow::Borrowed("<Control><Alt>c"),
             //key_username: Cow::Borrowed("<Alt>u"),
             //mouse_paste: Cow::Borrowed("<Button>2"),
         }
@@ -255,6 +806,7 @@ impl Default for Keymap {
 #[derive(Debug)]
 pub struct Menu {
     pub max_addhost_menu_cluster_items: u8,
+    pub max_recent_tags: u8,
     //max_host_menu_items: u8, // unused
     //menu_host_autotearoff: u8, // unused
     //menu_send_autotearoff: u8, // unused
@@ -268,6 +820,7 @@ impl Default for Menu {
 
         Self {
             max_addhost_menu_cluster_items: 6,
+            max_recent_tags: 5,
             //max_host_menu_items: 30,
             //menu_host_autotearoff: 0,
             //menu_send_autotearoff: 0,
@@ -278,19 +831,46 @@ impl Default for Menu {
 
 #[derive(Debug)]
 pub struct Misc {
+    pub activate_added_hosts: bool,
     pub auto_close: Cow<'static, str>,
     pub auto_quit: bool,
+    pub auto_retile_on_close: bool,
+    pub confirm_quit_with_sessions: bool,
+    pub ctrl_d_quits: CtrlDQuits,
+    pub console_geometry: Option<String>,
     pub console_position: Option<String>,
+    pub console_redraw_direct: bool,
+    pub default_domain: Option<String>,
     pub external_cluster_command: Option<PathBuf>,
     pub extra_cluster_file: Vec<PathBuf>,
     pub extra_tag_file: Vec<PathBuf>,
+    pub fast_startup: bool,
     pub history_height: u16,
     pub history_width: u16,
+    pub host_filter_command: Option<PathBuf>,
+    pub initial_active: InitialActive,
+    pub input_encoding: InputEncoding,
+    pub keep_dead_in_menu: bool,
+    pub max_tag_recursion: u32,
+    pub on_parse_error: OnParseError,
+    pub open_dialog_when_empty: bool,
+    pub order_by_given: bool,
     pub port: Option<String>,
+    pub probe_timeout_ms: u64,
+    pub resolver_use_libc_fallback: bool,
+    pub respect_host_geometry: bool,
+    pub run_action_on_readd: bool,
+    pub run_external_cluster: RunExternalCluster,
+    pub session_timeout: Cow<'static, str>,
     pub show_history: bool,
+    pub size_hint_flags: WmSizeHints,
+    pub suggest_tags: bool,
+    pub title_format: Option<String>,
     pub unique_servers: bool,
+    pub unmap_on_raise: Option<bool>, // None follows unmap_on_redraw
     pub unmap_on_redraw: bool,
     pub use_all_a_records: bool,
+    pub use_ssh_config_hosts: bool,
     //use_natural_sort: bool, // unused
     pub window_tiling: bool,
     pub window_tiling_right: bool,
@@ -299,19 +879,46 @@ pub struct Misc {
 impl Default for Misc {
     fn default() -> Self {
         Self {
+            activate_added_hosts: true,
             auto_close: Cow::Borrowed("5"),
             auto_quit: true,
+            auto_retile_on_close: false,
+            confirm_quit_with_sessions: false,
+            ctrl_d_quits: CtrlDQuits::Empty,
+            console_geometry: None,
             console_position: None,
+            console_redraw_direct: false,
+            default_domain: None,
             external_cluster_command: None,
             extra_cluster_file: Vec::new(),
             extra_tag_file: Vec::new(),
+            fast_startup: false,
             history_height: 10,
             history_width: 40,
+            host_filter_command: None,
+            initial_active: InitialActive::All,
+            input_encoding: InputEncoding::Utf8,
+            keep_dead_in_menu: false,
+            max_tag_recursion: 128,
+            on_parse_error: OnParseError::Skip,
+            open_dialog_when_empty: false,
+            order_by_given: false,
             port: None,
+            probe_timeout_ms: 500,
+            resolver_use_libc_fallback: true,
+            respect_host_geometry: false,
+            run_action_on_readd: true,
+            run_external_cluster: RunExternalCluster::Initial,
+            session_timeout: Cow::Borrowed(""),
             show_history: false,
+            size_hint_flags: WmSizeHints::default(),
+            suggest_tags: false,
+            title_format: None,
+            unmap_on_raise: None,
             unmap_on_redraw: false,
             unique_servers: false,
             use_all_a_records: false,
+            use_ssh_config_hosts: true,
             //use_natural_sort: false,
             window_tiling: true,
             window_tiling_right: true,
@@ -319,6 +926,25 @@ impl Default for Misc {
     }
 }
 
+impl Misc {
+    // added is true for Hosts -> Add Host(s), which activate_added_hosts=no leaves inactive.
+    pub fn initial_active(&self, added: bool) -> InitialActive {
+        if added && !self.activate_added_hosts {
+            InitialActive::None
+        } else {
+            self.initial_active
+        }
+    }
+
+    // Where the console is first drawn, console_geometry=WxH+X+Y wins over console_position=+X+Y
+    pub fn initial_console_geometry(&self) -> Option<String> {
+        self.console_geometry
+            .as_ref()
+            .or_else(|| self.console_position.as_ref())
+            .cloned()
+    }
+}
+
 #[derive(Debug)]
 enum CheckedPathBuf {
     DoesNotExist,
@@ -327,8 +953,23 @@ enum CheckedPathBuf {
 
 #[derive(Debug)]
 pub struct Tcssh {
+    pub bracketed_paste: bool,
     config_dir: Option<CheckedPathBuf>,
+    pub console_autohide_ms: u64, // 0 never hides the console, see g::GtkStuff::autohide()
+    // How long to wait for the WM before showing the console, see sleep()
+    pub console_sleep_ms: u64,
+    pub dynamic_titles: bool,
+    pub escape_host_strings: bool,
+    pub fifo_dir: Option<PathBuf>,
+    pub map_sleep_ms: u64, // after (re)mapping each window when retiling, see sleep()
     pub opacity: f64,
+    pub password_command: Option<PathBuf>,
+    pub paste_appends_newline: bool,
+    pub post_connect_hook: Option<PathBuf>,
+    pub preview_send: bool,
+    pub retile_sleep_ms: u64, // after moving each window when retiling, see sleep()
+    pub sanitize_paste: bool,
+    pub send_flash: bool,
     pub sleep: bool,
     pub transparent: bool,
 }
@@ -336,8 +977,24 @@ pub struct Tcssh {
 impl Default for Tcssh {
     fn default() -> Self {
         Self {
+            bracketed_paste: false,
             config_dir: None,
+            console_autohide_ms: 0,
+            console_sleep_ms: 200,
+            dynamic_titles: false,
+            escape_host_strings: false,
+            fifo_dir: None,
+            map_sleep_ms: 100,
             opacity: 0.25f64,
+            password_command: None,
+            paste_appends_newline: true,
+            post_connect_hook: None,
+            preview_send: false,
+            retile_sleep_ms: 100,
+            sanitize_paste: true,
+            // The console's text entry hides what is typed (like a password entry)
+            // so there's no other feedback that a paste went out.
+            send_flash: true,
             sleep: false,
             transparent: true,
         }
@@ -389,6 +1046,13 @@ impl Tcssh {
             None => None,
         }
     }
+
+    // For tests, so they needn't touch the real ~/.tcssh
+    #[cfg(test)]
+    pub fn set_config_dir(&mut self, dir: PathBuf) {
+        self.config_dir = Some(CheckedPathBuf::Exists(dir));
+    }
+
     pub fn sleep(&self, ms: u64) {
         // perl cssh had sleeps all over the place,
         // with a usual comment "WMs are slow"
@@ -427,7 +1091,34 @@ impl Config {
         Ok(())
     }
 
+    // For App::reload_config(), self was just re-read from the config file,
+    // so carry over what didn't come from it.
+    pub fn keep_runtime_state(&mut self, old: &Config) -> Result<()> {
+        self.comms.comms = old.comms.comms.clone(); // from arg0
+        self.dynamic = old.dynamic.clone(); // arg0 or --title
+        self.macros.enabled = old.macros.enabled; // Send -> Use Macros
+        self.keymap.use_hotkeys = old.keymap.use_hotkeys; // File -> Use Hotkeys
+        check_terminal(self)
+    }
+
     pub fn get_script_args(&self) -> (&str, &str, &str, &str) {
+        // Both are templates, so helper.rs builds the command line from them
+        // instead of "comms comms_args [-l user] [-p port] host".
+        let template = match self.comms.comms {
+            CommsE::Custom => Some(&self.comms.custom_command),
+            CommsE::Console if !self.comms.console_command.is_empty() => {
+                Some(&self.comms.console_command)
+            }
+            _ => None,
+        };
+        if let Some(template) = template {
+            return (
+                CUSTOM_COMMS,
+                template,
+                &self.comms.command,
+                &self.misc.auto_close,
+            );
+        }
         let (comms, comms_args) = match self.comms.comms {
             CommsE::Console => (&self.comms.console, &self.comms.console_args),
             CommsE::Mosh => (&self.comms.mosh, &self.comms.mosh_args),
@@ -435,7 +1126,7 @@ impl Config {
             CommsE::Sftp => (&self.comms.sftp, &self.comms.sftp_args),
             CommsE::Ssh => (&self.comms.ssh, &self.comms.ssh_args),
             CommsE::Telnet => (&self.comms.telnet, &self.comms.telnet_args),
-            CommsE::Invalid => panic!("Config has no mapping for comms"),
+            CommsE::Custom | CommsE::Invalid => panic!("Config has no mapping for comms"),
         };
         (
             comms,
@@ -556,16 +1247,98 @@ fn check_terminal(config: &mut Config) -> Result<()> {
     Ok(())
 }
 
+// Keys which choose what gets run, or whose values land unquoted on the
+// terminal's sh -c command line (e.g. ssh_args=-oProxyCommand=..., terminal_args=-e ...)
+// so could run anything too. These are only taken from config files we own,
+// which nobody else can write to, see is_xfile::is_trusted_file()
+static COMMAND_KEYS: &[&str] = &[
+    "command",
+    "console",
+    "console_args",
+    "console_command",
+    "console_port_opt",
+    "custom_command",
+    "external_cluster_command",
+    "host_filter_command",
+    "mosh",
+    "mosh_port_opt",
+    "mosh_server",
+    "password_command",
+    "post_connect_hook",
+    "rsh",
+    "rsh_args",
+    "rsh_port_opt",
+    "sftp",
+    "sftp_args",
+    "sftp_batch_file",
+    "sftp_port_opt",
+    "ssh",
+    "ssh_args",
+    "ssh_port_opt",
+    "telnet",
+    "telnet_args",
+    "telnet_login_opt",
+    "terminal",
+    "terminal_allow_send_events",
+    "terminal_args",
+    "terminal_font",
+    "terminal_name",
+    "terminal_title_opt",
+];
+
 pub fn read_file(config: &mut Config, filename: &PathBuf) -> Result<()> {
-    reader::read_file(filename, true, |key, value| {
-        update_config(config, key, value);
+    let display = env::var("DISPLAY").ok();
+    read_file_for_display(config, filename, display.as_ref().map(String::as_str))
+}
+
+// Keys before any [section] apply everywhere. Those in a [display :0] section
+// only when DISPLAY is :0, and override the others wherever they are in the file.
+fn read_file_for_display(
+    config: &mut Config,
+    filename: &PathBuf,
+    display: Option<&str>,
+) -> Result<()> {
+    let trusted = is_xfile::is_trusted_file(filename);
+    let mut overrides = Vec::new();
+    let mut unknown = Vec::new();
+    reader::read_file(filename, true, |section, key, value| match section {
+        None => update_config(config, key, value, trusted),
+        Some(section) => match display_section(section) {
+            Some(d) => {
+                if Some(d) == display {
+                    overrides.push((key.to_string(), value.to_string()));
+                }
+            }
+            None => {
+                if !unknown.iter().any(|s| s == section) {
+                    eprintln!("Warn: Ignoring unknown section [{}]", section);
+                    unknown.push(section.to_string());
+                }
+            }
+        },
     })?;
+    for (key, value) in overrides {
+        update_config(config, &key, &value, trusted);
+    }
 
     Ok(())
 }
 
-fn update_config(config: &mut Config, key: &str, value: &str) {
+fn update_config(config: &mut Config, key: &str, value: &str, trusted: bool) {
+    if !trusted && COMMAND_KEYS.contains(&key) {
+        eprintln!(
+            "Warn: Ignoring {} from a config file which isn't ours, or others can write to",
+            key
+        );
+        return;
+    }
     match key {
+        // tcssh only, defaults to "yes", "no" so Hosts -> Add Host(s) opens them inactive.
+        "activate_added_hosts" => {
+            config.misc.activate_added_hosts =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
         "auto_close" => config.misc.auto_close = Cow::Owned(String::from(value)),
 
         // perl cssh defaults to "yes" and checked /yes/i
@@ -577,24 +1350,85 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
             config.misc.auto_quit =
                 value.contains("yes") || value.to_ascii_lowercase().contains("yes")
         }
+        // tcssh only, retile the survivors whenever sessions close, see wait_children.rs
+        "auto_retile_on_close" => {
+            config.misc.auto_retile_on_close =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        // tcssh only, wrap pasted clipboard text in ESC[200~ ... ESC[201~ so shells
+        // which understand it don't run each line as it arrives, see send_text::bracket_paste()
+        "bracketed_paste" => {
+            config.tcssh.bracketed_paste =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        // tcssh only, "host" (default) or "tag" so all of a tag's hosts get the same color.
+        "color_by" => config.terminal.color_by_tag = "tag" == value,
+
+        // tcssh only, defaults to "no"
+        "confirm_quit_with_sessions" => {
+            config.misc.confirm_quit_with_sessions =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
 
         // "command" => {} // command is not parsed from config, but it works on CLI. perl; 'cssh -a ls ::1'
         // "comms" => {}, // command, comms and title are not parsed from config.
+        // tcssh only, seconds, passed to ssh as -o ConnectTimeout=N. Empty for ssh's default.
+        "connect_timeout" => {
+            if value.is_empty() {
+                config.comms.connect_timeout = None;
+            } else {
+                match value.parse::<u32>() {
+                    Ok(secs) => config.comms.connect_timeout = Some(secs),
+                    Err(e) => eprintln!("Warn: Ignoring config value for connect_timeout. {}", e),
+                }
+            }
+        }
         "console" => config.comms.console = Cow::Owned(String::from(value)),
         "console_args" => config.comms.console_args = Cow::Owned(String::from(value)),
-        "console_position" => {
-            if value.is_empty() {
-                config.misc.console_position = None;
-            } else if STRICT_GEOMETRY.is_match(value) {
-                config.misc.console_position = Some(String::from(value));
+        // tcssh only, a template like custom_command for ccon, e.g. "ipmi-console %h".
+        // Empty (default) runs "console console_args [-p port] host" like perl cssh.
+        "console_command" => config.comms.console_command = Cow::Owned(String::from(value)),
+        // tcssh only, hide the console after this long without keystrokes or focus,
+        // and bring it back via key_show_console or by pushing the pointer to a screen edge.
+        "console_autohide_ms" => u64_parse(value, &mut config.tcssh.console_autohide_ms),
+        // tcssh only, these *_port_opt are put right before the port, e.g. "-p " or "--port="
+        "console_port_opt" => config.comms.console_port_opt = Cow::Owned(String::from(value)),
+        // tcssh only, like console_position but with a size too, e.g. 800x200+0+0
+        "console_geometry" => geometry_parse(key, value, &mut config.misc.console_geometry),
+        "console_position" => geometry_parse(key, value, &mut config.misc.console_position),
+        // tcssh only, "flicker" (default) hides the console while WMs place the xterms,
+        // "direct" just shows it, which looks better with compositing WMs.
+        "console_redraw_mode" => config.misc.console_redraw_direct = "direct" == value,
+        "console_sleep_ms" => u64_parse(value, &mut config.tcssh.console_sleep_ms),
+        // tcssh only, empty (default), always or never
+        "ctrl_d_quits" => match CtrlDQuits::from_str(value) {
+            Ok(ctrl_d_quits) => config.misc.ctrl_d_quits = ctrl_d_quits,
+            Err(e) => eprintln!("Warn: Ignoring config value for ctrl_d_quits. {}", e),
+        },
+        // tcssh only, used with --comms custom, see helper::expand_custom_command()
+        "custom_command" => config.comms.custom_command = Cow::Owned(String::from(value)),
+        // tcssh only, e.g. "internal.example.com" so 'web1' connects to web1.internal.example.com
+        "default_domain" => {
+            let value = value.trim_start_matches('.');
+            config.misc.default_domain = if value.is_empty() {
+                None
             } else {
-                eprintln!(
-                    "Warn: Ignoring config value for console_position ({})",
-                    value
-                );
+                Some(String::from(value))
             }
         }
         // "debug" => {} // not read from config in tcssh, just CLI
+        // tcssh only, defaults to "no"
+        "dynamic_titles" => {
+            config.tcssh.dynamic_titles =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+        // tcssh only, defaults to "no" (trust the user, like perl cssh)
+        "escape_host_strings" => {
+            config.tcssh.escape_host_strings =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
         "external_cluster_command" => {
             config.misc.external_cluster_command = Some(PathBuf::from(value));
         }
@@ -604,6 +1438,22 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
         // perl cssh didn't have extra_tag_file in it's config.
         // it always relied on --tag-file argument
         //		"extra_tag_file" => config.misc.extra_tag_file = value.split(',').map(PathBuf::from).collect(),
+        // tcssh only, the first tile shows the console right away, see App::run()
+        "fast_startup" => {
+            config.misc.fast_startup =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+        "fifo_dir" => {
+            config.tcssh.fifo_dir = if value.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(value))
+            }
+        }
+
+        // tcssh only, like terminal_reserve_* but only spacing, not decoration.
+        "grid_gap_x" => u32_parse(value, &mut config.terminal.grid_gap_x),
+        "grid_gap_y" => u32_parse(value, &mut config.terminal.grid_gap_y),
         "history_height" => {
             if let Ok(value) = u16::from_str_radix(value, 10) {
                 if value != 0 {
@@ -618,16 +1468,70 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
                 }
             }
         }
+        // tcssh only, run on the fully resolved hosts to filter or reorder them,
+        // see cluster::filter_hosts()
+        "host_filter_command" => {
+            config.misc.host_filter_command = if value.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(value))
+            }
+        }
+        // tcssh only, e.g. 30m to end the comms (ssh etc) after that long, via timeout(1)
+        // then the xterm closes as usual per auto_close. Empty (default) for no limit.
+        // That long since it started, busy or not, timeout(1) can't tell.
+        "session_timeout" => {
+            if value.is_empty() || DURATION.is_match(value) {
+                config.misc.session_timeout = Cow::Owned(String::from(value));
+            } else {
+                eprintln!(
+                    "Warn: Ignoring config value for session_timeout ({})",
+                    value
+                );
+            }
+        }
+        // tcssh only, all (default), first or none
+        "initial_active" => match InitialActive::from_str(value) {
+            Ok(initial_active) => config.misc.initial_active = initial_active,
+            Err(e) => eprintln!("Warn: Ignoring config value for initial_active. {}", e),
+        },
+        "input_encoding" => match InputEncoding::from_str(value) {
+            Ok(input_encoding) => config.misc.input_encoding = input_encoding,
+            Err(e) => eprintln!("Warn: Ignoring config value for input_encoding. {}", e),
+        },
         // Some of these keys aren't used yet.
+        // tcssh only, "yes" to grey out closed sessions in the Hosts menu instead of removing them.
+        // Hosts -> Re-add closed session(s) brings them back.
+        "keep_dead_in_menu" => {
+            config.misc.keep_dead_in_menu =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
         "key_addhost" => config.keymap.key_addhost = Cow::Owned(String::from(value)),
+        "key_clear" => config.keymap.key_clear = Cow::Owned(String::from(value)),
         "key_clientname" => config.keymap.key_clientname = Cow::Owned(String::from(value)),
         "key_history" => config.keymap.key_history = Cow::Owned(String::from(value)),
+        // tcssh only, File -> Use Hotkeys, toggles use_hotkeys, none by default
+        "key_hotkeys_enable" => config.keymap.key_hotkeys_enable = Cow::Owned(String::from(value)),
         "key_localname" => config.keymap.key_localname = Cow::Owned(String::from(value)),
         "key_macros_enable" => config.keymap.key_macros_enable = Cow::Owned(String::from(value)),
+        // tcssh only, Hosts -> Minimize/Restore Windows, none by default
+        "key_minimize_hosts" => config.keymap.key_minimize_hosts = Cow::Owned(String::from(value)),
+        // tcssh only, File -> Opacity, none by default
+        "key_opacity_down" => config.keymap.key_opacity_down = Cow::Owned(String::from(value)),
+        "key_opacity_up" => config.keymap.key_opacity_up = Cow::Owned(String::from(value)),
         "key_paste" => config.keymap.key_paste = Cow::Owned(String::from(value)),
+        "key_paste_enter" => config.keymap.key_paste_enter = Cow::Owned(String::from(value)),
         "key_quit" => config.keymap.key_quit = Cow::Owned(String::from(value)),
         "key_raise_hosts" => config.keymap.key_raise_hosts = Cow::Owned(String::from(value)), // perl cssh didn't read raise?
+        // tcssh only, File -> Reload config, none by default
+        "key_reload_config" => config.keymap.key_reload_config = Cow::Owned(String::from(value)),
+        "key_restore_hosts" => config.keymap.key_restore_hosts = Cow::Owned(String::from(value)),
         "key_retilehosts" => config.keymap.key_retile_hosts = Cow::Owned(String::from(value)), // note _ missing in cfg
+        // tcssh only, Send -> Password, none by default
+        "key_send_secret" => config.keymap.key_send_secret = Cow::Owned(String::from(value)),
+        // tcssh only, grabbed on the whole screen when console_autohide_ms is set
+        "key_show_console" => config.keymap.key_show_console = Cow::Owned(String::from(value)),
         //"key_username" => config.keymap.key_username = Cow::Owned(String::from(value)),
 
         //"lang" => {} // No L10N/I18N support
@@ -640,23 +1544,161 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
         // perl cssh defaulted to "yes" and checked eq 'yes'
         "macros_enabled" => config.macros.enabled = value == "yes",
 
+        "map_sleep_ms" => u64_parse(value, &mut config.tcssh.map_sleep_ms),
+
         // None of these are used yet
         "max_addhost_menu_cluster_items" => {
             u8_parse(value, &mut config.menu.max_addhost_menu_cluster_items)
         }
+        // tcssh only, how many tags the Add Host(s) dialog lists as Recent, 0 for none
+        "max_recent_tags" => u8_parse(value, &mut config.menu.max_recent_tags),
+        // tcssh only, how deeply tags may nest before expansion is assumed to be cyclic
+        "max_tag_recursion" => u32_parse(value, &mut config.misc.max_tag_recursion),
         //"max_host_menu_items" => u8_parse(value, &mut config.menu.max_host_menu_items), // unused
         //"menu_host_autotearoff" => u8_parse(value, &mut config.menu.menu_host_autotearoff), // unused
         //"menu_send_autotearoff" => u8_parse(value, &mut config.menu.menu_send_autotearoff), // unused
         //"send_menu_xml_file" => config.menu.send_menu_xml_file = PathBuf::from(value), // unused
         //"mouse_paste" => config.keymap.mouse_paste = Cow::Owned(String::from(value)), // unused
+        // tcssh only, skip (default), abort or literal
+        "on_parse_error" => match OnParseError::from_str(value) {
+            Ok(on_parse_error) => config.misc.on_parse_error = on_parse_error,
+            Err(e) => eprintln!("Warn: Ignoring config value for on_parse_error. {}", e),
+        },
+        // tcssh only, defaults to "no"
+        "open_dialog_when_empty" => {
+            config.misc.open_dialog_when_empty =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        "mosh_port_opt" => config.comms.mosh_port_opt = Cow::Owned(String::from(value)),
+        // tcssh only, passed to mosh as --port=
+        "mosh_port_range" => {
+            if value.is_empty() || valid_port_range(value) {
+                config.comms.mosh_port_range = Cow::Owned(String::from(value));
+            } else {
+                eprintln!(
+                    "Warn: Ignoring config value for mosh_port_range ({})",
+                    value
+                );
+            }
+        }
+        // tcssh only, passed to mosh as --server=
+        "mosh_server" => config.comms.mosh_server = Cow::Owned(String::from(value)),
+
         "opacity" => {
             if let Ok(value) = f64::from_str(value) {
                 config.tcssh.set_opacity(value);
             }
         }
 
+        // tcssh only, "name" (default) tiles in server name order,
+        // "given" tiles in the order hosts were given.
+        "order" => config.misc.order_by_given = "given" == value,
+
+        // tcssh only, prints a password for Send -> Password, see secret.rs
+        "password_command" => {
+            config.tcssh.password_command = if value.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(value))
+            }
+        }
+
+        // tcssh only, "no" for Send -> Paste and Enter to leave out the Enter.
+        "paste_appends_newline" => {
+            config.tcssh.paste_appends_newline =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        // tcssh only, run for each session once its window is up.
+        "post_connect_hook" => {
+            config.tcssh.post_connect_hook = if value.is_empty() {
+                None
+            } else {
+                Some(PathBuf::from(value))
+            }
+        }
+
+        // tcssh only, Send menu items show what each host would get in the history
+        // first, and only send when activated again, see App::previewed()
+        "preview_send" => {
+            config.tcssh.preview_send =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+        // tcssh only, how long --probe waits for each host's port to accept
+        "probe_timeout_ms" => u64_parse(value, &mut config.misc.probe_timeout_ms),
+
+        // tcssh only, none (default), tmux or screen, for ssh to attach to (or create)
+        // the session remote_session_name on each host. Any -a command runs within it.
+        "remote_multiplexer" => match RemoteMultiplexer::from_str(value) {
+            Ok(mux) => config.comms.remote_multiplexer = mux,
+            Err(e) => eprintln!("Warn: Ignoring config value for remote_multiplexer. {}", e),
+        },
+        // tcssh only, passed unquoted through two shells, so keep it simple.
+        "remote_session_name" => {
+            if !value.is_empty()
+                && value
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-' || c == '.')
+            {
+                config.comms.remote_session_name = Cow::Owned(String::from(value));
+            } else {
+                eprintln!(
+                    "Warn: Ignoring config value for remote_session_name ({})",
+                    value
+                );
+            }
+        }
+
+        // tcssh only, pixels at the top of the screen to keep clear of xterms,
+        // so a console placed there (see console_position) is never in the way.
+        // Pairs well with console_redraw_mode=direct
+        "reserve_for_console" => u32_parse(value, &mut config.screen.reserve_for_console),
+
+        // tcssh only, with use_all_a_records, ask libc (/etc/hosts etc) about names DNS didn't know
+        "resolver_use_libc_fallback" => {
+            config.misc.resolver_use_libc_fallback =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        // tcssh only, defaults to "no"
+        "respect_host_geometry" => {
+            config.misc.respect_host_geometry =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        "retile_sleep_ms" => u64_parse(value, &mut config.tcssh.retile_sleep_ms),
+
+        // tcssh only, "no" to not re-run the action command (-a) for Hosts -> Re-add closed session(s)
+        "run_action_on_readd" => {
+            config.misc.run_action_on_readd =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        // tcssh only, initial (default), always or never, see app::resolve_names()
+        "run_external_cluster" => match RunExternalCluster::from_str(value) {
+            Ok(run) => config.misc.run_external_cluster = run,
+            Err(e) => eprintln!(
+                "Warn: Ignoring config value for run_external_cluster. {}",
+                e
+            ),
+        },
+
         "rsh" => config.comms.rsh = Cow::Owned(String::from(value)),
         "rsh_args" => config.comms.rsh_args = Cow::Owned(String::from(value)),
+        "rsh_port_opt" => config.comms.rsh_port_opt = Cow::Owned(String::from(value)),
+
+        // tcssh only, defaults to "yes", strip escape sequences and control characters
+        // from pasted clipboard text, see send_text::sanitize(). Typed keys are unaffected.
+        "sanitize_paste" => {
+            config.tcssh.sanitize_paste =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
+        "send_flash" => {
+            config.tcssh.send_flash =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes");
+        }
 
         "screen_reserve_bottom" => u32_parse(value, &mut config.screen.reserve_bottom),
         "screen_reserve_left" => u32_parse(value, &mut config.screen.reserve_left),
@@ -665,6 +1707,12 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
 
         // perl cssh defaulted to 0 and checked perl true.
         "show_history" => config.misc.show_history = perl_true(value),
+        // tcssh only, e.g. USPosition|USSize|PPosition|PSize for WMs which ignore
+        // where we put the xterms, see WmSizeHints.
+        "size_hint_flags" => match WmSizeHints::from_str(value) {
+            Ok(hints) => config.misc.size_hint_flags = hints,
+            Err(e) => eprintln!("Warn: Ignoring config value for size_hint_flags. {}", e),
+        },
 
         "sleep_enabled" => {
             config.tcssh.sleep =
@@ -673,10 +1721,45 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
 
         "ssh" => config.comms.ssh = Cow::Owned(String::from(value)),
         "ssh_args" => config.comms.ssh_args = Cow::Owned(String::from(value)),
+        "ssh_port_opt" => config.comms.ssh_port_opt = Cow::Owned(String::from(value)),
+        // tcssh only, e.g. "LANG LC_*" becomes ssh -o SendEnv=LANG -o SendEnv=LC_*
+        "ssh_send_env" => {
+            config.comms.ssh_send_env = value
+                .split(|c: char| c == ',' || c.is_whitespace())
+                .filter(|name| !name.is_empty())
+                .map(String::from)
+                .collect()
+        }
         "sftp" => config.comms.sftp = Cow::Owned(String::from(value)),
         "sftp_args" => config.comms.sftp_args = Cow::Owned(String::from(value)),
+        // tcssh only, passed to sftp as -b, to run the same batch of commands on each host.
+        "sftp_batch_file" => {
+            if value.is_empty() {
+                config.comms.sftp_batch_file = None;
+            } else if Path::new(value).is_file() {
+                config.comms.sftp_batch_file = Some(PathBuf::from(value));
+            } else {
+                eprintln!(
+                    "Warn: Ignoring config value for sftp_batch_file, no such file {}",
+                    value
+                );
+            }
+        }
+        "sftp_port_opt" => config.comms.sftp_port_opt = Cow::Owned(String::from(value)),
+        // tcssh only, print "did you mean" when a host looks like a typo'd tag.
+        "suggest_tags" => {
+            config.misc.suggest_tags =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+        // tcssh only, ssh with -o LogLevel=ERROR, and clear the motd away after login
+        "suppress_motd" => {
+            config.comms.suppress_motd =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
         "telnet" => config.comms.telnet = Cow::Owned(String::from(value)),
         "telnet_args" => config.comms.telnet_args = Cow::Owned(String::from(value)),
+        // tcssh only, e.g. "-l" if your telnet takes one, empty means don't pass the username.
+        "telnet_login_opt" => config.comms.telnet_login_opt = Cow::Owned(String::from(value)),
 
         //        "terminal" => {}
         "terminal_allow_send_events" => {
@@ -691,6 +1774,12 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
         }
         // perl cssh defaulted to 'dark' and checked eq 'dark'
         "terminal_bg_style" => config.terminal.bg_style_dark = "dark" == value,
+        // tcssh only, passed as xterm -class, e.g. for window manager rules
+        "terminal_class" => {
+            if let Err(e) = config.terminal.set_class(value) {
+                eprintln!("Warn: Ignoring config value for terminal_class. {}", e);
+            }
+        }
 
         // perl cssh defaulted to 1 and checked perl true.
         "terminal_colorize" => config.terminal.colorize = perl_true(value),
@@ -699,6 +1788,8 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
         "terminal_decoration_width" => u32_parse(value, &mut config.terminal.decoration_width),
 
         "terminal_font" => config.terminal.font = Cow::Owned(String::from(value)),
+        // tcssh only, terminal_font by screen resolution, e.g. 3840x2160:10x20,1920x1080:6x13
+        "font_overrides" => config.terminal.set_font_overrides(value),
 
         "terminal_name" => {
             if !value.is_empty() {
@@ -720,18 +1811,8 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
 
         "terminal_size" => {
             if !value.is_empty() {
-                if let Some(cap) = TERM_SIZE.captures(value) {
-                    if let (Some(x), Some(y)) = (cap.get(1), cap.get(2)) {
-                        if let Ok(x) = u32::from_str_radix(x.as_str(), 10) {
-                            if let Ok(y) = u32::from_str_radix(y.as_str(), 10) {
-                                if x != 0 && y != 0 {
-                                    config.terminal.terminal_size_x = x;
-                                    config.terminal.terminal_size_y = y;
-                                    config.terminal.terminal_size = Cow::Owned(String::from(value));
-                                }
-                            }
-                        }
-                    }
+                if let Err(e) = config.terminal.set_size(value) {
+                    eprintln!("Warn: Ignoring config value for terminal_size. {}", e);
                 }
             }
         }
@@ -739,7 +1820,23 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
         "terminal_title_opt" => config.terminal.title_opt = Cow::Owned(String::from(value)),
 
         // "title" => {}, // command, comms and title are not parsed from config.
+        // but the format of the console's title is.
+        "title_format" => {
+            config.misc.title_format = if value.is_empty() {
+                None
+            } else {
+                Some(String::from(value))
+            }
+        }
 
+        // tcssh only, unmap_on_redraw for Raise and Retile, empty (default) for the same
+        "unmap_on_raise" => {
+            config.misc.unmap_on_raise = if value.is_empty() {
+                None
+            } else {
+                Some(value.contains("yes") || value.to_ascii_lowercase().contains("yes"))
+            }
+        }
         // perl cssh defaulted to "no" checked /yes/i
         "unmap_on_redraw" => {
             config.misc.unmap_on_redraw =
@@ -749,6 +1846,12 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
         // perl cssh defaulted to 0 checked perl true
         "use_all_a_records" => config.misc.use_all_a_records = perl_true(value),
 
+        // tcssh only, offer ~/.ssh/config's hosts in the "Add Host(s) or Cluster(s)" dialog.
+        "use_ssh_config_hosts" => {
+            config.misc.use_ssh_config_hosts =
+                value.contains("yes") || value.to_ascii_lowercase().contains("yes")
+        }
+
         // perl cssh defaulted to "yes" and checked eq 'yes'
         "use_hotkeys" => config.keymap.use_hotkeys = value == "yes",
 
@@ -767,11 +1870,188 @@ fn update_config(config: &mut Config, key: &str, value: &str) {
     }
 }
 
+// For --dump-config-commented, (section, key, what it is) in the order they're printed.
+// test_dump_docs_complete() checks every key dump_config() prints is here.
+#[rustfmt::skip]
+static DUMP_DOCS: &[(&str, &str, &str)] = &[
+    ("Terminal", "terminal_name", "The terminal to run, found via $PATH"),
+    ("Terminal", "terminal_args", "Extra args for the terminal"),
+    ("Terminal", "terminal_allow_send_events", "How to make xterm accept our key presses"),
+    ("Terminal", "terminal_class", "Passed as -class, e.g. for window manager rules"),
+    ("Terminal", "terminal_font", "Font for the terminals, which also sets their pixel size"),
+    ("Terminal", "font_overrides", "terminal_font by screen size, e.g. 3840x2160:10x20,1920x1080:6x13"),
+    ("Terminal", "terminal_size", "Columns x rows of each terminal"),
+    ("Terminal", "terminal_title_opt", "The terminal's option for setting its title"),
+    ("Terminal", "terminal_colorize", "1 to give each host its own colors"),
+    ("Terminal", "terminal_bg_style", "dark for colored text on black, empty for light"),
+    ("Terminal", "color_by", "host, or tag so all of a tag's hosts share colors"),
+    ("Terminal", "terminal_decoration_height", "Pixels the WM adds above and below each terminal"),
+    ("Terminal", "terminal_decoration_width", "Pixels the WM adds either side of each terminal"),
+    ("Terminal", "terminal_reserve_top", "Pixels kept clear above each terminal"),
+    ("Terminal", "terminal_reserve_bottom", "Pixels kept clear below each terminal"),
+    ("Terminal", "terminal_reserve_left", "Pixels kept clear left of each terminal"),
+    ("Terminal", "terminal_reserve_right", "Pixels kept clear right of each terminal"),
+    ("Terminal", "grid_gap_x", "Pixels between columns of terminals"),
+    ("Terminal", "grid_gap_y", "Pixels between rows of terminals"),
+    ("Terminal", "dynamic_titles", "yes to set each terminal's title as it connects, and when done"),
+    ("Terminal", "title_format", "Format of the console's title"),
+    ("Screen", "screen_reserve_top", "Pixels at the top of the screen kept clear of terminals"),
+    ("Screen", "screen_reserve_bottom", "Pixels at the bottom of the screen kept clear of terminals"),
+    ("Screen", "screen_reserve_left", "Pixels at the left of the screen kept clear of terminals"),
+    ("Screen", "screen_reserve_right", "Pixels at the right of the screen kept clear of terminals"),
+    ("Screen", "reserve_for_console", "More pixels at the top, so a console placed there isn't covered"),
+    ("Tiling", "window_tiling", "yes to tile the terminals"),
+    ("Tiling", "window_tiling_direction", "right tiles from the top left, anything else from the right"),
+    ("Tiling", "order", "name tiles in server name order, given in the order hosts were given"),
+    ("Tiling", "respect_host_geometry", "yes to leave hosts given as host=geometry where they were put"),
+    ("Tiling", "auto_retile_on_close", "yes to retile whenever sessions close"),
+    ("Tiling", "unmap_on_redraw", "yes to hide terminals while they're moved"),
+    ("Tiling", "unmap_on_raise", "unmap_on_redraw for Raise and Retile, empty for the same"),
+    ("Tiling", "size_hint_flags", "WM size hints, e.g. USPosition|USSize for WMs which move terminals"),
+    ("Tiling", "fast_startup", "yes to show the console straight after the first tile"),
+    ("Tiling", "sleep_enabled", "1 to pause for the WM, see map_sleep_ms and retile_sleep_ms"),
+    ("Tiling", "map_sleep_ms", "Pause after mapping each terminal"),
+    ("Tiling", "retile_sleep_ms", "Pause after moving each terminal"),
+    ("Comms", "ssh", "ssh to run"),
+    ("Comms", "ssh_args", "Args for ssh"),
+    ("Comms", "ssh_port_opt", "What goes before the port for ssh"),
+    ("Comms", "ssh_send_env", "Environment variables passed as ssh -o SendEnv, e.g. LANG LC_*"),
+    ("Comms", "suppress_motd", "yes to quiet ssh, and clear the screen after login"),
+    ("Comms", "connect_timeout", "Seconds, passed to ssh as -o ConnectTimeout, empty for ssh's own"),
+    ("Comms", "remote_multiplexer", "none, tmux or screen, for ssh to attach to on each host"),
+    ("Comms", "remote_session_name", "Session name for remote_multiplexer"),
+    ("Comms", "mosh_port_opt", "What goes before the port for mosh"),
+    ("Comms", "mosh_port_range", "Passed to mosh as --port="),
+    ("Comms", "mosh_server", "Passed to mosh as --server="),
+    ("Comms", "rsh", "rsh to run"),
+    ("Comms", "rsh_args", "Args for rsh"),
+    ("Comms", "rsh_port_opt", "What goes before the port for rsh"),
+    ("Comms", "sftp", "sftp to run"),
+    ("Comms", "sftp_args", "Args for sftp"),
+    ("Comms", "sftp_batch_file", "Passed to sftp as -b, to run the same commands on each host"),
+    ("Comms", "sftp_port_opt", "What goes before the port for sftp"),
+    ("Comms", "telnet", "telnet to run"),
+    ("Comms", "telnet_args", "Args for telnet"),
+    ("Comms", "telnet_login_opt", "e.g. -l if your telnet takes one, empty to not pass the user"),
+    ("Comms", "console", "console to run, for ccon"),
+    ("Comms", "console_args", "Args for console"),
+    ("Comms", "console_command", "Template for ccon instead of console, e.g. ipmi-console %h"),
+    ("Comms", "console_port_opt", "What goes before the port for console"),
+    ("Comms", "custom_command", "Template for --comms custom, e.g. kubectl exec -it %h -- bash"),
+    ("Comms", "session_timeout", "e.g. 30m to end each session that long after it starts, busy or not"),
+    ("Comms", "escape_host_strings", "yes to shell quote hosts, instead of trusting them"),
+    ("Comms", "post_connect_hook", "Run for each session once its window is up"),
+    ("Comms", "password_command", "Prints a password for Send -> Password, see secret.rs"),
+    ("Hosts", "default_domain", "Added to hosts without a dot, e.g. web1 becomes web1.example.com"),
+    ("Hosts", "use_all_a_records", "1 to connect to every IP a host resolves to"),
+    ("Hosts", "resolver_use_libc_fallback", "yes to ask libc about hosts DNS didn't know"),
+    ("Hosts", "extra_cluster_file", "More cluster files, comma separated"),
+    ("Hosts", "external_cluster_command", "Run with the hosts, to expand them"),
+    ("Hosts", "run_external_cluster", "initial, always or never, when to run external_cluster_command"),
+    ("Hosts", "host_filter_command", "Reads the resolved hosts on stdin, prints the ones to open"),
+    ("Hosts", "max_tag_recursion", "How deeply tags may nest"),
+    ("Hosts", "probe_timeout_ms", "How long --probe waits for each host to accept a connection"),
+    ("Hosts", "suggest_tags", "yes to print did you mean for hosts which look like typo'd tags"),
+    ("Hosts", "on_parse_error", "skip, abort or literal, for host strings which don't parse"),
+    ("Hosts", "use_ssh_config_hosts", "yes to offer ~/.ssh/config's hosts when adding hosts"),
+    ("Hosts", "initial_active", "all, first or none, which sessions start out active"),
+    ("Hosts", "activate_added_hosts", "no to add hosts inactive"),
+    ("Hosts", "keep_dead_in_menu", "yes to grey out closed sessions in the Hosts menu"),
+    ("Hosts", "run_action_on_readd", "no to not re-run -a for re-added sessions"),
+    ("Hosts", "max_addhost_menu_cluster_items", "Unused"),
+    ("Hosts", "max_recent_tags", "How many recently used tags Add Host(s) lists first, 0 for none"),
+    ("Console", "console_geometry", "Where the console goes, and its size, e.g. 800x200+0+0"),
+    ("Console", "console_position", "Where the console goes, e.g. +0+0"),
+    ("Console", "console_redraw_mode", "flicker hides the console while terminals are placed, direct doesn't"),
+    ("Console", "console_sleep_ms", "Pause before showing the console"),
+    ("Console", "console_autohide_ms", "Hide the console after this long idle, 0 never hides it"),
+    ("Console", "opacity", "The console's opacity, 0.0 to 1.0"),
+    ("Console", "show_history", "1 to show the history instead of the text entry"),
+    ("Console", "history_height", "Rows of history"),
+    ("Console", "history_width", "Columns of history"),
+    ("Console", "open_dialog_when_empty", "yes to ask for hosts when none were given"),
+    ("Console", "confirm_quit_with_sessions", "yes to ask before quitting with sessions open"),
+    ("Console", "auto_quit", "yes to quit once every session has closed"),
+    ("Console", "auto_close", "Seconds a terminal stays open after its session ends, 0 waits for Return"),
+    ("Console", "ctrl_d_quits", "empty, always or never, when Ctrl-D quits"),
+    ("Console", "fifo_dir", "Where the named pipes for each session go"),
+    ("Sending", "input_encoding", "utf8, latin1 or ascii, what pasted text may contain"),
+    ("Sending", "sanitize_paste", "yes to strip escape sequences from pasted text"),
+    ("Sending", "bracketed_paste", "yes to wrap pasted text in bracketed paste markers"),
+    ("Sending", "paste_appends_newline", "no to leave the Enter off Send -> Paste and Enter"),
+    ("Sending", "preview_send", "yes to preview Send menu items, and send on a second go"),
+    ("Sending", "send_flash", "yes to flash the console when text is sent"),
+    ("Macros", "macros_enabled", "yes to substitute the macros below in sent text"),
+    ("Macros", "macro_servername", "Replaced by each session's server name"),
+    ("Macros", "macro_hostname", "Replaced by the local host name"),
+    ("Macros", "macro_username", "Replaced by each session's user name"),
+    ("Macros", "macro_newline", "Replaced by a newline"),
+    ("Macros", "macro_version", "Replaced by the tcssh version"),
+    ("Keys", "use_hotkeys", "no to turn off the keys below"),
+    ("Keys", "key_addhost", "Hosts -> Add Host(s) or Cluster(s)"),
+    ("Keys", "key_clear", "Clear the text entry"),
+    ("Keys", "key_clientname", "Unused"),
+    ("Keys", "key_history", "File -> Show History"),
+    ("Keys", "key_hotkeys_enable", "File -> Use Hotkeys, turns the other keys off and on"),
+    ("Keys", "key_localname", "Unused"),
+    ("Keys", "key_macros_enable", "Unused"),
+    ("Keys", "key_minimize_hosts", "Hosts -> Minimize Windows"),
+    ("Keys", "key_opacity_down", "File -> Opacity -> Less"),
+    ("Keys", "key_opacity_up", "File -> Opacity -> More"),
+    ("Keys", "key_paste", "Paste into every active session"),
+    ("Keys", "key_paste_enter", "Send -> Paste and Enter"),
+    ("Keys", "key_quit", "File -> Quit"),
+    ("Keys", "key_raise_hosts", "Hosts -> Raise and Retile Windows"),
+    ("Keys", "key_reload_config", "File -> Reload config"),
+    ("Keys", "key_restore_hosts", "Hosts -> Restore Windows"),
+    ("Keys", "key_retilehosts", "Hosts -> Retile Windows"),
+    ("Keys", "key_send_secret", "Send -> Password"),
+    ("Keys", "key_show_console", "Bring back a console hidden by console_autohide_ms"),
+];
+
 struct OutConfig {
     buf: String,
 }
 
 impl OutConfig {
+    // --dump-config-commented, the same key=value lines, but in sections,
+    // each with a comment saying what it is and its default.
+    fn commented(&self, defaults: &OutConfig) -> String {
+        let values = self.values();
+        let default_values = defaults.values();
+        let mut out = String::with_capacity(self.buf.len() * 4);
+        out += "# tcssh config, see tcssh --help\n";
+        let mut section = "";
+        for (this_section, key, doc) in DUMP_DOCS {
+            if *this_section != section {
+                section = this_section;
+                out += &format!("\n#### {} ####\n", section);
+            }
+            let default = match default_values.get(key) {
+                Some(default) if !default.is_empty() => default,
+                _ => "(empty)",
+            };
+            out += &format!(
+                "\n# {}\n# Default: {}\n{}={}\n",
+                doc,
+                default,
+                key,
+                values.get(key).unwrap_or(&"")
+            );
+        }
+        out
+    }
+
+    fn values(&self) -> BTreeMap<&str, &str> {
+        self.buf
+            .lines()
+            .filter_map(|line| {
+                let mut key_value = line.splitn(2, '=');
+                Some((key_value.next()?, key_value.next()?))
+            })
+            .collect()
+    }
+
     // add yes no
     fn ayn(&mut self, key: &str, value: bool) {
         self.buf += key;
@@ -834,16 +2114,69 @@ impl Add<&Option<PathBuf>> for OutConfig {
     }
 }
 
-pub fn dump_config(config: &Config) {
+// --dump-config, or --dump-config-commented for a starter config file.
+pub fn dump_config(config: &Config, commented: bool) {
+    let cfg = out_config(config);
+    if commented {
+        print!("{}", cfg.commented(&out_config(&Config::default())));
+    } else {
+        print!("{}", cfg.buf);
+    }
+}
+
+fn out_config(config: &Config) -> OutConfig {
     let mut cfg = OutConfig {
         buf: String::with_capacity(2048),
     };
 
+    cfg.ayn("activate_added_hosts=", config.misc.activate_added_hosts);
     cfg.add("auto_close=", &config.misc.auto_close);
     cfg.ayn("auto_quit=", config.misc.auto_quit);
+    cfg.ayn("auto_retile_on_close=", config.misc.auto_retile_on_close);
+    cfg.ayn("bracketed_paste=", config.tcssh.bracketed_paste);
+    cfg.add(
+        "color_by=",
+        if config.terminal.color_by_tag {
+            "tag"
+        } else {
+            "host"
+        },
+    );
+    cfg.ayn(
+        "confirm_quit_with_sessions=",
+        config.misc.confirm_quit_with_sessions,
+    );
+    cfg.add(
+        "connect_timeout=",
+        &config.comms.connect_timeout.map(|secs| secs.to_string()),
+    );
     cfg.add("console=", &config.comms.console);
     cfg.add("console_args=", &config.comms.console_args);
+    cfg.add("console_command=", &config.comms.console_command);
+    cfg.add(
+        "console_autohide_ms=",
+        format!("{}", config.tcssh.console_autohide_ms).as_str(),
+    );
+    cfg.add("console_port_opt=", &config.comms.console_port_opt);
+    cfg.add("console_geometry=", &config.misc.console_geometry);
     cfg.add("console_position=", &config.misc.console_position);
+    cfg.add(
+        "console_redraw_mode=",
+        if config.misc.console_redraw_direct {
+            "direct"
+        } else {
+            "flicker"
+        },
+    );
+    cfg.add(
+        "console_sleep_ms=",
+        format!("{}", config.tcssh.console_sleep_ms).as_str(),
+    );
+    cfg.add("ctrl_d_quits=", config.misc.ctrl_d_quits.as_str());
+    cfg.add("custom_command=", &config.comms.custom_command);
+    cfg.add("default_domain=", &config.misc.default_domain);
+    cfg.ayn("dynamic_titles=", config.tcssh.dynamic_titles);
+    cfg.ayn("escape_host_strings=", config.tcssh.escape_host_strings);
     cfg.add(
         "external_cluster_command=",
         &config.misc.external_cluster_command,
@@ -856,6 +2189,16 @@ pub fn dump_config(config: &Config) {
         .map(|x| x.to_string_lossy().into_owned())
         .collect();
     cfg.add("extra_cluster_file=", tmp.join(",").as_str());
+    cfg.ayn("fast_startup=", config.misc.fast_startup);
+    cfg.add("fifo_dir=", &config.tcssh.fifo_dir);
+    cfg.add(
+        "grid_gap_x=",
+        format!("{}", config.terminal.grid_gap_x).as_str(),
+    );
+    cfg.add(
+        "grid_gap_y=",
+        format!("{}", config.terminal.grid_gap_y).as_str(),
+    );
 
     cfg.add(
         "history_height=",
@@ -866,15 +2209,30 @@ pub fn dump_config(config: &Config) {
         format!("{}", config.misc.history_width).as_str(),
     );
 
+    cfg.add("host_filter_command=", &config.misc.host_filter_command);
+    cfg.add("initial_active=", config.misc.initial_active.as_str());
+    cfg.add("input_encoding=", config.misc.input_encoding.as_str());
+
+    cfg.ayn("keep_dead_in_menu=", config.misc.keep_dead_in_menu);
     cfg.add("key_addhost=", &config.keymap.key_addhost);
+    cfg.add("key_clear=", &config.keymap.key_clear);
     cfg.add("key_clientname=", &config.keymap.key_clientname);
     cfg.add("key_history=", &config.keymap.key_history);
+    cfg.add("key_hotkeys_enable=", &config.keymap.key_hotkeys_enable);
     cfg.add("key_localname=", &config.keymap.key_localname);
     cfg.add("key_macros_enable=", &config.keymap.key_macros_enable);
+    cfg.add("key_minimize_hosts=", &config.keymap.key_minimize_hosts);
+    cfg.add("key_opacity_down=", &config.keymap.key_opacity_down);
+    cfg.add("key_opacity_up=", &config.keymap.key_opacity_up);
     cfg.add("key_paste=", &config.keymap.key_paste);
+    cfg.add("key_paste_enter=", &config.keymap.key_paste_enter);
     cfg.add("key_quit=", &config.keymap.key_quit);
     cfg.add("key_raise_hosts=", &config.keymap.key_raise_hosts);
+    cfg.add("key_reload_config=", &config.keymap.key_reload_config);
+    cfg.add("key_restore_hosts=", &config.keymap.key_restore_hosts);
     cfg.add("key_retilehosts=", &config.keymap.key_retile_hosts);
+    cfg.add("key_send_secret=", &config.keymap.key_send_secret);
+    cfg.add("key_show_console=", &config.keymap.key_show_console);
 
     cfg.add("macro_hostname=", &config.macros.hostname);
     cfg.add("macro_newline=", &config.macros.newline);
@@ -884,16 +2242,83 @@ pub fn dump_config(config: &Config) {
 
     cfg.ayn("macros_enabled=", config.macros.enabled);
 
+    cfg.add(
+        "map_sleep_ms=",
+        format!("{}", config.tcssh.map_sleep_ms).as_str(),
+    );
+
     cfg.add(
         "max_addhost_menu_cluster_items=",
         format!("{}", config.menu.max_addhost_menu_cluster_items).as_str(),
     );
+    cfg.add(
+        "max_recent_tags=",
+        format!("{}", config.menu.max_recent_tags).as_str(),
+    );
+    cfg.add(
+        "max_tag_recursion=",
+        format!("{}", config.misc.max_tag_recursion).as_str(),
+    );
+
+    cfg.add("mosh_port_opt=", &config.comms.mosh_port_opt);
+    cfg.add("mosh_port_range=", &config.comms.mosh_port_range);
+    cfg.add("mosh_server=", &config.comms.mosh_server);
 
+    cfg.add("on_parse_error=", config.misc.on_parse_error.as_str());
     cfg.add("opacity=", format!("{}", config.tcssh.opacity).as_str());
+    cfg.ayn(
+        "open_dialog_when_empty=",
+        config.misc.open_dialog_when_empty,
+    );
+
+    cfg.add(
+        "order=",
+        if config.misc.order_by_given {
+            "given"
+        } else {
+            "name"
+        },
+    );
+
+    cfg.add("password_command=", &config.tcssh.password_command);
+    cfg.ayn("paste_appends_newline=", config.tcssh.paste_appends_newline);
+    cfg.add("post_connect_hook=", &config.tcssh.post_connect_hook);
+    cfg.ayn("preview_send=", config.tcssh.preview_send);
+    cfg.add(
+        "probe_timeout_ms=",
+        format!("{}", config.misc.probe_timeout_ms).as_str(),
+    );
+
+    cfg.add(
+        "remote_multiplexer=",
+        config.comms.remote_multiplexer.as_str(),
+    );
+    cfg.add("remote_session_name=", &config.comms.remote_session_name);
+    cfg.add(
+        "reserve_for_console=",
+        format!("{}", config.screen.reserve_for_console).as_str(),
+    );
+    cfg.ayn(
+        "resolver_use_libc_fallback=",
+        config.misc.resolver_use_libc_fallback,
+    );
+    cfg.ayn("respect_host_geometry=", config.misc.respect_host_geometry);
+
+    cfg.add(
+        "retile_sleep_ms=",
+        format!("{}", config.tcssh.retile_sleep_ms).as_str(),
+    );
 
     cfg.add("rsh=", &config.comms.rsh);
     cfg.add("rsh_args=", &config.comms.rsh_args);
+    cfg.add("rsh_port_opt=", &config.comms.rsh_port_opt);
+    cfg.ayn("run_action_on_readd=", config.misc.run_action_on_readd);
+    cfg.add(
+        "run_external_cluster=",
+        config.misc.run_external_cluster.as_str(),
+    );
 
+    cfg.ayn("sanitize_paste=", config.tcssh.sanitize_paste);
     cfg.add(
         "screen_reserve_bottom=",
         format!("{}", config.screen.reserve_bottom).as_str(),
@@ -911,16 +2336,33 @@ pub fn dump_config(config: &Config) {
         format!("{}", config.screen.reserve_top).as_str(),
     );
 
+    cfg.ayn("send_flash=", config.tcssh.send_flash);
+    cfg.add("session_timeout=", &config.misc.session_timeout);
+
     cfg.add("sftp=", &config.comms.sftp);
     cfg.add("sftp_args=", &config.comms.sftp_args);
+    cfg.add("sftp_batch_file=", &config.comms.sftp_batch_file);
+    cfg.add("sftp_port_opt=", &config.comms.sftp_port_opt);
 
     cfg.a01("show_history=", config.misc.show_history);
+    cfg.add(
+        "size_hint_flags=",
+        config.misc.size_hint_flags.as_string().as_str(),
+    );
     cfg.a01("sleep_enabled=", config.tcssh.sleep);
 
     cfg.add("ssh=", &config.comms.ssh);
     cfg.add("ssh_args=", &config.comms.ssh_args);
+    cfg.add("ssh_port_opt=", &config.comms.ssh_port_opt);
+    cfg.add(
+        "ssh_send_env=",
+        config.comms.ssh_send_env.join(" ").as_str(),
+    );
+    cfg.ayn("suggest_tags=", config.misc.suggest_tags);
+    cfg.ayn("suppress_motd=", config.comms.suppress_motd);
     cfg.add("telnet=", &config.comms.telnet);
     cfg.add("telnet_args=", &config.comms.telnet_args);
+    cfg.add("telnet_login_opt=", &config.comms.telnet_login_opt);
 
     cfg.add(
         "terminal_allow_send_events=",
@@ -934,6 +2376,7 @@ pub fn dump_config(config: &Config) {
         ""
     };
     cfg.add("terminal_bg_style=", tmp);
+    cfg.add("terminal_class=", &config.terminal.class);
 
     cfg.a01("terminal_colorize=", config.terminal.colorize);
     cfg.add(
@@ -946,6 +2389,10 @@ pub fn dump_config(config: &Config) {
     );
 
     cfg.add("terminal_font=", &config.terminal.font);
+    cfg.add(
+        "font_overrides=",
+        config.terminal.font_overrides_str().as_str(),
+    );
     cfg.add("terminal_name=", &config.terminal.terminal_name);
 
     cfg.add(
@@ -966,9 +2413,19 @@ pub fn dump_config(config: &Config) {
     );
     cfg.add("terminal_size=", &config.terminal.terminal_size);
     cfg.add("terminal_title_opt=", &config.terminal.title_opt);
+    cfg.add("title_format=", &config.misc.title_format);
+    cfg.add(
+        "unmap_on_raise=",
+        match config.misc.unmap_on_raise {
+            Some(true) => "yes",
+            Some(false) => "no",
+            None => "",
+        },
+    );
     cfg.ayn("unmap_on_redraw=", config.misc.unmap_on_redraw);
     cfg.a01("use_all_a_records=", config.misc.use_all_a_records);
     cfg.ayn("use_hotkeys=", config.keymap.use_hotkeys);
+    cfg.ayn("use_ssh_config_hosts=", config.misc.use_ssh_config_hosts);
     cfg.ayn("window_tiling=", config.misc.window_tiling);
 
     let tmp = if config.misc.window_tiling_right {
@@ -977,8 +2434,27 @@ pub fn dump_config(config: &Config) {
         ""
     };
     cfg.add("window_tiling_direction=", tmp);
+    cfg
+}
 
-    print!("{}", cfg.buf);
+// mosh takes either a single port, or "low:high"
+fn valid_port_range(value: &str) -> bool {
+    if let Some(cap) = PORT_RANGE.captures(value) {
+        let port = |m: Option<regex::Match<'_>>| m.and_then(|m| u16::from_str(m.as_str()).ok());
+        match (port(cap.get(1)), cap.get(2)) {
+            (Some(_), None) => true,
+            (Some(lo), hi) => port(hi).map_or(false, |hi| lo <= hi),
+            _ => false,
+        }
+    } else {
+        false
+    }
+}
+
+fn u64_parse(value: &str, it: &mut u64) {
+    if let Ok(value) = u64::from_str_radix(value, 10) {
+        *it = value;
+    }
 }
 
 fn u32_parse(value: &str, it: &mut u32) {
@@ -993,6 +2469,16 @@ fn u8_parse(value: &str, it: &mut u8) {
     }
 }
 
+fn geometry_parse(key: &str, value: &str, it: &mut Option<String>) {
+    if value.is_empty() {
+        *it = None;
+    } else if STRICT_GEOMETRY.is_match(value) {
+        *it = Some(String::from(value));
+    } else {
+        eprintln!("Warn: Ignoring config value for {} ({})", key, value);
+    }
+}
+
 fn perl_true(value: &str) -> bool {
     // perl false (in str context) is "" or "0"
     // perl true  (in str context) is "00", "0x0", " ", any other str
@@ -1007,7 +2493,7 @@ pub fn parse_ssh_config_and_add_hosts(tags: &mut Vec<String>) {
             let file = dir;
             if file.exists() {
                 let mut stags = Vec::new();
-                match reader::read_file(file, false, |key, value| match key {
+                match reader::read_file(file, false, |_, key, value| match key {
                     "Host" => {
                         for value in value.split_whitespace() {
                             if !SSH_CONFIG_META.is_match(value) {
@@ -1037,3 +2523,531 @@ pub fn parse_ssh_config_and_add_hosts(tags: &mut Vec<String>) {
         }
     }
 }
+
+#[test]
+fn test_valid_port_range() {
+    assert!(valid_port_range("60000"));
+    assert!(valid_port_range("60000:61000"));
+    assert!(valid_port_range("65535:65535"));
+    assert!(!valid_port_range("65536"));
+    assert!(!valid_port_range("61000:60000"));
+    assert!(!valid_port_range("60000:"));
+    assert!(!valid_port_range("60000-61000"));
+    assert!(!valid_port_range(""));
+}
+
+#[test]
+fn test_display_sections() {
+    use std::fs;
+
+    let p = crate::tmpnam::tmpnam().unwrap();
+    fs::write(
+        &p,
+        "history_width=50
+[display :0]
+screen_reserve_left=100
+[display :1]
+screen_reserve_left=200
+history_width=60
+[elsewhere]
+history_width=70
+",
+    )
+    .unwrap();
+    let read = |display: Option<&str>| -> Config {
+        let mut config = Config::default();
+        config.misc.history_height = 5; // not in the file, so kept
+        read_file_for_display(&mut config, &p, display).unwrap();
+        config
+    };
+    let none = read(None);
+    let zero = read(Some(":0"));
+    let one = read(Some(":1"));
+    fs::remove_file(&p).unwrap();
+
+    assert_eq!(none.screen.reserve_left, 0);
+    assert_eq!(none.misc.history_width, 50);
+    assert_eq!(zero.screen.reserve_left, 100);
+    assert_eq!(zero.misc.history_width, 50);
+    assert_eq!(one.screen.reserve_left, 200);
+    assert_eq!(one.misc.history_width, 60);
+    assert_eq!(one.misc.history_height, 5);
+
+    assert_eq!(display_section("display :0"), Some(":0"));
+    assert_eq!(display_section("display"), None);
+    assert_eq!(display_section("display :0 :1"), None);
+    assert_eq!(display_section("elsewhere"), None);
+}
+
+#[test]
+fn test_post_connect_hook() {
+    let mut config = Config::default();
+    update_config(&mut config, "post_connect_hook", "/usr/local/bin/register", true);
+    assert_eq!(
+        config.tcssh.post_connect_hook,
+        Some(PathBuf::from("/usr/local/bin/register"))
+    );
+    update_config(&mut config, "post_connect_hook", "", true);
+    assert_eq!(config.tcssh.post_connect_hook, None);
+}
+
+#[test]
+fn test_initial_active() {
+    let opened =
+        |initial: InitialActive| -> Vec<bool> { (0..3).map(|i| initial.is_active(i)).collect() };
+    assert_eq!(opened(InitialActive::All), vec![true, true, true]);
+    assert_eq!(opened(InitialActive::First), vec![true, false, false]);
+    assert_eq!(opened(InitialActive::None), vec![false, false, false]);
+
+    let mut config = Config::default();
+    assert_eq!(config.misc.initial_active, InitialActive::All);
+    update_config(&mut config, "initial_active", "First", true);
+    assert_eq!(config.misc.initial_active, InitialActive::First);
+    update_config(&mut config, "initial_active", "bogus", true); // ignored
+    assert_eq!(config.misc.initial_active, InitialActive::First);
+}
+
+#[test]
+fn test_connect_timeout() {
+    let mut config = Config::default();
+    config.comms.comms = CommsE::Ssh;
+    assert_eq!(config.comms.connect_timeout_opt(), "");
+
+    update_config(&mut config, "connect_timeout", "5", true);
+    assert_eq!(config.comms.connect_timeout, Some(5));
+    assert_eq!(config.comms.connect_timeout_opt(), " -o ConnectTimeout=5");
+
+    // no duplicate, ssh_args wins
+    update_config(&mut config, "ssh_args", "-x -o connecttimeout=30", true);
+    assert_eq!(config.comms.connect_timeout_opt(), "");
+    update_config(&mut config, "ssh_args", "-x", true);
+    assert_eq!(config.comms.connect_timeout_opt(), " -o ConnectTimeout=5");
+
+    // nothing for the others
+    config.comms.comms = CommsE::Telnet;
+    assert_eq!(config.comms.connect_timeout_opt(), "");
+    config.comms.comms = CommsE::Mosh;
+    assert_eq!(config.comms.connect_timeout_opt(), "");
+
+    update_config(&mut config, "connect_timeout", "ten", true); // ignored
+    assert_eq!(config.comms.connect_timeout, Some(5));
+    update_config(&mut config, "connect_timeout", "", true);
+    assert_eq!(config.comms.connect_timeout, None);
+}
+
+#[test]
+fn test_resolver_use_libc_fallback() {
+    let mut config = Config::default();
+    assert!(config.misc.resolver_use_libc_fallback);
+    update_config(&mut config, "resolver_use_libc_fallback", "no", true);
+    assert!(!config.misc.resolver_use_libc_fallback);
+}
+
+#[test]
+fn test_set_opacity() {
+    let mut tcssh = Tcssh::default();
+    let opacity_is = |tcssh: &Tcssh, opacity: f64| (tcssh.opacity - opacity).abs() < 1e-9;
+
+    tcssh.set_opacity(0.5);
+    assert!(tcssh.transparent);
+    assert!(opacity_is(&tcssh, 0.5));
+
+    tcssh.set_opacity(1.0); // fully opaque, so transparency off
+    assert!(!tcssh.transparent);
+    assert!(opacity_is(&tcssh, 1.0));
+    tcssh.set_opacity(1.1);
+    assert!(!tcssh.transparent);
+    assert!(opacity_is(&tcssh, 1.0));
+
+    tcssh.set_opacity(0.0);
+    assert!(tcssh.transparent);
+    assert!(opacity_is(&tcssh, 0.0));
+    tcssh.set_opacity(-0.1);
+    assert!(tcssh.transparent);
+    assert!(opacity_is(&tcssh, 0.0));
+}
+
+#[test]
+fn test_max_tag_recursion() {
+    let mut config = Config::default();
+    assert_eq!(config.misc.max_tag_recursion, 128);
+    update_config(&mut config, "max_tag_recursion", "512", true);
+    assert_eq!(config.misc.max_tag_recursion, 512);
+    update_config(&mut config, "max_tag_recursion", "lots", true);
+    assert_eq!(config.misc.max_tag_recursion, 512);
+}
+
+#[test]
+fn test_preview_send() {
+    let mut config = Config::default();
+    assert!(!config.tcssh.preview_send);
+    update_config(&mut config, "preview_send", "yes", true);
+    assert!(config.tcssh.preview_send);
+}
+
+#[test]
+fn test_probe_timeout_ms() {
+    let mut config = Config::default();
+    assert_eq!(config.misc.probe_timeout_ms, 500);
+    update_config(&mut config, "probe_timeout_ms", "2000", true);
+    assert_eq!(config.misc.probe_timeout_ms, 2000);
+}
+
+#[test]
+fn test_unmap_on_raise() {
+    let mut config = Config::default();
+    assert_eq!(config.misc.unmap_on_raise, None);
+    update_config(&mut config, "unmap_on_raise", "no", true);
+    assert_eq!(config.misc.unmap_on_raise, Some(false));
+    update_config(&mut config, "unmap_on_raise", "Yes", true);
+    assert_eq!(config.misc.unmap_on_raise, Some(true));
+    update_config(&mut config, "unmap_on_raise", "", true);
+    assert_eq!(config.misc.unmap_on_raise, None);
+}
+
+#[test]
+fn test_max_recent_tags() {
+    let mut config = Config::default();
+    assert_eq!(config.menu.max_recent_tags, 5);
+    update_config(&mut config, "max_recent_tags", "0", true);
+    assert_eq!(config.menu.max_recent_tags, 0);
+}
+
+#[test]
+fn test_key_hotkeys_enable() {
+    let mut config = Config::default();
+    assert_eq!(config.keymap.key_hotkeys_enable, "");
+    update_config(&mut config, "key_hotkeys_enable", "<Alt>k", true);
+    assert_eq!(config.keymap.key_hotkeys_enable, "<Alt>k");
+}
+
+#[test]
+fn test_font_overrides() {
+    let mut config = Config::default();
+    assert_eq!(config.terminal.font_for_screen(3840, 2160), None);
+    update_config(
+        &mut config,
+        "font_overrides",
+        "3840x2160:10x20, 1920x1080:xft:Mono:size=9,bogus:7x14,800x600:",
+        true,
+    );
+    assert_eq!(config.terminal.font_for_screen(3840, 2160), Some("10x20"));
+    assert_eq!(
+        config.terminal.font_for_screen(1920, 1080),
+        Some("xft:Mono:size=9")
+    );
+    assert_eq!(config.terminal.font_for_screen(800, 600), None);
+    assert_eq!(config.terminal.font_for_screen(2560, 1440), None);
+    assert_eq!(
+        config.terminal.font_overrides_str(),
+        "3840x2160:10x20,1920x1080:xft:Mono:size=9"
+    );
+}
+
+#[test]
+fn test_suppress_motd() {
+    let mut config = Config::default();
+    assert!(!config.comms.suppress_motd);
+    update_config(&mut config, "suppress_motd", "yes", true);
+    assert!(config.comms.suppress_motd);
+}
+
+#[test]
+fn test_password_command() {
+    let mut config = Config::default();
+    update_config(&mut config, "password_command", "/bin/pass", false); // untrusted
+    assert_eq!(config.tcssh.password_command, None);
+    update_config(&mut config, "password_command", "/bin/pass", true);
+    assert_eq!(
+        config.tcssh.password_command,
+        Some(PathBuf::from("/bin/pass"))
+    );
+    update_config(&mut config, "password_command", "", true);
+    assert_eq!(config.tcssh.password_command, None);
+}
+
+#[test]
+fn test_bracketed_paste() {
+    let mut config = Config::default();
+    assert!(!config.tcssh.bracketed_paste);
+    update_config(&mut config, "bracketed_paste", "yes", true);
+    assert!(config.tcssh.bracketed_paste);
+}
+
+#[test]
+fn test_auto_retile_on_close() {
+    let mut config = Config::default();
+    assert!(!config.misc.auto_retile_on_close);
+    update_config(&mut config, "auto_retile_on_close", "yes", true);
+    assert!(config.misc.auto_retile_on_close);
+    update_config(&mut config, "auto_retile_on_close", "no", true);
+    assert!(!config.misc.auto_retile_on_close);
+}
+
+#[test]
+fn test_activate_added_hosts() {
+    let mut config = Config::default();
+    config.misc.initial_active = InitialActive::First;
+    assert!(config.misc.activate_added_hosts);
+    assert_eq!(config.misc.initial_active(false), InitialActive::First);
+    assert_eq!(config.misc.initial_active(true), InitialActive::First);
+
+    update_config(&mut config, "activate_added_hosts", "no", true);
+    assert!(!config.misc.activate_added_hosts);
+    assert_eq!(config.misc.initial_active(false), InitialActive::First);
+    assert_eq!(config.misc.initial_active(true), InitialActive::None);
+}
+
+#[test]
+fn test_size_hint_flags() {
+    let mut config = Config::default();
+    assert_eq!(config.misc.size_hint_flags.flags, 3); // perl cssh's 1 | 2
+    assert_eq!(config.misc.size_hint_flags.as_string(), "USPosition|USSize");
+
+    update_config(&mut config, "size_hint_flags", "usposition | PSize", true);
+    let hints = config.misc.size_hint_flags;
+    assert!(hints.has(WmSizeHints::US_POSITION));
+    assert!(!hints.has(WmSizeHints::US_SIZE));
+    assert!(hints.has(WmSizeHints::P_SIZE));
+    assert!(!hints.has(WmSizeHints::P_MIN_SIZE));
+    assert_eq!(hints.as_string(), "USPosition|PSize");
+
+    update_config(&mut config, "size_hint_flags", "USSize|PGravity", true); // ignored
+    assert_eq!(config.misc.size_hint_flags, hints);
+}
+
+#[test]
+fn test_on_parse_error() {
+    let mut config = Config::default();
+    assert_eq!(config.misc.on_parse_error, OnParseError::Skip);
+    update_config(&mut config, "on_parse_error", "literal", true);
+    assert_eq!(config.misc.on_parse_error, OnParseError::Literal);
+    update_config(&mut config, "on_parse_error", "die", true); // ignored
+    assert_eq!(config.misc.on_parse_error, OnParseError::Literal);
+}
+
+#[test]
+fn test_console_command() {
+    let mut config = Config::default();
+    config.comms.comms = CommsE::Console;
+    assert_eq!(config.get_script_args().0, "console");
+
+    update_config(&mut config, "console_command", "conserver-cli -c %h", true);
+    assert_eq!(config.get_script_args().0, CUSTOM_COMMS);
+    assert_eq!(config.get_script_args().1, "conserver-cli -c %h");
+
+    // only for console
+    config.comms.comms = CommsE::Ssh;
+    assert_eq!(config.get_script_args().0, "ssh");
+    config.comms.comms = CommsE::Telnet;
+    assert_eq!(config.get_script_args().0, "telnet");
+}
+
+#[test]
+fn test_dump_docs_complete() {
+    let out = out_config(&Config::default());
+    let dumped: BTreeSet<&str> = out.values().keys().cloned().collect();
+    let documented: BTreeSet<&str> = DUMP_DOCS.iter().map(|(_, key, _)| *key).collect();
+    assert_eq!(dumped, documented);
+    assert_eq!(documented.len(), DUMP_DOCS.len()); // no duplicates
+}
+
+#[test]
+fn test_dump_config_commented() {
+    let defaults = out_config(&Config::default());
+    let mut config = Config::default();
+    update_config(&mut config, "terminal_font", "7x14", true);
+    let commented = out_config(&config).commented(&defaults);
+    assert!(commented.starts_with("# tcssh config"));
+    assert!(commented.contains("\n#### Terminal ####\n"));
+    assert!(commented.contains("\n#### Comms ####\n"));
+    assert!(commented.contains(
+        "\n# Font for the terminals, which also sets their pixel size\n# Default: 6x13\n\
+         terminal_font=7x14\n"
+    ));
+    assert!(commented.contains("\n# Default: (empty)\ndefault_domain=\n"));
+
+    // reads back the same as the plain dump
+    let mut reread = Config::default();
+    for line in commented.lines().filter(|line| !line.starts_with('#')) {
+        let mut key_value = line.splitn(2, '=');
+        if let (Some(key), Some(value)) = (key_value.next(), key_value.next()) {
+            update_config(&mut reread, key, value, true);
+        }
+    }
+    assert_eq!(out_config(&reread).buf, out_config(&config).buf);
+}
+
+#[test]
+fn test_fast_startup() {
+    let mut config = Config::default();
+    assert!(!config.misc.fast_startup);
+    update_config(&mut config, "fast_startup", "yes", true);
+    assert!(config.misc.fast_startup);
+}
+
+#[test]
+fn test_ctrl_d_quits() {
+    let mut config = Config::default();
+    assert_eq!(config.misc.ctrl_d_quits, CtrlDQuits::Empty);
+    assert!(config.misc.ctrl_d_quits.quits(0));
+    assert!(!config.misc.ctrl_d_quits.quits(2));
+    update_config(&mut config, "ctrl_d_quits", "always", true);
+    assert!(config.misc.ctrl_d_quits.quits(2));
+    update_config(&mut config, "ctrl_d_quits", "never", true);
+    assert!(!config.misc.ctrl_d_quits.quits(0));
+    update_config(&mut config, "ctrl_d_quits", "sometimes", true); // ignored
+    assert_eq!(config.misc.ctrl_d_quits, CtrlDQuits::Never);
+}
+
+#[test]
+fn test_run_external_cluster() {
+    let mut config = Config::default();
+    assert_eq!(
+        config.misc.run_external_cluster,
+        RunExternalCluster::Initial
+    );
+    update_config(&mut config, "run_external_cluster", "Never", true);
+    assert_eq!(config.misc.run_external_cluster, RunExternalCluster::Never);
+    update_config(&mut config, "run_external_cluster", "sometimes", true); // ignored
+    assert_eq!(config.misc.run_external_cluster, RunExternalCluster::Never);
+}
+
+#[test]
+fn test_host_filter_command() {
+    let mut config = Config::default();
+    update_config(&mut config, "host_filter_command", "/bin/sort", false); // untrusted
+    assert_eq!(config.misc.host_filter_command, None);
+    update_config(&mut config, "host_filter_command", "/bin/sort", true);
+    assert_eq!(
+        config.misc.host_filter_command,
+        Some(PathBuf::from("/bin/sort"))
+    );
+    update_config(&mut config, "host_filter_command", "", true);
+    assert_eq!(config.misc.host_filter_command, None);
+}
+
+#[test]
+fn test_console_autohide() {
+    let mut config = Config::default();
+    assert_eq!(config.tcssh.console_autohide_ms, 0);
+    assert_eq!(config.keymap.key_show_console, "<Control><Alt>c");
+
+    update_config(&mut config, "console_autohide_ms", "30000", true);
+    update_config(&mut config, "key_show_console", "<Super>grave", true);
+    assert_eq!(config.tcssh.console_autohide_ms, 30000);
+    assert_eq!(config.keymap.key_show_console, "<Super>grave");
+
+    update_config(&mut config, "console_autohide_ms", "soon", true); // ignored
+    assert_eq!(config.tcssh.console_autohide_ms, 30000);
+}
+
+#[test]
+fn test_console_geometry() {
+    let mut config = Config::default();
+    assert_eq!(config.misc.initial_console_geometry(), None);
+
+    update_config(&mut config, "console_position", "+10+20", true);
+    assert_eq!(
+        config.misc.initial_console_geometry(),
+        Some("+10+20".to_string())
+    );
+
+    update_config(&mut config, "console_geometry", "800x200+0+0", true);
+    assert_eq!(
+        config.misc.initial_console_geometry(),
+        Some("800x200+0+0".to_string())
+    );
+
+    update_config(&mut config, "console_geometry", "800x200; rm", true); // ignored
+    assert_eq!(
+        config.misc.console_geometry,
+        Some("800x200+0+0".to_string())
+    );
+
+    update_config(&mut config, "console_geometry", "", true);
+    assert_eq!(
+        config.misc.initial_console_geometry(),
+        Some("+10+20".to_string())
+    );
+}
+
+#[test]
+fn test_session_timeout() {
+    let mut config = Config::default();
+    update_config(&mut config, "session_timeout", "30m", true);
+    assert_eq!(config.misc.session_timeout, "30m");
+    update_config(&mut config, "session_timeout", "1.5h", true);
+    assert_eq!(config.misc.session_timeout, "1.5h");
+    update_config(&mut config, "session_timeout", "30m; rm", true); // ignored
+    assert_eq!(config.misc.session_timeout, "1.5h");
+    update_config(&mut config, "session_timeout", "", true);
+    assert_eq!(config.misc.session_timeout, "");
+}
+
+#[test]
+fn test_remote_multiplexer() {
+    let mut config = Config::default();
+    assert_eq!(config.comms.remote_multiplexer, RemoteMultiplexer::None);
+    update_config(&mut config, "remote_multiplexer", "Tmux", true);
+    assert_eq!(config.comms.remote_multiplexer, RemoteMultiplexer::Tmux);
+    update_config(&mut config, "remote_multiplexer", "byobu", true); // ignored
+    assert_eq!(config.comms.remote_multiplexer, RemoteMultiplexer::Tmux);
+
+    update_config(&mut config, "remote_session_name", "work-1", true);
+    assert_eq!(config.comms.remote_session_name, "work-1");
+    update_config(&mut config, "remote_session_name", "a;b", true); // ignored
+    assert_eq!(config.comms.remote_session_name, "work-1");
+}
+
+#[test]
+fn test_sftp_batch_file() {
+    let mut config = Config::default();
+    update_config(&mut config, "sftp_batch_file", "/nonexistent/batch", true); // ignored
+    assert_eq!(config.comms.sftp_batch_file, None);
+    update_config(&mut config, "sftp_batch_file", "/etc/passwd", true);
+    assert_eq!(
+        config.comms.sftp_batch_file,
+        Some(PathBuf::from("/etc/passwd"))
+    );
+    update_config(&mut config, "sftp_batch_file", "/etc/hosts", false); // untrusted
+    assert_eq!(
+        config.comms.sftp_batch_file,
+        Some(PathBuf::from("/etc/passwd"))
+    );
+    update_config(&mut config, "sftp_batch_file", "", true);
+    assert_eq!(config.comms.sftp_batch_file, None);
+}
+
+#[test]
+fn test_untrusted_command_keys() {
+    let mut config = Config::default();
+    update_config(&mut config, "ssh", "/tmp/evil", false);
+    assert_eq!(config.comms.ssh, "ssh");
+    update_config(&mut config, "external_cluster_command", "/tmp/evil", false);
+    assert_eq!(config.misc.external_cluster_command, None);
+    for &(key, value) in &[
+        ("ssh_args", "-oProxyCommand=/tmp/evil"),
+        ("terminal_args", "-e /tmp/evil"),
+        ("terminal_font", "fixed; /tmp/evil"),
+        ("mosh_server", "/tmp/evil"),
+    ] {
+        update_config(&mut config, key, value, false);
+    }
+    assert_eq!(config.comms.ssh_args, "");
+    assert_eq!(config.terminal.args, None);
+    assert_eq!(config.terminal.font, Config::default().terminal.font);
+    assert_eq!(config.comms.mosh_server, "");
+    update_config(&mut config, "window_tiling", "no", false); // not a command key
+    assert!(!config.misc.window_tiling);
+    update_config(&mut config, "default_domain", "example.com", false);
+    assert_eq!(
+        config.misc.default_domain,
+        Some(String::from("example.com"))
+    );
+    update_config(&mut config, "mosh_port_range", "60000:60010", false);
+    assert_eq!(config.comms.mosh_port_range, "60000:60010");
+
+    update_config(&mut config, "ssh", "/usr/local/bin/ssh", true);
+    assert_eq!(config.comms.ssh, "/usr/local/bin/ssh");
+}
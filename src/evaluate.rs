@@ -3,7 +3,9 @@
 use std::ffi::OsStr;
 use std::process::Command;
 
+use crate::child;
 use crate::config;
+use crate::helper;
 use crate::host;
 use crate::wait_children;
 
@@ -16,27 +18,6 @@ pub fn evaluate_commands(evaluate: &str, config: &config::Config) {
     match host::parse(evaluate) {
         None => return,
         Some(host) => {
-            let user_life;
-            let user = match host.username {
-                None => "",
-                Some(user) => {
-                    user_life = format!("-l {}", user);
-                    &user_life
-                }
-            };
-
-            let port_life;
-            let port = match host.port {
-                None => "",
-                Some(port) => match config.comms.comms {
-                    config::CommsE::Telnet => port,
-                    _ => {
-                        port_life = format!("-p {}", port);
-                        &port_life
-                    }
-                },
-            };
-
             // 1) Testing terminal
             eprintln!("Testing terminal - running command:");
             eprintln!(
@@ -62,27 +43,7 @@ pub fn evaluate_commands(evaluate: &str, config: &config::Config) {
             }
 
             // 2) Testing comms
-            let (comms, comms_args, _, _) = config.get_script_args();
-            let mut c = String::with_capacity(256);
-            c += comms;
-            c += " ";
-            c += comms_args;
-            c += " ";
-            match config.comms.comms {
-                config::CommsE::Telnet => {
-                    c += host.hostname;
-                    c += " ";
-                    c += port;
-                }
-                _ => {
-                    c += user;
-                    c += " ";
-                    c += port;
-                    c += " ";
-                    c += host.hostname;
-                    c += " hostname ; echo Got hostname via ssh; sleep 2";
-                }
-            };
+            let c = comms_command(&host, config);
 
             eprintln!("\nTesting comms - running command:\nsh -c '{}'", c);
 
@@ -108,3 +69,82 @@ pub fn evaluate_commands(evaluate: &str, config: &config::Config) {
         }
     }
 }
+
+// What --evaluate runs for the comms test, e.g.
+//     ssh  -l bob -p 2222 web1 hostname ; echo Got hostname via ssh; sleep 2
+// The port is the one child.rs would use, see child::effective_port()
+fn comms_command(host: &host::Host, config: &config::Config) -> String {
+    let user = match host.username {
+        None => String::new(),
+        Some(user) => format!("-l {}", user),
+    };
+    let port = child::effective_port(host, config).unwrap_or_default();
+
+    let (comms, comms_args, _, _) = config.get_script_args();
+    let mut c = String::with_capacity(256);
+    if comms == config::CUSTOM_COMMS {
+        c += &helper::expand_custom_command(
+            comms_args,
+            host.hostname,
+            host.username.unwrap_or(""),
+            &port,
+        );
+    } else {
+        c += comms;
+        c += " ";
+        c += comms_args;
+        c += " ";
+        match config.comms.comms {
+            config::CommsE::Telnet => {
+                c += host.hostname;
+                c += " ";
+                c += &port;
+            }
+            _ => {
+                c += &user;
+                c += " ";
+                if !port.is_empty() {
+                    c += config.comms.port_opt();
+                    c += &port;
+                }
+                c += " ";
+                c += host.hostname;
+                c += " hostname ; echo Got hostname via ssh; sleep 2";
+            }
+        };
+    }
+    c
+}
+
+#[test]
+fn test_comms_command_port() {
+    let mut config = config::Config::default();
+    let command =
+        |config: &config::Config, host_str| comms_command(&host::parse(host_str).unwrap(), config);
+    config.misc.port = None;
+    assert_eq!(
+        command(&config, "web1"),
+        "ssh    web1 hostname ; echo Got hostname via ssh; sleep 2"
+    );
+    assert_eq!(
+        command(&config, "web1:2222"),
+        "ssh   -p 2222 web1 hostname ; echo Got hostname via ssh; sleep 2"
+    );
+    config.misc.port = Some("2200".to_string()); // --port or port=
+    assert_eq!(
+        command(&config, "web1"),
+        "ssh   -p 2200 web1 hostname ; echo Got hostname via ssh; sleep 2"
+    );
+    assert_eq!(
+        command(&config, "web1:2222"),
+        "ssh   -p 2222 web1 hostname ; echo Got hostname via ssh; sleep 2"
+    );
+
+    config.comms.comms = config::CommsE::Telnet;
+    config.misc.port = None;
+    assert_eq!(command(&config, "web1"), "telnet  web1 ");
+    assert_eq!(command(&config, "web1:2222"), "telnet  web1 2222");
+    config.misc.port = Some("2200".to_string());
+    assert_eq!(command(&config, "web1"), "telnet  web1 2200");
+    assert_eq!(command(&config, "web1:2222"), "telnet  web1 2222");
+}
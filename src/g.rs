@@ -15,40 +15,85 @@ use gtk::{
     Menu,
     MenuBar,
     MenuItem,
+    MenuItemExt,  // for set_label()
     MenuShellExt, // for menu.append()
     PolicyType,
     TextView,
     WidgetExt, // for show_all()
     Window,
 };
+use std::cell::{Cell, RefCell};
 use std::os::raw::c_uint;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use x11::keysym;
+use x11::xlib::ControlMask;
 
 use crate::app;
+use crate::cluster;
 use crate::config;
 use crate::er::Result;
 use crate::host::STRICT_GEOMETRY;
+use crate::macros;
 use crate::macros::VERSION_JUST_NUMBER;
+use crate::retile::RetileScope;
 use crate::server;
+use crate::title;
 use crate::tk2gtk;
 
 #[derive(Debug)]
 enum Console {
-    HiddenBeforeFirstDraw(Option<String>), // initial geometry from ~/.tcssh/config console_position=+123+123
+    HiddenBeforeFirstDraw(Option<String>), // initial geometry from console_geometry=800x200+0+0 or console_position=+123+123
     Hidden(i32, i32),                      // (x,y) of console before we hide it
     Shown,
 }
 
+// Send -> Special Keys, (label, keysym, modifiers)
+static SPECIAL_KEYS: [(&str, u32, u32); 25] = [
+    ("Ctrl-C", keysym::XK_c, ControlMask),
+    ("Ctrl-D", keysym::XK_d, ControlMask),
+    ("Ctrl-Z", keysym::XK_z, ControlMask),
+    ("Escape", keysym::XK_Escape, 0),
+    ("Tab", keysym::XK_Tab, 0),
+    ("Up", keysym::XK_Up, 0),
+    ("Down", keysym::XK_Down, 0),
+    ("Left", keysym::XK_Left, 0),
+    ("Right", keysym::XK_Right, 0),
+    ("PageUp", keysym::XK_Page_Up, 0),
+    ("PageDown", keysym::XK_Page_Down, 0),
+    ("Home", keysym::XK_Home, 0),
+    ("End", keysym::XK_End, 0),
+    ("F1", keysym::XK_F1, 0),
+    ("F2", keysym::XK_F2, 0),
+    ("F3", keysym::XK_F3, 0),
+    ("F4", keysym::XK_F4, 0),
+    ("F5", keysym::XK_F5, 0),
+    ("F6", keysym::XK_F6, 0),
+    ("F7", keysym::XK_F7, 0),
+    ("F8", keysym::XK_F8, 0),
+    ("F9", keysym::XK_F9, 0),
+    ("F10", keysym::XK_F10, 0),
+    ("F11", keysym::XK_F11, 0),
+    ("F12", keysym::XK_F12, 0),
+];
+
 #[derive(Debug)]
 pub struct GtkStuff {
     main_window: Window,
+    opacity: f64, // main_window's opacity when not flashing
     console: Console,
     menu_bar: MenuBar,
     pub hosts_menu: Menu,
+    hosts_add: MenuItem,
     send_menu: Menu,
     main_box: Box,
     text_entry_in_use: bool, // are we showing text_entry or history_window
     text_entry: Entry,
     history_window: gtk::ScrolledWindow,
+    history_view: TextView,
+    last_activity: Rc<Cell<Instant>>, // last keystroke or focus, for console_autohide_ms
+    hotkeys: RefCell<Vec<gtk::AccelGroup>>, // see bind_accelerator() and set_hotkeys()
+    hotkeys_on: Cell<bool>,
 }
 
 impl Console {
@@ -100,12 +145,15 @@ impl GtkStuff {
         main_window.hide();
         main_window.set_title("tcssh");
 
-        if config.tcssh.transparent {
+        let opacity = if config.tcssh.transparent {
             set_visual(&main_window, &None);
             main_window.connect_screen_changed(set_visual);
             main_window.set_app_paintable(true); // crucial for transparency
             main_window.set_opacity(config.tcssh.opacity);
-        }
+            config.tcssh.opacity
+        } else {
+            1.0
+        };
 
         let main_box = Box::new(gtk::Orientation::Vertical, 10);
         main_window.add(&main_box);
@@ -114,6 +162,7 @@ impl GtkStuff {
         main_box.pack_start(&menu_bar, false, false, 0);
 
         let hosts_menu = Menu::new();
+        let hosts_add = MenuItem::new_with_mnemonic("Add _Host(s) or Cluster(s)");
         let send_menu = Menu::new();
 
         let text_entry = Entry::new();
@@ -121,17 +170,18 @@ impl GtkStuff {
         text_entry.set_visibility(false); // So we don't see text in the entry box. (intended for password entry)
 
         let history_window = gtk::ScrolledWindow::new(None, None);
+        let text_view = TextView::new();
         {
             history_window.set_policy(PolicyType::Automatic, PolicyType::Automatic);
 
-            let height = i32::from(config.misc.history_height) * 16;
-            let width = i32::from(config.misc.history_width) * 9;
-            // *16 because we may be reading perl config file
+            // history_height/width are rows/cols because we may be reading perl config file
             // and Tk seemed to use font as metric, instead of pixels.
+            let (cell_w, cell_h) = font_cell_size(&text_view);
+            let height = i32::from(config.misc.history_height) * cell_h;
+            let width = i32::from(config.misc.history_width) * cell_w;
             history_window.set_min_content_height(height);
             history_window.set_min_content_width(width);
 
-            let text_view = TextView::new();
             history_window.add(&text_view);
         }
 
@@ -142,7 +192,18 @@ impl GtkStuff {
             main_box.add(&history_window);
         }
 
-        main_window.connect_delete_event(|_, _| {
+        let last_activity = Rc::new(Cell::new(Instant::now()));
+        let last_activity_clone = last_activity.clone();
+        main_window.connect_focus_in_event(move |_, _| {
+            last_activity_clone.set(Instant::now());
+            Inhibit(false)
+        });
+
+        let rapp_clone = rapp.clone();
+        main_window.connect_delete_event(move |window, _| {
+            if !quit_confirmed(&rapp_clone, window) {
+                return Inhibit(true); // keep the window, and the sessions
+            }
             gtk::main_quit();
             Inhibit(false)
         });
@@ -155,26 +216,29 @@ impl GtkStuff {
             let clipboard = gtk::Clipboard::get(&SELECTION_CLIPBOARD);
             text_entry.connect_paste_clipboard(move |_| {
                 if let Some(str) = clipboard.wait_for_text() {
-                    rapp_clone.borrow_mut().send_text(&str);
+                    rapp_clone.borrow_mut().send_paste(&str, false);
                 }
             });
         }
 
-        let console_position = match config.misc.console_position {
-            Some(ref s) => Some(s.clone()),
-            None => None,
-        };
+        let console_geometry = config.misc.initial_console_geometry();
 
         Ok(GtkStuff {
             main_window,
-            console: Console::HiddenBeforeFirstDraw(console_position),
+            opacity,
+            console: Console::HiddenBeforeFirstDraw(console_geometry),
             menu_bar,
             main_box,
             text_entry_in_use,
             text_entry,
             history_window,
+            history_view: text_view,
+            last_activity,
             hosts_menu,
+            hosts_add,
             send_menu,
+            hotkeys: RefCell::new(Vec::new()),
+            hotkeys_on: Cell::new(true),
         })
     }
 
@@ -186,6 +250,10 @@ impl GtkStuff {
 
         let file_menu = Menu::new();
         let file_history = MenuItem::new_with_mnemonic("Show _History");
+        let file_clear = MenuItem::new_with_mnemonic("_Clear history");
+        let file_reload = MenuItem::new_with_mnemonic("_Reload config");
+        let file_opacity = MenuItem::new_with_mnemonic("_Opacity");
+        let file_hotkeys = gtk::CheckMenuItem::new_with_mnemonic("Use Hot_keys");
         let file_quit = MenuItem::new_with_mnemonic("_Quit");
 
         let rapp_clone = rapp.clone();
@@ -196,48 +264,151 @@ impl GtkStuff {
         });
         self.bind_accelerator(&app.config.keymap.key_history, &file_history);
 
-        file_quit.connect_activate(|_| {
-            gtk::main_quit();
+        let rapp_clone = rapp.clone();
+        file_clear.connect_activate(move |_| {
+            if let Some(ref gtkstuff) = rapp_clone.borrow().gtkstuff {
+                gtkstuff.clear_history();
+            }
+        });
+        self.bind_accelerator(&app.config.keymap.key_clear, &file_clear);
+
+        let rapp_clone = rapp.clone();
+        file_reload.connect_activate(move |_| {
+            if let Err(e) = rapp_clone.borrow_mut().reload_config() {
+                eprintln!("Failed to reload config {}", e);
+            }
+        });
+        self.bind_accelerator(&app.config.keymap.key_reload_config, &file_reload);
+
+        let rapp_clone = rapp.clone();
+        let main_window_clone = self.main_window.clone();
+        file_quit.connect_activate(move |_| {
+            if quit_confirmed(&rapp_clone, &main_window_clone) {
+                gtk::main_quit();
+            }
         });
         self.bind_accelerator(&app.config.keymap.key_quit, &file_quit);
 
+        // Off, the hotkeys go to the servers like any other key, e.g. <Alt>q.
+        // Its own key stays bound, to turn them back on.
+        file_hotkeys.set_active(app.config.keymap.use_hotkeys);
+        let rapp_clone = rapp.clone();
+        file_hotkeys.connect_toggled(move |c| {
+            let mut app = rapp_clone.borrow_mut();
+            app.config.keymap.use_hotkeys = c.get_active();
+            if let Some(ref gtkstuff) = app.gtkstuff {
+                gtkstuff.set_hotkeys(c.get_active());
+            }
+        });
+        if let Some((key, modifier)) = parse_accel(&app.config.keymap.key_hotkeys_enable) {
+            let group = gtk::AccelGroup::new();
+            self.main_window.add_accel_group(&group);
+            file_hotkeys.add_accelerator(
+                "activate",
+                &group,
+                key,
+                modifier,
+                gtk::AccelFlags::VISIBLE,
+            );
+        }
+
         file_menu.append(&file_history);
+        file_menu.append(&file_clear);
+        file_menu.append(&file_reload);
+        file_menu.append(&file_opacity);
+        file_menu.append(&file_hotkeys);
         file_menu.append(&file_quit);
 
+        self.populate_opacity_menu(&file_opacity, app, rapp);
+
         file.set_submenu(Some(&file_menu));
 
         let hosts_retile = MenuItem::new_with_mnemonic("_Retile Windows");
         let hosts_raise = MenuItem::new_with_mnemonic("Ra_ise and Retile Windows");
+        let hosts_retile_active = MenuItem::new_with_mnemonic("Retile Acti_ve Windows");
+        let hosts_retile_inactive = MenuItem::new_with_mnemonic("Retile I_nactive Windows");
+        let hosts_minimize = MenuItem::new_with_mnemonic("_Minimize Windows");
+        let hosts_restore = MenuItem::new_with_mnemonic("R_estore Windows");
         let hosts_active = MenuItem::new_with_mnemonic("Set _all active");
         let hosts_inactive = MenuItem::new_with_mnemonic("Set _half inactive");
         let hosts_toggle = MenuItem::new_with_mnemonic("_Toggle active state");
+        let hosts_tag = MenuItem::new_with_mnemonic("Activate ta_g…");
+        let hosts_position = MenuItem::new_with_mnemonic("Activate by _position…");
+        let hosts_manage = MenuItem::new_with_mnemonic("Manage sessi_ons…");
         let hosts_close = MenuItem::new_with_mnemonic("_Close inactive sessions");
-        let hosts_add = MenuItem::new_with_mnemonic("Add _Host(s) or Cluster(s)");
         let hosts_re_add = MenuItem::new_with_mnemonic("Re-add closed _session(s)");
+        let hosts_save = MenuItem::new_with_mnemonic("Save as cl_uster…");
+        let hosts_save_layout = MenuItem::new_with_mnemonic("Save _layout…");
+        let hosts_load_layout = MenuItem::new_with_mnemonic("Loa_d layout…");
 
         self.hosts_menu.append(&hosts_retile);
         self.hosts_menu.append(&hosts_raise);
+        self.hosts_menu.append(&hosts_retile_active);
+        self.hosts_menu.append(&hosts_retile_inactive);
+        self.hosts_menu.append(&hosts_minimize);
+        self.hosts_menu.append(&hosts_restore);
         self.hosts_menu.append(&hosts_active);
         self.hosts_menu.append(&hosts_inactive);
         self.hosts_menu.append(&hosts_toggle);
+        self.hosts_menu.append(&hosts_tag);
+        self.hosts_menu.append(&hosts_position);
+        self.hosts_menu.append(&hosts_manage);
         self.hosts_menu.append(&hosts_close);
-        self.hosts_menu.append(&hosts_add);
+        self.hosts_menu.append(&self.hosts_add);
         self.hosts_menu.append(&hosts_re_add);
+        self.hosts_menu.append(&hosts_save);
+        self.hosts_menu.append(&hosts_save_layout);
+        self.hosts_menu.append(&hosts_load_layout);
 
         hosts.set_submenu(Some(&self.hosts_menu));
 
         let rapp_clone = rapp.clone();
         hosts_retile.connect_activate(move |_| {
-            rapp_clone.borrow_mut().retile_hosts(false, false).ok();
+            rapp_clone
+                .borrow_mut()
+                .retile_hosts(false, false, RetileScope::All)
+                .ok();
         });
         self.bind_accelerator(&app.config.keymap.key_retile_hosts, &hosts_retile);
 
         let rapp_clone = rapp.clone();
         hosts_raise.connect_activate(move |_| {
-            rapp_clone.borrow_mut().retile_hosts(false, true).ok();
+            rapp_clone
+                .borrow_mut()
+                .retile_hosts(false, true, RetileScope::All)
+                .ok();
         });
         self.bind_accelerator(&app.config.keymap.key_raise_hosts, &hosts_raise);
 
+        // Just those in use, or not, the others stay where they are.
+        let rapp_clone = rapp.clone();
+        hosts_retile_active.connect_activate(move |_| {
+            rapp_clone
+                .borrow_mut()
+                .retile_hosts(false, false, RetileScope::Active)
+                .ok();
+        });
+
+        let rapp_clone = rapp.clone();
+        hosts_retile_inactive.connect_activate(move |_| {
+            rapp_clone
+                .borrow_mut()
+                .retile_hosts(false, false, RetileScope::Inactive)
+                .ok();
+        });
+
+        let rapp_clone = rapp.clone();
+        hosts_minimize.connect_activate(move |_| {
+            rapp_clone.borrow().minimize_all();
+        });
+        self.bind_accelerator(&app.config.keymap.key_minimize_hosts, &hosts_minimize);
+
+        let rapp_clone = rapp.clone();
+        hosts_restore.connect_activate(move |_| {
+            rapp_clone.borrow().restore_all();
+        });
+        self.bind_accelerator(&app.config.keymap.key_restore_hosts, &hosts_restore);
+
         let rapp_clone = rapp.clone();
         hosts_active.connect_activate(move |_| {
             rapp_clone.borrow_mut().set_all_active();
@@ -253,18 +424,78 @@ impl GtkStuff {
             rapp_clone.borrow_mut().toggle_active_state();
         });
 
+        let rapp_clone = rapp.clone();
+        let main_window = self.main_window.clone();
+        hosts_tag.connect_activate(move |_| {
+            let tags = rapp_clone.borrow().cluster.list_tags();
+            if let Some(tag) = ask_for_tag(&main_window, &tags) {
+                rapp_clone.borrow_mut().set_active_by_tag(&tag);
+            }
+        });
+
+        let rapp_clone = rapp.clone();
+        let main_window = self.main_window.clone();
+        hosts_position.connect_activate(move |_| {
+            let cells: Vec<((u32, u32), String)> = rapp_clone
+                .borrow()
+                .servers
+                .iter()
+                .filter_map(|(server_key, s)| s.grid_position.map(|p| (p, server_key.clone())))
+                .collect();
+            if let Some((row, col)) = ask_for_grid_position(&main_window, &cells) {
+                rapp_clone.borrow_mut().activate_grid_position(row, col);
+            }
+        });
+
+        let rapp_clone = rapp.clone();
+        let main_window = self.main_window.clone();
+        hosts_manage.connect_activate(move |_| {
+            show_session_manager(&main_window, &rapp_clone);
+        });
+
         let rapp_clone = rapp.clone();
         hosts_close.connect_activate(move |_| {
             rapp_clone.borrow_mut().close_inactive_sessions();
         });
 
-        self.populate_add_hosts_or_clusters_menu(&hosts_add, app, rapp);
+        self.populate_add_hosts_or_clusters_menu(&self.hosts_add, app, rapp);
 
         let rapp_clone = rapp.clone();
         hosts_re_add.connect_activate(move |_| {
             rapp_clone.borrow_mut().re_add_closed_sessions(&rapp_clone);
         });
 
+        let rapp_clone = rapp.clone();
+        let main_window = self.main_window.clone();
+        hosts_save.connect_activate(move |_| {
+            if let Some(name) = ask_for_cluster_name(&main_window) {
+                if let Err(e) = rapp_clone.borrow_mut().save_as_cluster(&name) {
+                    eprintln!("Warn: Failed to save cluster {}. {}", name, e);
+                }
+            }
+        });
+
+        // Where each terminal is now, to put them back later without a retile.
+        let rapp_clone = rapp.clone();
+        let main_window = self.main_window.clone();
+        hosts_save_layout.connect_activate(move |_| {
+            if let Some(name) = ask_for_name(&main_window, "Save layout", "_Save", "layout name") {
+                if let Err(e) = rapp_clone.borrow_mut().save_layout(&name) {
+                    eprintln!("Warn: Failed to save layout {}. {}", name, e);
+                }
+            }
+        });
+
+        let rapp_clone = rapp.clone();
+        let main_window = self.main_window.clone();
+        hosts_load_layout.connect_activate(move |_| {
+            if let Some(name) = ask_for_name(&main_window, "Load layout", "_Load", "layout name") {
+                if let Err(e) = rapp_clone.borrow_mut().load_layout(&name) {
+                    eprintln!("Warn: Failed to load layout {}. {}", name, e);
+                }
+            }
+        });
+
         self.populate_send_menu(&send, app, rapp);
 
         let help_menu = Menu::new();
@@ -278,7 +509,8 @@ impl GtkStuff {
         help_about.connect_activate(move |_| {
             let p = gtk::AboutDialog::new();
             p.set_authors(&["Mark Nieweglowski"]);
-            p.set_comments(Some("Transparent Cluster SSH"));
+            let comments = format!("Transparent Cluster SSH\n\n{}", macros::build_info());
+            p.set_comments(Some(comments.as_str()));
             p.set_copyright(Some("2019 Mark Nieweglowski"));
             p.set_license_type(gtk::License::Gpl30);
             p.set_program_name(&"tcssh");
@@ -298,31 +530,37 @@ impl GtkStuff {
 
         self.main_window.show_all();
 
+        // The menus are all bound by now.
+        self.set_hotkeys(app.config.keymap.use_hotkeys);
+
         let text_entry = self.text_entry.clone();
-        let use_hotkeys = app.config.keymap.use_hotkeys;
+        let last_activity = self.last_activity.clone();
 
         let rapp_clone = rapp.clone();
         self.main_window.connect_key_press_event(move |_, event| {
             text_entry.get_buffer().set_text("");
+            last_activity.set(Instant::now());
 
             let keyval = event.get_keyval();
             let keycode = event.get_hardware_keycode();
             let state = event.get_state();
 
-            if use_hotkeys {
-                // TODO
-                // stuff.  like Alt? == hostname/username/quit
-            }
-
-            // ctrl-d with zero servers == exit program
-            if ModifierType::CONTROL_MASK == state
-                && 'd' as u32 == keyval
-                && rapp_clone.borrow().servers.is_empty()
-            {
-                gtk::main_quit();
-                // after gtk's main loop app calls its exit_prog()
-                // which terminates children, closes display, ends process.
-                return Inhibit(false);
+            // The hotkeys are accel groups, see set_hotkeys(), so they only
+            // get here (and on to the servers) when they're turned off.
+
+            // ctrl-d with zero servers == exit program, see ctrl_d_quits.
+            // Otherwise it's sent on below like any other key.
+            if ModifierType::CONTROL_MASK == state && 'd' as u32 == keyval {
+                let quits = {
+                    let app = rapp_clone.borrow();
+                    app.config.misc.ctrl_d_quits.quits(app.servers.len())
+                };
+                if quits {
+                    gtk::main_quit();
+                    // after gtk's main loop app calls its exit_prog()
+                    // which terminates children, closes display, ends process.
+                    return Inhibit(false);
+                }
             }
 
             // TODO if we're showing history. keypresses need to
@@ -359,11 +597,9 @@ impl GtkStuff {
         app: &app::App,
         rapp: &app::Rapp,
     ) {
-        let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
-        let dialog = gtk::Dialog::new_with_buttons(
-            Some(&"Add Host(s) or Cluster(s)"),
-            Some(&self.main_window),
-            flags,
+        let dialog = modal_dialog(
+            &self.main_window,
+            "Add Host(s) or Cluster(s)",
             &[
                 (&"_Add", gtk::ResponseType::Accept.into()),
                 (&"_Cancel", gtk::ResponseType::Reject.into()),
@@ -382,13 +618,48 @@ impl GtkStuff {
         //        }
         //     }
         // }
-        config::parse_ssh_config_and_add_hosts(&mut tags);
+        // The dialog is built once, so ~/.ssh/config is only read once per run.
+        if app.config.misc.use_ssh_config_hosts {
+            config::parse_ssh_config_and_add_hosts(&mut tags);
+        }
+
+        // The recently used tags go first, under a Recent heading, then everything.
+        // The headings are "" in tags, which are skipped when adding below.
+        let recent: Vec<String> = app
+            .recent_tags
+            .iter()
+            .filter(|tag| tags.contains(tag))
+            .cloned()
+            .collect();
+        let mut headings = Vec::new();
+        if !recent.is_empty() {
+            headings.push("Recent");
+            headings.push("All");
+            let mut with_recent = Vec::with_capacity(recent.len() + tags.len() + 2);
+            with_recent.push(String::new());
+            with_recent.extend(recent);
+            with_recent.push(String::new());
+            with_recent.append(&mut tags);
+            tags = with_recent;
+        }
+        let mut headings = headings.into_iter();
 
         let list_box = gtk::ListBox::new();
         list_box.set_selection_mode(gtk::SelectionMode::Multiple);
         list_box.set_activate_on_single_click(true);
         let mut max_len = 20;
         for tag in &tags {
+            if tag.is_empty() {
+                let heading = headings.next().unwrap_or("");
+                let label = gtk::Label::new(Some(heading));
+                label.set_markup(&format!("<b>{}</b>", heading));
+                label.set_halign(gtk::Align::Start);
+                let list_box_row = gtk::ListBoxRow::new();
+                list_box_row.set_sensitive(false); // not selectable
+                list_box_row.add(&label);
+                list_box.add(&list_box_row);
+                continue;
+            }
             let len = tag.len();
             if len > max_len {
                 max_len = len;
@@ -397,6 +668,7 @@ impl GtkStuff {
             label.set_justify(gtk::Justification::Left);
             label.set_halign(gtk::Align::Start);
             let list_box_row = gtk::ListBoxRow::new();
+            list_box_row.set_tooltip_text(Some(tag_tooltip(&app.cluster, tag).as_str()));
             list_box_row.add(&label);
             list_box.add(&list_box_row);
         }
@@ -414,12 +686,11 @@ impl GtkStuff {
         if n > app.config.menu.max_addhost_menu_cluster_items as usize {
             let scroll = gtk::ScrolledWindow::new(None, None);
             scroll.set_policy(PolicyType::Automatic, PolicyType::Automatic);
-            let height = i32::from(app.config.menu.max_addhost_menu_cluster_items) * 16;
+            let (_, cell_h) = font_cell_size(&list_box);
+            let height = i32::from(app.config.menu.max_addhost_menu_cluster_items) * cell_h;
             scroll.set_min_content_height(height);
             // perl cssh used Tk, and used height of max_addhost_menu_cluster_items
-            // but gtk seems to use pixels intead of items, so *16,
-            // Who knows what font gtk is using in this dialog, 16 for height seems
-            // as good a guess as any.
+            // but gtk seems to use pixels intead of items, so * the height of a line.
             scroll.add(&list_box);
             dialog_box.pack_start(&scroll, true, true, 0);
         } else {
@@ -445,8 +716,10 @@ impl GtkStuff {
                     if i >= 0 {
                         let i = i as usize;
                         if i < n {
+                            // a recent tag is listed twice, under Recent and All,
+                            // picking both still only opens it the once.
                             if let Some(tag) = tags.get(i) {
-                                if !tag.is_empty() {
+                                if !tag.is_empty() && !to_open.contains(tag) {
                                     to_open.push(tag.clone());
                                 }
                             }
@@ -477,27 +750,77 @@ impl GtkStuff {
         self.bind_accelerator(&app.config.keymap.key_addhost, &hosts_add);
     }
 
+    // Pop up the "Add Host(s) or Cluster(s)" dialog.
+    // The dialog borrows app when it's done, so wait until gtk's main loop
+    // is running (and nothing else has app borrowed) before activating it.
+    pub fn open_add_hosts_dialog(&self) {
+        let hosts_add = self.hosts_add.clone();
+        gtk::idle_add(move || {
+            WidgetExt::activate(&hosts_add); // emits the menu item's "activate" signal
+            gtk::Continue(false)
+        });
+    }
+
     fn bind_accelerator(&self, accel: &str, menu_item: &MenuItem) {
-        if accel.is_empty() {
-            return;
+        let (key, modifier) = match parse_accel(accel) {
+            Some(parsed) => parsed,
+            None => return,
+        };
+        let group = gtk::AccelGroup::new();
+        if self.hotkeys_on.get() {
+            self.main_window.add_accel_group(&group);
         }
-        let (mut key, mut modifier) = gtk::accelerator_parse(accel);
+        menu_item.add_accelerator("activate", &group, key, modifier, gtk::AccelFlags::VISIBLE);
+        self.hotkeys.borrow_mut().push(group);
+    }
 
-        if key == 0 {
-            // parse failures return 0, 0.
-            if let Some(accel) = tk2gtk::translate_accel(accel) {
-                let (k, m) = gtk::accelerator_parse(&accel);
-                key = k;
-                modifier = m;
-            }
-            if key == 0 {
-                eprintln!("Ignoring accelerator {} because it is not recognized by gtk::accelerator_parse()", accel);
-                return;
+    // use_hotkeys, live. Detaching the groups leaves the menus' accelerators as they were.
+    pub fn set_hotkeys(&self, on: bool) {
+        if self.hotkeys_on.replace(on) == on {
+            return;
+        }
+        for group in self.hotkeys.borrow().iter() {
+            if on {
+                self.main_window.add_accel_group(group);
+            } else {
+                self.main_window.remove_accel_group(group);
             }
         }
-        let group = gtk::AccelGroup::new();
-        self.main_window.add_accel_group(&group);
-        menu_item.add_accelerator("activate", &group, key, modifier, gtk::AccelFlags::VISIBLE);
+    }
+
+    // File -> Opacity, nudge the console's opacity live, or pick a preset.
+    fn populate_opacity_menu(&self, opacity: &MenuItem, app: &app::App, rapp: &app::Rapp) {
+        let opacity_menu = Menu::new();
+
+        let opacity_more = MenuItem::new_with_mnemonic("_More opaque");
+        let opacity_less = MenuItem::new_with_mnemonic("_Less opaque");
+        opacity_menu.append(&opacity_more);
+        opacity_menu.append(&opacity_less);
+
+        let rapp_clone = rapp.clone();
+        opacity_more.connect_activate(move |_| {
+            rapp_clone.borrow_mut().adjust_opacity(0.1);
+        });
+        self.bind_accelerator(&app.config.keymap.key_opacity_up, &opacity_more);
+
+        let rapp_clone = rapp.clone();
+        opacity_less.connect_activate(move |_| {
+            rapp_clone.borrow_mut().adjust_opacity(-0.1);
+        });
+        self.bind_accelerator(&app.config.keymap.key_opacity_down, &opacity_less);
+
+        for &percent in &[25, 50, 75, 100] {
+            let item = MenuItem::new_with_label(&format!("{}%", percent));
+            opacity_menu.append(&item);
+            let rapp_clone = rapp.clone();
+            item.connect_activate(move |_| {
+                rapp_clone
+                    .borrow_mut()
+                    .set_opacity(f64::from(percent) / 100.0);
+            });
+        }
+
+        opacity.set_submenu(Some(&opacity_menu));
     }
 
     fn populate_send_menu(&self, send: &MenuItem, app: &app::App, rapp: &app::Rapp) {
@@ -524,27 +847,87 @@ impl GtkStuff {
         self.send_menu.append(&send_test);
         self.send_menu.append(&send_random);
 
+        let send_special = MenuItem::new_with_mnemonic("_Special Keys");
+        let special_menu = Menu::new();
+        for &(label, sym, state) in SPECIAL_KEYS.iter() {
+            let item = MenuItem::new_with_label(label);
+            special_menu.append(&item);
+            let rapp_clone = rapp.clone();
+            item.connect_activate(move |_| {
+                rapp_clone.borrow_mut().send_keysym(label, sym, state);
+            });
+        }
+        send_special.set_submenu(Some(&special_menu));
+        self.send_menu.append(&send_special);
+
+        // One host, picked in the dialog, the others don't see it.
+        let send_to_host = MenuItem::new_with_mnemonic("To _Host…");
+        self.send_menu.append(&send_to_host);
+        let rapp_clone = rapp.clone();
+        let main_window = self.main_window.clone();
+        send_to_host.connect_activate(move |_| {
+            let server_keys: Vec<String> = {
+                let app = rapp_clone.borrow();
+                let order_by_given = app.config.misc.order_by_given;
+                server::in_order(&app.servers, order_by_given)
+                    .into_iter()
+                    .map(|(server_key, _)| server_key.clone())
+                    .collect()
+            };
+            if let Some((server_key, text)) = ask_for_command(&main_window, &server_keys) {
+                let mut app = rapp_clone.borrow_mut();
+                let wid = app.servers.get(&server_key).map(|server| server.wid);
+                if let Some(wid) = wid {
+                    app.send_text_to(wid, &text);
+                }
+            }
+        });
+
+        // The text entry's own paste (key_paste) just sends the clipboard,
+        // this one hits Return after it, e.g. to run a pasted command.
+        let send_paste_enter = MenuItem::new_with_mnemonic("Paste and _Enter");
+        self.send_menu.append(&send_paste_enter);
+        let rapp_clone = rapp.clone();
+        let clipboard = gtk::Clipboard::get(&SELECTION_CLIPBOARD);
+        send_paste_enter.connect_activate(move |_| {
+            if let Some(str) = clipboard.wait_for_text() {
+                rapp_clone.borrow_mut().send_paste_previewed(&str);
+            }
+        });
+        self.bind_accelerator(&app.config.keymap.key_paste_enter, &send_paste_enter);
+
+        // Only with a password_command, see secret.rs
+        if app.config.tcssh.password_command.is_some() {
+            let send_password = MenuItem::new_with_mnemonic("Pass_word");
+            self.send_menu.append(&send_password);
+            let rapp_clone = rapp.clone();
+            send_password.connect_activate(move |_| {
+                rapp_clone.borrow_mut().send_secret();
+            });
+            self.bind_accelerator(&app.config.keymap.key_send_secret, &send_password);
+        }
+
         let rapp_clone = rapp.clone();
         let text = app.config.macros.servername.clone();
         send_servername.connect_activate(move |_| {
-            rapp_clone.borrow_mut().send_text(&text);
+            rapp_clone.borrow_mut().send_text_previewed(&text);
         });
 
         let rapp_clone = rapp.clone();
         let text = app.config.macros.hostname.clone();
         send_hostname.connect_activate(move |_| {
-            rapp_clone.borrow_mut().send_text(&text);
+            rapp_clone.borrow_mut().send_text_previewed(&text);
         });
 
         let rapp_clone = rapp.clone();
         let text = app.config.macros.username.clone();
         send_username.connect_activate(move |_| {
-            rapp_clone.borrow_mut().send_text(&text);
+            rapp_clone.borrow_mut().send_text_previewed(&text);
         });
 
         let rapp_clone = rapp.clone();
         send_test.connect_activate(move |_| {
-            rapp_clone.borrow_mut().send_text(&"Lorem Ipsum");
+            rapp_clone.borrow_mut().send_text_previewed(&"Lorem Ipsum");
         });
 
         let rapp_clone = rapp.clone();
@@ -554,14 +937,116 @@ impl GtkStuff {
     }
 
     pub fn change_main_window_title(&self, app: &app::App) {
-        self.main_window.set_title(&format!(
-            "{} [{}]",
-            match app.config.dynamic.title {
+        // Only called when app.servers changes, so recomputing everything is cheap enough.
+        let format = match app.config.misc.title_format {
+            Some(ref format) => format.as_str(),
+            None => title::DEFAULT_TITLE_FORMAT,
+        };
+        let hostnames: Vec<&str> = if title::wants_hostnames(format) {
+            app.servers.values().map(|s| s.givenname.as_str()).collect()
+        } else {
+            Vec::new()
+        };
+        let info = title::TitleInfo {
+            title: match app.config.dynamic.title {
                 Some(ref title) => title,
                 None => "",
             },
-            app.get_n_servers()
-        ));
+            n_servers: app.get_n_servers(),
+            n_active: app.servers.values().filter(|s| s.active).count(),
+            hostnames: &hostnames,
+        };
+        self.main_window
+            .set_title(&title::format_title(format, &info));
+    }
+
+    // Briefly change the console's opacity, so there's some feedback that
+    // pasted text was sent, (the text entry hides what it is sent).
+    pub fn flash_send_indicator(&self, n_targets: usize) {
+        if n_targets == 0 {
+            return;
+        }
+        let flash = if self.opacity < 0.75 { 1.0 } else { 0.5 };
+        self.main_window.set_opacity(flash);
+
+        let main_window = self.main_window.clone();
+        let opacity = self.opacity;
+        gtk::timeout_add(150, move || {
+            main_window.set_opacity(opacity);
+            gtk::Continue(false)
+        });
+    }
+
+    // preview_send, the preview goes in the history. If that isn't shown, say where it went.
+    pub fn show_preview(&self, preview: &str) {
+        if let Some(buffer) = self.history_view.get_buffer() {
+            let mut end = buffer.get_end_iter();
+            buffer.insert(
+                &mut end,
+                &format!("Preview, activate again to send\n{}", preview),
+            );
+        }
+        if self.text_entry_in_use {
+            self.text_entry.set_placeholder_text(Some(
+                "Previewed in File -> Show History, activate again to send",
+            ));
+        }
+    }
+
+    pub fn clear_preview(&self) {
+        self.text_entry.set_placeholder_text(Some(""));
+    }
+
+    // console_autohide_ms, grab key_show_console and check on the console every so often.
+    pub fn setup_autohide(&self, app: &app::App, rapp: &app::Rapp) {
+        let idle_ms = app.config.tcssh.console_autohide_ms;
+        if idle_ms == 0 {
+            return;
+        }
+        if let Some((key, modifier)) = parse_accel(&app.config.keymap.key_show_console) {
+            // Only the core X modifiers, so e.g. <Super> needs to be given as <Mod4>
+            if let Err(e) = app.xdisplay.grab_key(key, modifier.bits() & 0xff) {
+                eprintln!("Warn: Ignoring config value for key_show_console. {}", e);
+            }
+        }
+        let rapp = rapp.clone();
+        gtk::timeout_add(250, move || {
+            // dialogs spin gtk's main loop too, so app may be borrowed, try again later.
+            if let Ok(ref mut app) = rapp.try_borrow_mut() {
+                let show =
+                    app.xdisplay.grabbed_key_pressed() || app.xdisplay.pointer_at_screen_edge();
+                if let Some(ref mut gtkstuff) = app.gtkstuff {
+                    gtkstuff.autohide(idle_ms, show);
+                }
+            }
+            gtk::Continue(true)
+        });
+    }
+
+    // Hide the console after idle_ms without keystrokes, or show it again.
+    // Keystrokes are only broadcast from the console, so it never hides while it has the focus,
+    // and once hidden nothing is sent until it's back, best paired with key_show_console.
+    fn autohide(&mut self, idle_ms: u64, show: bool) {
+        match self.console {
+            Console::Hidden(_, _) if show => {
+                self.show_main_window();
+                self.last_activity.set(Instant::now());
+            }
+            Console::Shown if show || self.main_window.is_active() => {
+                self.last_activity.set(Instant::now());
+            }
+            Console::Shown => {
+                if self.last_activity.get().elapsed() >= Duration::from_millis(idle_ms) {
+                    self.hide_main_window();
+                }
+            }
+            _ => {}
+        }
+    }
+
+    pub fn set_opacity(&mut self, opacity: f64) {
+        self.opacity = opacity;
+        self.main_window.set_opacity(opacity);
     }
 
     pub fn hide_main_window(&mut self) {
@@ -591,18 +1076,27 @@ impl GtkStuff {
     }
 
     pub fn build_hosts_menu(&self, app: &mut app::App, rapp: &app::Rapp) {
-        for (ref server_key, ref mut server) in app.servers.iter_mut() {
+        let order_by_given = app.config.misc.order_by_given;
+        for (server_key, server) in server::in_order_mut(&mut app.servers, order_by_given) {
             self.build_host_menu(server_key, server, rapp);
         }
         self.change_main_window_title(app);
     }
 
     pub fn build_host_menu(&self, server_key: &str, server: &mut server::Server, rapp: &app::Rapp) {
-        if server.menu_item.is_none() {
+        if let Some(ref menu_item) = server.menu_item {
+            // Greyed out by keep_dead_in_menu, and handed back by revive_menu_items().
+            if !menu_item.get_sensitive() {
+                menu_item.set_label(server_key);
+                menu_item.set_sensitive(true);
+                menu_item.set_active(server.active);
+            }
+        } else {
             let menu_item = gtk::CheckMenuItem::new_with_label(server_key);
-            menu_item.set_active(true);
-            let server_key = server_key.to_string(); // copy string so closure can own it.
-            let rapp = rapp.clone();
+            menu_item.set_active(server.active); // see initial_active
+            let rapp_clone = rapp.clone();
+            // The callback looks up its server via this menu_item, since
+            // keep_dead_in_menu may hand it to a re-added server (new key, new wid).
             menu_item.connect_toggled(move |c| {
                 // If this host is clicked in the hosts_menu,
                 // then we can borrow rapp (because caller is gtk directly to us).
@@ -614,18 +1108,28 @@ impl GtkStuff {
                 // This sounds like a crude assumption, but KISS, gtk is single threaded,
                 // and I don't want to wrap the bool in a Rc<RefCell<>> so it can be
                 // referenced in this static callback.
-                if let Ok(ref mut app) = rapp.try_borrow_mut() {
-                    if let Some(ref mut server) = app.servers.get_mut(&server_key) {
+                if let Ok(ref mut app) = rapp_clone.try_borrow_mut() {
+                    if let Some(server) = server::find_by_menu_item_mut(&mut app.servers, c) {
                         server.active = c.get_active();
                     }
+                    app.forget_preview();
                 }
             });
+
             self.hosts_menu.append(&menu_item);
             menu_item.show_all();
             server.menu_item = Some(menu_item);
         }
     }
 
+    // Empties both, whichever is shown, and leaves it shown.
+    fn clear_history(&self) {
+        if let Some(buffer) = self.history_view.get_buffer() {
+            buffer.set_text("");
+        }
+        self.text_entry.set_text("");
+    }
+
     fn toggle_history(&mut self) {
         if self.text_entry_in_use {
             self.text_entry_in_use = false;
@@ -640,6 +1144,339 @@ impl GtkStuff {
     }
 }
 
+// A dialog for the ask_for_*() functions, show_session_manager() and Add Host(s).
+// Their dialog.run() spins gtk's main loop until a button is pressed, and the
+// menu callbacks run from there borrow rapp, so nothing may hold a rapp borrow
+// across it. Collect what the dialog needs first, and borrow again after.
+fn modal_dialog(window: &Window, title: &str, buttons: &[(&str, i32)]) -> gtk::Dialog {
+    let flags = gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT;
+    gtk::Dialog::new_with_buttons(Some(title), Some(window), flags, buttons)
+}
+
+// Send -> To Host…, pops up a dialog asking for a host and a command to send it.
+// Returns None if cancelled or left empty.
+// The command is sent with a newline, so it runs.
+fn ask_for_command(window: &Window, server_keys: &[String]) -> Option<(String, String)> {
+    let dialog = modal_dialog(
+        window,
+        "Send command to host",
+        &[
+            (&"_Send", gtk::ResponseType::Accept.into()),
+            (&"_Cancel", gtk::ResponseType::Reject.into()),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept.into());
+
+    let hosts = gtk::ComboBoxText::new();
+    for server_key in server_keys {
+        hosts.append(Some(server_key.as_str()), server_key);
+    }
+    if let Some(first) = server_keys.first() {
+        hosts.set_active_id(Some(first.as_str()));
+    }
+    let text_entry = Entry::new();
+    text_entry.set_width_chars(40);
+    text_entry.set_activates_default(true); // so Enter sends
+    let content_area = dialog.get_content_area();
+    content_area.pack_start(&hosts, false, false, 0);
+    content_area.pack_start(&text_entry, true, true, 0);
+    content_area.show_all();
+
+    let button_pressed = dialog.run();
+    let server_key = hosts.get_active_id();
+    let text = text_entry.get_text();
+    dialog.destroy();
+    if button_pressed != gtk::ResponseType::Accept.into() {
+        return None;
+    }
+    match (server_key, text) {
+        (Some(server_key), Some(ref gstring)) if !gstring.as_str().is_empty() => Some((
+            server_key.as_str().to_string(),
+            format!("{}\n", gstring.as_str()),
+        )),
+        _ => None,
+    }
+}
+
+// Width and height of a character in widget's font, for sizing things
+// configured in rows and columns. Measured via pango, so a large font or
+// Xft.dpi is accounted for. These are gtk's logical pixels, which gtk
+// already multiplies by the scale factor on HiDPI, so we don't.
+// Falls back to the 9x16 we used to assume.
+fn font_cell_size<W: WidgetExt>(widget: &W) -> (i32, i32) {
+    const SAMPLE: &str = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    if let Some(layout) = widget.create_pango_layout(Some(SAMPLE)) {
+        let (w, h) = layout.get_pixel_size();
+        if w > 0 && h > 0 {
+            return ((w + SAMPLE.len() as i32 - 1) / SAMPLE.len() as i32, h);
+        }
+    }
+    (9, 16)
+}
+
+// What a tag in the Add Host(s) dialog expands to. Those from ~/.ssh/config
+// aren't tags, so they're just themselves.
+fn tag_tooltip(cluster: &cluster::Cluster, tag: &str) -> String {
+    const MAX_CHARS: usize = 200;
+    let text = match cluster.get_tag(tag) {
+        Some(hosts) => hosts.join(" "),
+        None => return tag.to_string(),
+    };
+    match text.char_indices().nth(MAX_CHARS) {
+        Some((i, _)) => format!("{}…", &text[..i]),
+        None => text,
+    }
+}
+
+// Pick one tag (from the clusters or tags files) for Hosts -> Activate tag…
+fn ask_for_tag(window: &Window, tags: &[String]) -> Option<String> {
+    let dialog = modal_dialog(
+        window,
+        "Activate tag",
+        &[
+            (&"_Activate", gtk::ResponseType::Accept.into()),
+            (&"_Cancel", gtk::ResponseType::Reject.into()),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept.into());
+
+    let list_box = gtk::ListBox::new();
+    list_box.set_selection_mode(gtk::SelectionMode::Single);
+    for tag in tags {
+        let label = gtk::Label::new(Some(tag.as_str()));
+        label.set_halign(gtk::Align::Start);
+        let list_box_row = gtk::ListBoxRow::new();
+        list_box_row.add(&label);
+        list_box.add(&list_box_row);
+    }
+    if tags.is_empty() {
+        list_box.add(&gtk::Label::new(Some("No tags in the clusters or tags files")));
+    }
+    let content_area = dialog.get_content_area();
+    content_area.pack_start(&list_box, true, true, 0);
+    content_area.show_all();
+
+    let button_pressed = dialog.run();
+    let selected = list_box.get_selected_row().map(|row| row.get_index());
+    dialog.destroy();
+    if button_pressed != gtk::ResponseType::Accept.into() {
+        return None;
+    }
+    match selected {
+        Some(i) if i >= 0 => tags.get(i as usize).cloned(),
+        _ => None,
+    }
+}
+
+// Hosts -> Activate by position…, a button per terminal, laid out like the retiled grid.
+fn ask_for_grid_position(window: &Window, cells: &[((u32, u32), String)]) -> Option<(u32, u32)> {
+    let dialog = modal_dialog(
+        window,
+        "Activate by position",
+        &[(&"_Cancel", gtk::ResponseType::Reject.into())],
+    );
+
+    let chosen = Rc::new(Cell::new(None));
+    let grid = gtk::Grid::new();
+    grid.set_row_spacing(4);
+    grid.set_column_spacing(4);
+    for ((row, col), server_key) in cells {
+        let button = gtk::Button::new_with_label(server_key);
+        let position = (*row, *col);
+        let chosen = chosen.clone();
+        let dialog = dialog.clone();
+        button.connect_clicked(move |_| {
+            chosen.set(Some(position));
+            dialog.response(gtk::ResponseType::Accept.into());
+        });
+        grid.attach(&button, *col as i32, *row as i32, 1, 1);
+    }
+    let content_area = dialog.get_content_area();
+    if cells.is_empty() {
+        content_area.pack_start(
+            &gtk::Label::new(Some("Nothing tiled, try Hosts -> Retile Windows")),
+            true,
+            true,
+            0,
+        );
+    }
+    content_area.pack_start(&grid, true, true, 0);
+    content_area.show_all();
+
+    let button_pressed = dialog.run();
+    dialog.destroy();
+    if button_pressed != gtk::ResponseType::Accept.into() {
+        return None;
+    }
+    chosen.get()
+}
+
+// gtk::accelerator_parse(), falling back to the Tk style of perl cssh config files.
+fn parse_accel(accel: &str) -> Option<(u32, ModifierType)> {
+    if accel.is_empty() {
+        return None;
+    }
+    let (mut key, mut modifier) = gtk::accelerator_parse(accel);
+
+    if key == 0 {
+        // parse failures return 0, 0.
+        if let Some(accel) = tk2gtk::translate_accel(accel) {
+            let (k, m) = gtk::accelerator_parse(&accel);
+            key = k;
+            modifier = m;
+        }
+        if key == 0 {
+            eprintln!(
+                "Ignoring accelerator {} because it is not recognized by gtk::accelerator_parse()",
+                accel
+            );
+            return None;
+        }
+    }
+    Some((key, modifier))
+}
+
+// Hosts -> Save as cluster…, the tag to save the open sessions under.
+fn ask_for_cluster_name(window: &Window) -> Option<String> {
+    ask_for_name(window, "Save as cluster", "_Save", "cluster name")
+}
+
+// Hosts -> Save/Load layout… (and Save as cluster…), the name to save or load under.
+fn ask_for_name(window: &Window, title: &str, button: &str, placeholder: &str) -> Option<String> {
+    let dialog = modal_dialog(
+        window,
+        title,
+        &[
+            (button, gtk::ResponseType::Accept.into()),
+            (&"_Cancel", gtk::ResponseType::Reject.into()),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept.into());
+
+    let text_entry = Entry::new();
+    text_entry.set_activates_default(true);
+    text_entry.set_placeholder_text(Some(placeholder));
+    let content_area = dialog.get_content_area();
+    content_area.pack_start(&text_entry, true, true, 0);
+    content_area.show_all();
+
+    let button_pressed = dialog.run();
+    let name = text_entry
+        .get_text()
+        .map(|gstring| gstring.as_str().trim().to_string());
+    dialog.destroy();
+    if button_pressed != gtk::ResponseType::Accept.into() {
+        return None;
+    }
+    name.filter(|name| !name.is_empty())
+}
+
+// Hosts -> Manage sessions…, select several open sessions, then (de)activate or close them.
+fn show_session_manager(window: &Window, rapp: &app::Rapp) {
+    let (sessions, max_items): (Vec<(String, bool)>, u8) = {
+        let app = rapp.borrow();
+        let sessions = app
+            .servers
+            .iter()
+            .map(|(server_key, server)| (server_key.clone(), server.active))
+            .collect();
+        (sessions, app.config.menu.max_addhost_menu_cluster_items)
+    };
+
+    let dialog = modal_dialog(
+        window,
+        "Manage sessions",
+        &[
+            (&"_Activate", gtk::ResponseType::Accept.into()),
+            (&"_Deactivate", gtk::ResponseType::Reject.into()),
+            (&"C_lose", gtk::ResponseType::Apply.into()),
+            (&"_Cancel", gtk::ResponseType::Cancel.into()),
+        ],
+    );
+
+    let list_box = gtk::ListBox::new();
+    list_box.set_selection_mode(gtk::SelectionMode::Multiple);
+    list_box.set_activate_on_single_click(true);
+    for (server_key, active) in &sessions {
+        // ✓ marks the active ones
+        let text = format!("{} {}", if *active { "\u{2713}" } else { " " }, server_key);
+        let label = gtk::Label::new(Some(text.as_str()));
+        label.set_halign(gtk::Align::Start);
+        let list_box_row = gtk::ListBoxRow::new();
+        list_box_row.add(&label);
+        list_box.add(&list_box_row);
+    }
+    if sessions.is_empty() {
+        list_box.add(&gtk::Label::new(Some("No open sessions")));
+    }
+    let content_area = dialog.get_content_area();
+    if sessions.len() > max_items as usize {
+        let scroll = gtk::ScrolledWindow::new(None, None);
+        scroll.set_policy(PolicyType::Automatic, PolicyType::Automatic);
+        let (_, cell_h) = font_cell_size(&list_box);
+        scroll.set_min_content_height(i32::from(max_items) * cell_h);
+        scroll.add(&list_box);
+        content_area.pack_start(&scroll, true, true, 0);
+    } else {
+        content_area.pack_start(&list_box, true, true, 0);
+    }
+    content_area.show_all();
+
+    let button_pressed = dialog.run();
+    // rows are in sessions' order, which is app.servers' order.
+    let selected: Vec<&str> = list_box
+        .get_selected_rows()
+        .iter()
+        .map(|row| row.get_index())
+        .filter(|i| *i >= 0)
+        .filter_map(|i| sessions.get(i as usize))
+        .map(|(server_key, _)| server_key.as_str())
+        .collect();
+    dialog.destroy();
+
+    let mut app = rapp.borrow_mut();
+    app.forget_preview();
+    for server_key in selected {
+        // it may have closed while the dialog was up.
+        if let Some(server) = app.servers.get_mut(server_key) {
+            if button_pressed == gtk::ResponseType::Accept.into() {
+                server.set_active(true);
+            } else if button_pressed == gtk::ResponseType::Reject.into() {
+                server.set_active(false);
+            } else if button_pressed == gtk::ResponseType::Apply.into() {
+                server.terminate_host();
+            }
+        }
+    }
+}
+
+// With confirm_quit_with_sessions=yes, ask before killing more than one
+// active session. Returns true if it's ok to quit.
+// ctrl-d with zero servers doesn't come through here, nothing to lose there.
+fn quit_confirmed(rapp: &app::Rapp, window: &Window) -> bool {
+    let n_active = {
+        let app = rapp.borrow();
+        if !app.config.misc.confirm_quit_with_sessions {
+            return true;
+        }
+        app.servers.values().filter(|s| s.active).count()
+    }; // not held across dialog.run(), see modal_dialog()
+    if n_active < 2 {
+        return true;
+    }
+
+    let dialog = gtk::MessageDialog::new(
+        Some(window),
+        gtk::DialogFlags::MODAL | gtk::DialogFlags::DESTROY_WITH_PARENT,
+        gtk::MessageType::Question,
+        gtk::ButtonsType::OkCancel,
+        &format!("Quit and close {} active sessions?", n_active),
+    );
+    let button_pressed = dialog.run();
+    dialog.destroy();
+    button_pressed == gtk::ResponseType::Ok.into()
+}
+
 fn set_visual(window: &Window, _screen: &Option<Screen>) {
     // stolen from gtk-rs examples
     if let Some(screen) = window.get_screen() {
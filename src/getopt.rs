@@ -1,18 +1,39 @@
-use regex::Regex;
 use std::borrow::Cow;
 use std::path::PathBuf;
+use std::str::FromStr;
 use structopt::StructOpt;
 
+use crate::child;
 use crate::config;
 use crate::er::Result;
+use crate::host::STRICT_GEOMETRY;
 
 #[derive(Debug, StructOpt)]
 #[structopt(name = "Getopt", rename_all = "kebab-case")]
 pub struct Getopt {
+    /// Only the first terminal opened starts out active (receiving keystrokes).
+    #[structopt(long = "active-first")]
+    active_first: bool,
+
     /// Number of seconds to wait before closing finished terminal windows.
     #[structopt(short = "K", long = "autoclose")]
     auto_close: Option<String>, // "man sleep" accepts floats and optional suffix s m h d
 
+    /// Open the terminals (and run any --action in them), then exit without a console.
+    ///
+    /// The terminals live on by themselves, but without the console there is nothing
+    /// to broadcast keystrokes to them, so this is for fire-and-forget, e.g. with -a.
+    #[structopt(long = "batch", conflicts_with = "pick")]
+    pub batch: bool,
+
+    /// If the console can't start, because gtk fails to initialize, still open the
+    /// terminals like --batch does and exit, instead of giving up.
+    ///
+    /// Without the console there is nothing to broadcast keystrokes to the terminals,
+    /// each is on its own.
+    #[structopt(long = "no-console")]
+    pub no_console: bool,
+
     /// Use supplied file as additional cluster file.
     ///
     /// Accepts csv of files "--cluster-file file1,file2,file3"
@@ -23,12 +44,43 @@ pub struct Getopt {
     #[structopt(short = "c", long = "cluster-file")]
     cluster_file: Option<String>,
 
+    /// Terminal class (xterm -class) e.g. for window manager rules. Overrides terminal_class.
+    #[structopt(long = "class")]
+    class: Option<String>,
+
+    /// Color each terminal's background (or foreground with --light) based on its hostname.
+    /// Overrides terminal_colorize from the config file.
+    #[structopt(long = "color")]
+    color: bool,
+
+    /// Do not color the terminals, e.g. for screenshots.
+    #[structopt(long = "no-color", conflicts_with = "color")]
+    no_color: bool,
+
+    /// Initial geometry of the console window, e.g. "800x200+0+0".
+    /// Overrides console_geometry and console_position from the config file.
+    #[structopt(long = "console-geometry")]
+    console_geometry: Option<String>,
+
+    /// Which comms to use, instead of going by how we were invoked (tcssh, tctel, etc).
+    /// One of console, custom, mosh, rsh, sftp, ssh, telnet.
+    #[structopt(long = "comms")]
+    comms: Option<String>,
+
+    /// The command for --comms custom, %h is replaced with the host, %u user and %p port.
+    ///
+    /// e.g. --comms custom --comms-command 'kubectl exec -it %h -- bash'
+    #[structopt(long = "comms-command")]
+    comms_command: Option<String>,
+
     // perl changed the CLI args available based upon $0 aka argv[0] (aka how executable is invoked)
     // that's a bit too dynamic for us.  So allow all and add validation to prevent nonsense.
     // available for ssh rsh, but not telnet or console
     /// Run the command in each session, e.g. "-a 'ping 1.1.1.1'" to run ping in each xterm
-    #[structopt(short = "a", long = "action")]
-    command: Option<String>,
+    ///
+    /// Repeat it to run several in order, e.g. -a 'cd /var/log' -a 'tail -f syslog'
+    #[structopt(short = "a", long = "action", raw(number_of_values = "1"))]
+    command: Vec<String>,
 
     /// Use supplied file the configuration file.
     /// Defaults is $CONFIG_DIR/config
@@ -36,16 +88,37 @@ pub struct Getopt {
     #[structopt(short = "C", long = "config-file")]
     config_file: Option<PathBuf>,
 
+    /// Dark terminals, colored text on black. Overrides terminal_bg_style from the config file.
+    #[structopt(long = "dark")]
+    dark: bool,
+
+    /// Light terminals, black text on a colored background.
+    #[structopt(long = "light", conflicts_with = "dark")]
+    light: bool,
+
     // perl cssh allowed '--debug level' and multiple --debug options without args.
-    // We cannot mimic that, and only have one level of debug.. so make it bool
-    /// Debug
-    #[structopt(long = "debug")]
-    pub debug: bool,
+    // We take categories instead, and a number means all of them.
+    // A bare --debug is turned into --debug=all by from_env_args()
+    /// Debug output to stderr. Any of x, retile, cluster, dns, send, fork or all.
+    ///
+    /// e.g. --debug=x,retile or --debug=dns --debug=fork  A bare --debug is --debug=all
+    #[structopt(long = "debug", raw(number_of_values = "1", require_equals = "true"))]
+    pub debug: Vec<String>,
 
     /// Dump the default configuration in the format used by ~/.tcssh/config
     #[structopt(short = "d", long = "dump-config")]
     pub dump_config: bool,
 
+    /// Like --dump-config, but in sections with a comment for each key, to start a config file.
+    #[structopt(long = "dump-config-commented", conflicts_with = "dump_config")]
+    pub dump_config_commented: bool,
+
+    /// How pasted text is mapped to key presses. One of utf8 (default), latin1, ascii.
+    ///
+    /// With latin1 or ascii, characters outside that range are dropped (with a warning).
+    #[structopt(long = "encoding")]
+    encoding: Option<String>,
+
     /// Display and evaluate the terminal and connection arguments to display any potential errors.
     /// The <hostname> is required to aid the evaluation. [user@]<host>[:port]
     ///
@@ -57,12 +130,29 @@ pub struct Getopt {
     #[structopt(short = "e", long = "evaluate")]
     pub evaluate: Option<String>,
 
+    /// Print the command which would be run to open a terminal for this one host, then exit.
+    ///
+    /// e.g. "tcssh --print-command bob@web1:2222" shows the xterm, its --helper, and what
+    /// that passes to ssh, as assembled from the config file and any other --args.
+    #[structopt(long = "print-command")]
+    pub print_command: Option<String>,
+
+    /// Print some usage examples, then exit.
+    #[structopt(long = "examples")]
+    pub examples: bool,
+
     /// Specify the font to use in the terminal windows. Use standard X font notation such as "5x8".
     #[structopt(short = "f", long = "font")]
-    font: Option<String>,
+    pub font: Option<String>,
 
     pub hosts: Vec<String>,
 
+    /// Everything after -- is passed on to ssh (or the other comms), after its own args.
+    ///
+    /// e.g. "tcssh web1 web2 -- -o StrictHostKeyChecking=accept-new"
+    #[structopt(raw(last = "true"))]
+    pub passthrough: Vec<String>,
+
     // perl's GetOpt allows optional arguments.
     // so    'cssh --list'     lists available tags.
     // while 'cssh --list foo' lists the expansion of the tag 'foo'
@@ -76,14 +166,43 @@ pub struct Getopt {
     #[structopt(short = "L", long = "list")]
     pub list: Option<String>,
 
+    /// If no hosts are given (and there is no "default" cluster) then pop up the
+    /// "Add Host(s) or Cluster(s)" dialog instead of an empty console.
+    #[structopt(long = "pick")]
+    pick: bool,
+
     /// Specify an alternate port for connections.
     #[structopt(short = "p", long = "port")]
     port: Option<u16>,
 
+    /// Also read $CONFIG_DIR/profiles/<name>.config, after the config file,
+    /// so its values win. e.g. --profile work for profiles/work.config
+    #[structopt(long = "profile")]
+    profile: Option<String>,
+
+    /// Before opening any terminals, try a TCP connect to each host's port (default 22),
+    /// and skip the hosts which don't answer within probe_timeout_ms.
+    #[structopt(long = "probe")]
+    pub probe: bool,
+
+    /// Print a line to stdout as each session comes up (or fails to).
+    ///
+    /// e.g. "OK web1 pid=1234 wid=56623107" or "FAIL web2 Expected PID:WINDOWID"
+    #[structopt(long = "progress")]
+    pub progress: bool,
+
     /// Do not output extra text when using some options
     #[structopt(short = "Q", long = "quiet")]
     pub quiet: bool,
 
+    /// Seed for --shuffle, so the same hosts are opened in the same order. Defaults to the time.
+    #[structopt(long = "seed", requires = "shuffle")]
+    pub seed: Option<u32>,
+
+    /// Open the hosts in a random order, e.g. to not always hit the first one first.
+    #[structopt(long = "shuffle")]
+    pub shuffle: bool,
+
     /// Show history within console window.
     #[structopt(short = "s", long = "show-history")]
     show_history: bool,
@@ -113,14 +232,47 @@ pub struct Getopt {
     #[structopt(short = "t", long = "term-args")]
     term_args: Option<String>,
 
+    /// Columns x rows of each terminal, e.g. --term-size 100x30 for a denser run.
+    /// Overrides terminal_size from the config file.
+    #[structopt(long = "term-size")]
+    term_size: Option<String>,
+
     /// Toggle window tiling (overriding the config file).
+    ///
+    /// Whether that ends up on or off depends on window_tiling in the config file,
+    /// so prefer --force-tile or --no-tile.
     #[structopt(short = "g", long = "tile")]
     tile: bool,
 
+    /// Tile the windows, whatever window_tiling in the config file says.
+    #[structopt(
+        long = "force-tile",
+        raw(conflicts_with_all = r#"&["tile", "no_tile"]"#)
+    )]
+    force_tile: bool,
+
+    /// Do not tile the windows, whatever window_tiling in the config file says.
+    #[structopt(long = "no-tile", conflicts_with = "tile")]
+    no_tile: bool,
+
     /// Specify the initial part of the title used in the console and client windows.
     #[structopt(short = "T", long = "title")]
     title: Option<String>,
 
+    /// Format of the console's title. %t title, %n number of servers, %a number active, %H hostnames.
+    ///
+    /// e.g. --title-format '%t %a/%n' Defaults to '%t [%n]'
+    #[structopt(long = "title-format")]
+    title_format: Option<String>,
+
+    /// No terminals start out active, enable them via the Hosts menu.
+    #[structopt(long = "none-active", conflicts_with = "active_first")]
+    none_active: bool,
+
+    /// Do not offer the hosts from ~/.ssh/config in the "Add Host(s) or Cluster(s)" dialog.
+    #[structopt(long = "no-ssh-config")]
+    no_ssh_config: bool,
+
     /// Opacity. 1 = opaque, 0.5 = semi-transparent, 0 = transparent.
     #[structopt(short = "O", long = "opacity")]
     opacity: Option<f64>,
@@ -132,9 +284,24 @@ pub struct Getopt {
     /// If a hostname resolves to multiple IPs, then toggle connecting to all of them.
     #[structopt(short = "A", long = "use-all-a-records")]
     use_all_a_records: bool,
+
+    /// Wait until every terminal is up and about to run ssh (or other comms), before showing the console.
+    /// It can't tell whether the login, or any command given with -a, then succeeds.
+    /// Exits with an error if any terminal failed to report back.
+    #[structopt(long = "wait-all")]
+    wait_all: bool,
+
+    /// How many more times to try connecting to the X server, if it isn't ready yet.
+    /// Defaults to $TCSSH_X_RETRIES, or 3.
+    #[structopt(long = "x-retries")]
+    pub x_retries: Option<u32>,
 }
 
 impl Getopt {
+    pub fn from_env_args() -> Self {
+        Self::from_iter(expand_bare_debug(std::env::args()))
+    }
+
     pub fn setup(&self, config: &mut config::Config) -> Result<()> {
         // handle --config_file=foo, error out if foo does not exist
         if let Some(config_file) = &self.config_file {
@@ -149,12 +316,23 @@ impl Getopt {
                 }
             }
         }
+        // CLI args still win over the profile, see override_config_with_args()
+        if let Some(profile) = &self.profile {
+            let config_dir = config.tcssh.get_config_dir();
+            read_profile(config, config_dir, profile)?;
+        }
         Ok(())
     }
 
     pub fn override_config_with_args(&self, config: &mut config::Config) -> Result<()> {
         // Now override config with getopt --args
 
+        if self.active_first {
+            config.misc.initial_active = config::InitialActive::First;
+        }
+        if self.none_active {
+            config.misc.initial_active = config::InitialActive::None;
+        }
         if let Some(auto_close) = &self.auto_close {
             // clone because Config.auto_close is Cow<'static> but Getopt is not 'static.
             config.misc.auto_close = Cow::Owned(auto_close.clone());
@@ -163,8 +341,61 @@ impl Getopt {
             let mut v = cluster_file.split(',').map(PathBuf::from).collect();
             config.misc.extra_cluster_file.append(&mut v);
         }
-        if let Some(command) = &self.command {
-            config.comms.command = Cow::Owned(command.clone());
+        if self.color {
+            config.terminal.colorize = true;
+        }
+        if self.no_color {
+            config.terminal.colorize = false;
+        }
+        if !self.command.is_empty() {
+            // one shell command, so macros are substituted in each of them.
+            // A line each, as "; " would break -a 'sleep 1 &' or -a 'cd /tmp;'
+            config.comms.command = Cow::Owned(self.command.join("\n"));
+        }
+        if self.dark {
+            config.terminal.bg_style_dark = true;
+        }
+        if self.light {
+            config.terminal.bg_style_dark = false;
+        }
+        if let Some(console_geometry) = &self.console_geometry {
+            if !STRICT_GEOMETRY.is_match(console_geometry) {
+                return Err(format!("Bad --console-geometry {}", console_geometry).into());
+            }
+            config.misc.console_geometry = Some(console_geometry.clone());
+        }
+        if let Some(comms) = &self.comms {
+            config.comms.comms = config::CommsE::from_str(comms)?;
+        }
+        if let Some(comms_command) = &self.comms_command {
+            config.comms.custom_command = Cow::Owned(comms_command.clone());
+        }
+        if let config::CommsE::Custom = config.comms.comms {
+            if config.comms.custom_command.is_empty() {
+                return Err(
+                    "--comms custom needs --comms-command or custom_command in the config".into(),
+                );
+            }
+        }
+        match config.comms.comms {
+            config::CommsE::Ssh => {}
+            _ => {
+                if !config.comms.ssh_send_env.is_empty() {
+                    eprintln!("Warn: Ignoring ssh_send_env, it is only passed to ssh");
+                }
+                if config.comms.connect_timeout.is_some() {
+                    eprintln!("Warn: Ignoring connect_timeout, it is only passed to ssh");
+                }
+                if config.comms.remote_multiplexer != config::RemoteMultiplexer::None {
+                    eprintln!("Warn: Ignoring remote_multiplexer, it is only used with ssh");
+                }
+                if config.comms.suppress_motd {
+                    eprintln!("Warn: Ignoring suppress_motd, it is only used with ssh");
+                }
+            }
+        }
+        if let Some(encoding) = &self.encoding {
+            config.misc.input_encoding = config::InputEncoding::from_str(encoding)?;
         }
         if let Some(font) = &self.font {
             config.terminal.font = Cow::Owned(font.clone());
@@ -172,47 +403,68 @@ impl Getopt {
         if self.show_history {
             config.misc.show_history = true;
         }
+        if self.pick {
+            config.misc.open_dialog_when_empty = true;
+        }
         if let Some(port) = self.port {
             config.misc.port = Some(format!("{}", port));
         }
         if self.sleep {
             config.tcssh.sleep = true;
         }
+        if self.no_ssh_config {
+            config.misc.use_ssh_config_hosts = false;
+        }
         if config.comms.ssh_args.is_empty() && self.ssh_args.is_none() {
             // inject default, (if nothing in config file and no --arg)
-            config.comms.ssh_args = Cow::Borrowed("-x -o ConnectTimeout=10");
+            // leaving ConnectTimeout to connect_timeout if that's set.
+            config.comms.ssh_args = if config.comms.connect_timeout.is_some() {
+                Cow::Borrowed("-x")
+            } else {
+                Cow::Borrowed("-x -o ConnectTimeout=10")
+            };
         } else if let Some(ssh_args) = &self.ssh_args {
             // else use --arg if it exists
             config.comms.ssh_args = Cow::Owned(ssh_args.clone());
         }
+        if !self.passthrough.is_empty() {
+            // quoted, as helper.rs runs the command with sh -c
+            let extra: Vec<String> = self
+                .passthrough
+                .iter()
+                .map(|arg| format!("'{}'", child::shell_escape(arg)))
+                .collect();
+            config.comms.append_args(&extra.join(" "))?;
+        }
         if let Some(tag_file) = &self.tag_file {
             let mut v = tag_file.split(',').map(PathBuf::from).collect();
             config.misc.extra_tag_file.append(&mut v);
         }
         if let Some(term_args) = &self.term_args {
-            config.terminal.args = Some(term_args.clone());
-
             // if term args matched /-class (\w+)/ then adjust config.allow_send_events.
-            match Regex::new(r"-class (\w+)") {
-                Err(e) => return Err(e.into()),
-                Ok(re) => {
-                    if let Some(cap) = re.captures(term_args) {
-                        if let Some(item) = cap.get(1) {
-                            config.terminal.allow_send_events = Cow::Owned(format!(
-                                "-xrm '{}.VT100.allowSendEvents:true'",
-                                item.as_str()
-                            ));
-                        }
-                    }
-                }
-            }
+            config.terminal.set_args(term_args);
+        }
+        if let Some(class) = &self.class {
+            config.terminal.set_class(class)?;
+        }
+        if let Some(term_size) = &self.term_size {
+            config.terminal.set_size(term_size)?;
         }
         if self.tile {
             config.misc.window_tiling = !config.misc.window_tiling;
         }
+        if self.force_tile {
+            config.misc.window_tiling = true;
+        }
+        if self.no_tile {
+            config.misc.window_tiling = false;
+        }
         if let Some(title) = &self.title {
             config.dynamic.title = Some(title.clone());
         }
+        if let Some(title_format) = &self.title_format {
+            config.misc.title_format = Some(title_format.clone());
+        }
         if let Some(opacity) = self.opacity {
             config.tcssh.set_opacity(opacity);
         }
@@ -222,6 +474,349 @@ impl Getopt {
         if self.use_all_a_records {
             config.misc.use_all_a_records = !config.misc.use_all_a_records;
         }
+        if self.wait_all {
+            config.dynamic.wait_all = true;
+        }
         Ok(())
     }
 }
+
+// clap can't tell a bare --debug (with an optional value) from no --debug,
+// so spell it out before clap sees it.
+fn expand_bare_debug<I: Iterator<Item = String>>(args: I) -> Vec<String> {
+    let mut after_dashdash = false;
+    args.map(|arg| {
+        if arg == "--" {
+            after_dashdash = true;
+        }
+        if !after_dashdash && arg == "--debug" {
+            "--debug=all".to_string()
+        } else {
+            arg
+        }
+    })
+    .collect()
+}
+
+// --profile name, error out if config_dir/profiles/name.config does not exist.
+fn read_profile(
+    config: &mut config::Config,
+    config_dir: Option<PathBuf>,
+    name: &str,
+) -> Result<()> {
+    if name.is_empty() || name.contains('/') {
+        return Err(format!("Bad --profile {}", name).into());
+    }
+    let mut profile = match config_dir {
+        Some(config_dir) => config_dir,
+        None => return Err("--profile needs ~/.tcssh or ~/.clusterssh to exist".into()),
+    };
+    profile.push("profiles");
+    profile.push(format!("{}.config", name));
+    if !profile.is_file() {
+        return Err(format!(
+            "No profile {}, {} does not exist",
+            name,
+            profile.to_string_lossy()
+        )
+        .into());
+    }
+    config::read_file(config, &profile)
+}
+
+#[cfg(test)]
+fn override_test_config(args: &[&str]) -> config::Config {
+    let mut config = config::Config::default();
+    let getopt = Getopt::from_iter(args);
+    getopt.override_config_with_args(&mut config).unwrap();
+    config
+}
+
+#[test]
+fn test_term_size_args() {
+    let config = override_test_config(&["tcssh"]);
+    assert_eq!(config.terminal.terminal_size_x, 80);
+    assert_eq!(config.terminal.terminal_size_y, 24);
+
+    let config = override_test_config(&["tcssh", "--term-size", "100x30"]);
+    assert_eq!(config.terminal.terminal_size_x, 100);
+    assert_eq!(config.terminal.terminal_size_y, 30);
+
+    for bad in &["0x24", "80x0", "80", "80x24x1"] {
+        let mut config = config::Config::default();
+        let getopt = Getopt::from_iter(&["tcssh", "--term-size", *bad]);
+        assert!(getopt.override_config_with_args(&mut config).is_err());
+        assert_eq!(config.terminal.terminal_size_x, 80);
+        assert_eq!(config.terminal.terminal_size_y, 24);
+    }
+}
+
+#[test]
+fn test_color_args() {
+    let config = override_test_config(&["tcssh"]);
+    assert!(config.terminal.colorize);
+    assert!(config.terminal.bg_style_dark);
+
+    let config = override_test_config(&["tcssh", "--no-color", "--light"]);
+    assert!(!config.terminal.colorize);
+    assert!(!config.terminal.bg_style_dark);
+
+    let mut config = config::Config::default();
+    config.terminal.colorize = false; // e.g. from the config file
+    config.terminal.bg_style_dark = false;
+    let getopt = Getopt::from_iter(&["tcssh", "--color", "--dark"]);
+    getopt.override_config_with_args(&mut config).unwrap();
+    assert!(config.terminal.colorize);
+    assert!(config.terminal.bg_style_dark);
+
+    assert!(Getopt::from_iter_safe(&["tcssh", "--color", "--no-color"]).is_err());
+}
+
+#[test]
+fn test_comms_custom_args() {
+    let config = override_test_config(&[
+        "tcssh",
+        "--comms",
+        "custom",
+        "--comms-command",
+        "kubectl exec -it %h -- bash",
+    ]);
+    assert_eq!(config.get_script_args().0, config::CUSTOM_COMMS);
+    assert_eq!(config.get_script_args().1, "kubectl exec -it %h -- bash");
+
+    let mut config = config::Config::default();
+    let getopt = Getopt::from_iter(&["tcssh", "--comms", "custom"]);
+    assert!(getopt.override_config_with_args(&mut config).is_err());
+}
+
+#[test]
+fn test_connect_timeout_default_ssh_args() {
+    let config = override_test_config(&["tcssh", "host1"]);
+    assert_eq!(config.comms.ssh_args, "-x -o ConnectTimeout=10");
+
+    let mut config = config::Config::default();
+    config.comms.connect_timeout = Some(3);
+    let getopt = Getopt::from_iter(&["tcssh", "host1"]);
+    getopt.override_config_with_args(&mut config).unwrap();
+    assert_eq!(config.comms.ssh_args, "-x");
+}
+
+#[test]
+fn test_passthrough_args() {
+    let args = [
+        "tcssh",
+        "web1",
+        "web2",
+        "--",
+        "-o",
+        "StrictHostKeyChecking=accept-new",
+    ];
+    let getopt = Getopt::from_iter(&args);
+    assert_eq!(getopt.hosts, vec!["web1", "web2"]);
+    assert_eq!(
+        getopt.passthrough,
+        vec!["-o", "StrictHostKeyChecking=accept-new"]
+    );
+
+    let config = override_test_config(&args);
+    assert_eq!(
+        config.comms.ssh_args,
+        "-x -o ConnectTimeout=10 '-o' 'StrictHostKeyChecking=accept-new'"
+    );
+
+    let config = override_test_config(&["tcssh", "-o", "-A", "web1", "--", "it's"]);
+    assert_eq!(config.comms.ssh_args, r"-A 'it'\''s'");
+
+    let mut config = config::Config::default();
+    let args = [
+        "tcssh",
+        "--comms",
+        "custom",
+        "--comms-command=%h",
+        "--",
+        "-v",
+    ];
+    let getopt = Getopt::from_iter(&args);
+    assert!(getopt.override_config_with_args(&mut config).is_err());
+}
+
+#[test]
+fn test_tile_args() {
+    let config = override_test_config(&["tcssh", "host1"]);
+    assert!(config.misc.window_tiling);
+    let config = override_test_config(&["tcssh", "--tile", "host1"]); // toggles
+    assert!(!config.misc.window_tiling);
+    let config = override_test_config(&["tcssh", "--no-tile", "host1"]);
+    assert!(!config.misc.window_tiling);
+
+    let mut config = config::Config::default();
+    config.misc.window_tiling = false; // e.g. window_tiling=no in the config file
+    let getopt = Getopt::from_iter(&["tcssh", "--force-tile", "host1"]);
+    getopt.override_config_with_args(&mut config).unwrap();
+    assert!(config.misc.window_tiling);
+
+    assert!(Getopt::from_iter_safe(&["tcssh", "--force-tile", "--no-tile"]).is_err());
+    assert!(Getopt::from_iter_safe(&["tcssh", "--tile", "--no-tile"]).is_err());
+}
+
+#[test]
+fn test_class_args() {
+    let config = override_test_config(&["tcssh", "--class", "Ops", "host1"]);
+    assert_eq!(config.terminal.class, Some("Ops".to_string()));
+    assert_eq!(
+        config.terminal.allow_send_events,
+        "-xrm 'Ops.VT100.allowSendEvents:true'"
+    );
+
+    let config = override_test_config(&["tcssh", "--term-args=-class Dev -sb", "host1"]);
+    assert_eq!(config.terminal.class, None); // already in the args
+    assert_eq!(
+        config.terminal.allow_send_events,
+        "-xrm 'Dev.VT100.allowSendEvents:true'"
+    );
+}
+
+#[test]
+fn test_action_args() {
+    let config = override_test_config(&["tcssh", "-a", "uptime", "host1"]);
+    assert_eq!(config.comms.command, "uptime");
+
+    let config = override_test_config(&["tcssh", "-a", "cd /tmp", "--action", "echo %s", "host1"]);
+    assert_eq!(config.comms.command, "cd /tmp\necho %s");
+    match crate::macros::substitute(
+        &config.comms.command,
+        &config.macros,
+        "host1",
+        "host1",
+        &None,
+    ) {
+        crate::macros::Subst::Diff { text } => assert_eq!(text, "cd /tmp\necho host1"),
+        _ => panic!("expected %s to be substituted"),
+    }
+
+    let config = override_test_config(&["tcssh", "-a", "sleep 1 &", "-a", "uptime", "host1"]);
+    assert_eq!(config.comms.command, "sleep 1 &\nuptime");
+
+    let config = override_test_config(&["tcssh", "host1"]);
+    assert_eq!(config.comms.command, "");
+}
+
+#[test]
+fn test_console_geometry_arg() {
+    let config = override_test_config(&["tcssh", "--console-geometry", "800x200+0+0"]);
+    assert_eq!(
+        config.misc.initial_console_geometry(),
+        Some("800x200+0+0".to_string())
+    );
+
+    let mut config = config::Config::default();
+    let getopt = Getopt::from_iter(&["tcssh", "--console-geometry", "wide"]);
+    assert!(getopt.override_config_with_args(&mut config).is_err());
+}
+
+#[test]
+fn test_no_ssh_config_arg() {
+    let config = override_test_config(&["tcssh"]);
+    assert!(config.misc.use_ssh_config_hosts);
+
+    let config = override_test_config(&["tcssh", "--no-ssh-config"]);
+    assert!(!config.misc.use_ssh_config_hosts);
+}
+
+#[test]
+fn test_shuffle_args() {
+    let getopt = Getopt::from_iter(&["tcssh", "--shuffle", "--seed", "42", "host1"]);
+    assert!(getopt.shuffle);
+    assert_eq!(getopt.seed, Some(42));
+
+    assert!(Getopt::from_iter_safe(&["tcssh", "--seed", "42", "host1"]).is_err());
+}
+
+#[test]
+fn test_no_console_args() {
+    let getopt = Getopt::from_iter(&["tcssh", "--no-console", "host1"]);
+    assert!(getopt.no_console);
+    assert!(!getopt.batch);
+    assert_eq!(getopt.hosts, vec!["host1"]);
+
+    assert!(!Getopt::from_iter(&["tcssh", "host1"]).no_console);
+}
+
+#[test]
+fn test_batch_args() {
+    let getopt = Getopt::from_iter(&["tcssh", "--batch", "-a", "uptime", "host1"]);
+    assert!(getopt.batch);
+    assert_eq!(getopt.hosts, vec!["host1"]);
+
+    assert!(!Getopt::from_iter(&["tcssh", "host1"]).batch);
+    assert!(Getopt::from_iter_safe(&["tcssh", "--batch", "--pick"]).is_err());
+}
+
+#[test]
+fn test_debug_args() {
+    let getopt = Getopt::from_iter(expand_bare_debug(
+        ["tcssh", "--debug", "host1"].iter().map(|s| s.to_string()),
+    ));
+    assert_eq!(getopt.debug, vec!["all"]);
+    assert_eq!(getopt.hosts, vec!["host1"]);
+
+    let getopt = Getopt::from_iter(&["tcssh", "--debug=x,retile", "--debug=dns", "host1"]);
+    assert_eq!(getopt.debug, vec!["x,retile", "dns"]);
+    assert_eq!(getopt.hosts, vec!["host1"]);
+
+    let getopt = Getopt::from_iter(&["tcssh", "host1"]);
+    assert!(getopt.debug.is_empty());
+}
+
+#[test]
+fn test_profile() {
+    let config_dir = crate::tmpnam::tmpnam().unwrap();
+    let profiles = config_dir.join("profiles");
+    std::fs::create_dir_all(&profiles).unwrap();
+    let config_file = config_dir.join("config");
+    std::fs::write(
+        &config_file,
+        "terminal_font=6x13\nterminal_bg_style=light\nterminal_colorize=1\n",
+    )
+    .unwrap();
+    std::fs::write(
+        profiles.join("work.config"),
+        "terminal_font=7x14\nterminal_colorize=0\n",
+    )
+    .unwrap();
+
+    // config file, then profile, then CLI args
+    let mut config = config::Config::default();
+    config.tcssh.set_config_dir(config_dir.clone());
+    let getopt = Getopt::from_iter(&["tcssh", "--profile", "work", "--color"]);
+    getopt.setup(&mut config).unwrap();
+    getopt.override_config_with_args(&mut config).unwrap();
+    assert_eq!(config.terminal.font, "7x14"); // profile over config file
+    assert!(!config.terminal.bg_style_dark); // config file, untouched by the profile
+    assert!(config.terminal.colorize); // CLI over profile
+
+    // --config-file instead of config_dir's, the profile still comes from config_dir
+    std::fs::write(&config_file, "terminal_font=6x13\n").unwrap();
+    let other_file = config_dir.join("other");
+    std::fs::write(&other_file, "terminal_bg_style=light\n").unwrap();
+    let mut config = config::Config::default();
+    config.tcssh.set_config_dir(config_dir.clone());
+    let other_file = other_file.to_string_lossy().to_string();
+    let getopt = Getopt::from_iter(&["tcssh", "-C", other_file.as_str(), "--profile", "work"]);
+    getopt.setup(&mut config).unwrap();
+    assert_eq!(config.terminal.font, "7x14");
+    assert!(!config.terminal.bg_style_dark);
+
+    let mut config = config::Config::default();
+    config.tcssh.set_config_dir(config_dir.clone());
+    assert!(Getopt::from_iter(&["tcssh", "--profile", "home"])
+        .setup(&mut config)
+        .is_err());
+
+    let mut config = config::Config::default();
+    assert!(read_profile(&mut config, Some(config_dir.clone()), "home").is_err());
+    assert!(read_profile(&mut config, Some(config_dir.clone()), "../config").is_err());
+    assert!(read_profile(&mut config, None, "work").is_err());
+
+    std::fs::remove_dir_all(&config_dir).unwrap();
+}
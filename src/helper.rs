@@ -19,15 +19,18 @@ use std::env;
 use std::fs::OpenOptions;
 use std::io::BufWriter;
 use std::io::Write;
+use std::os::unix::io::IntoRawFd;
 use std::thread;
 use std::time::Duration;
 
 use crate::child;
+use crate::config;
+use crate::title;
 
 pub fn run(args: &mut env::Args) {
-    let (pipe, command) = parse_args(args);
+    let (pipe, command, wait_all) = parse_args(args);
 
-    write_to_pipe(pipe, get_pid_and_windowid());
+    write_to_pipe(pipe, get_pid_and_windowid(), wait_all);
 
     // perl cssh has a warn before exec, mimic it.
     eprintln!("Running: {}", &command);
@@ -35,7 +38,7 @@ pub fn run(args: &mut env::Args) {
     child::exec(&command);
 }
 
-fn parse_args(args: &mut env::Args) -> (String, String) {
+fn parse_args<I: Iterator<Item = String>>(args: &mut I) -> (String, String, bool) {
     let comms = args
         .next()
         .expect("Expected first argument to be ssh, console, rsh, sftp, or telnet");
@@ -52,12 +55,6 @@ fn parse_args(args: &mut env::Args) -> (String, String) {
         .next()
         .expect("Expected fifth argument to be the path to a named pipe");
 
-    let mut command = String::with_capacity(256);
-    command += &comms;
-    command += " ";
-    command += &comms_args;
-    command += " ";
-
     let svr_str: String;
     let svr = if let Some(svr) = args.next() {
         svr_str = svr;
@@ -74,33 +71,93 @@ fn parse_args(args: &mut env::Args) -> (String, String) {
         ""
     };
 
-    if let Some(user) = args.next() {
+    let user = args.next().unwrap_or_default();
+    let port = args.next().unwrap_or_default();
+
+    // Only passed (non empty) by child.rs when comms is mosh.
+    let mosh_server = args.next().unwrap_or_default();
+    let mosh_port_range = args.next().unwrap_or_default();
+
+    // Only passed (non empty) by child.rs when comms is telnet.
+    let telnet_login_opt = args.next().unwrap_or_default();
+
+    // Only passed (non empty) by child.rs when dynamic_titles=yes
+    let title = args.next().unwrap_or_default();
+
+    // "yes" when run with --wait-all
+    let wait_all = args.next().map_or(false, |w| w == "yes");
+
+    // Only passed (non empty) by child.rs when session_timeout is set.
+    let session_timeout = args.next().unwrap_or_default();
+
+    // What goes before the port, config's ssh_port_opt etc. Not used for telnet.
+    let port_opt = args.next().unwrap_or_else(|| String::from("-p "));
+
+    let mut command = String::with_capacity(256);
+    if wait_all {
+        // The parent is still reading the pipe, which we left open as fd 3.
+        // Tell it comms is about to start, then close it, so ssh etc don't inherit it.
+        // Nothing on the far end can reach this fd, so that's as far as --wait-all sees.
+        command += "echo ";
+        command += WAIT_ALL_STARTED;
+        command += " >&3 ; exec 3>&- ; ";
+    }
+    if !title.is_empty() {
+        // the remote shell may well set its own title, but this covers connecting..
+        set_title(&mut command, "", &title);
+        command += " ; ";
+    }
+    if !session_timeout.is_empty() {
+        // timeout(1) is from coreutils, if it's missing just run without it.
+        // --foreground or ssh etc can't read the xterm.
+        command += "$(command -v timeout >/dev/null && echo timeout --foreground ";
+        command += &session_timeout;
+        command += ") ";
+    }
+    if comms == config::CUSTOM_COMMS {
+        // comms_args is the template, e.g. "kubectl exec -it %h -- bash"
+        command += &expand_custom_command(&comms_args, svr, &user, &port);
+    } else {
+        command += &comms;
+        command += " ";
+        command += &comms_args;
+        command += " ";
+
+        if !mosh_server.is_empty() {
+            command += "--server=";
+            command += &mosh_server;
+            command += " ";
+        }
+        if !mosh_port_range.is_empty() {
+            command += "--port=";
+            command += &mosh_port_range;
+            command += " ";
+        }
+
         if (!user.is_empty()) && comms != "telnet" {
             command += "-l ";
             command += &user;
             command += " ";
+        } else if (!user.is_empty()) && !telnet_login_opt.is_empty() {
+            // telnet still wants 'svr port' last, so the username goes before them.
+            command += &telnet_login_opt;
+            command += " ";
+            command += &user;
+            command += " ";
         }
-    }
 
-    let port_str: String;
-    let port = if let Some(port) = args.next() {
-        port_str = port;
-        &port_str
-    } else {
-        ""
-    };
-
-    if comms == "telnet" {
-        command += svr;
-        command += " ";
-        command += port;
-    } else if !port.is_empty() {
-        command += "-p ";
-        command += port;
-        command += " ";
-        command += svr;
-    } else {
-        command += svr;
+        if comms == "telnet" {
+            command += svr;
+            command += " ";
+            command += &port;
+        } else if !port.is_empty() {
+            command += &port_opt;
+            command += &port;
+            command += " ";
+            command += svr;
+        } else {
+            command += svr;
+        }
     }
 
     if !config_command.is_empty() {
@@ -109,6 +166,12 @@ fn parse_args(args: &mut env::Args) -> (String, String) {
         command += "\"";
     }
 
+    if !title.is_empty() {
+        // ..and this shows which xterms are done, while they wait to close.
+        command += " ; ";
+        set_title(&mut command, "[DEAD] ", &title);
+    }
+
     command += " ; ";
     if auto_close.is_empty() || auto_close == "0" {
         command += "echo Press RETURN to continue; read IGNORE";
@@ -120,7 +183,31 @@ fn parse_args(args: &mut env::Args) -> (String, String) {
         command += &auto_close;
     };
 
-    (pipe, command)
+    (pipe, command, wait_all)
+}
+
+// For --comms custom, fill in the custom_command template.
+// Also console_command, for console comms.
+//     %h  host (as resolved, like ssh would get)
+//     %u  username (empty if none)
+//     %p  port (empty if none)
+//     %%  a literal %
+// Anything else is passed through untouched.
+pub fn expand_custom_command(template: &str, host: &str, user: &str, port: &str) -> String {
+    title::expand_tokens(template, |token| match token {
+        'h' => Some(host.to_string()),
+        'u' => Some(user.to_string()),
+        'p' => Some(port.to_string()),
+        _ => None,
+    })
+}
+
+// xterm's escape sequence to set its title, via sh's printf which expands the \033 etc.
+fn set_title(command: &mut String, prefix: &str, title: &str) {
+    *command += r"printf '\033]0;%s\007' '";
+    *command += prefix;
+    *command += &child::shell_escape(title);
+    *command += "'";
 }
 
 fn get_pid_and_windowid() -> String {
@@ -131,7 +218,12 @@ fn get_pid_and_windowid() -> String {
     format!("{}:{}\n", pid, windowid)
 }
 
-fn write_to_pipe(fname: String, s: String) {
+// --wait-all's second line on the pipe, after PID:WINDOWID
+pub static WAIT_ALL_STARTED: &'static str = "STARTED";
+
+// With keep_open the pipe is left open as fd 3 (and not close-on-exec)
+// for the command to write WAIT_ALL_STARTED to.
+fn write_to_pipe(fname: String, s: String, keep_open: bool) {
     let f = OpenOptions::new()
         .write(true)
         .create_new(false)
@@ -153,9 +245,334 @@ fn write_to_pipe(fname: String, s: String) {
             writer
                 .flush()
                 .unwrap_or_else(|_| panic!("Failed to flush pipe {}", fname));
+            if keep_open {
+                let file = writer
+                    .into_inner()
+                    .unwrap_or_else(|_| panic!("Failed to flush pipe {}", fname));
+                let fd = file.into_raw_fd(); // so it's not closed on drop
+                unsafe {
+                    if fd == 3 {
+                        libc::fcntl(fd, libc::F_SETFD, 0); // clear FD_CLOEXEC
+                    } else {
+                        libc::dup2(fd, 3); // the dup is not FD_CLOEXEC
+                        libc::close(fd);
+                    }
+                }
+            }
         }
         Err(e) => {
             panic!("Could not open {} {:?}", fname, e);
         }
     };
 }
+
+#[cfg(test)]
+fn parse_test_args(args: &[&str]) -> String {
+    let mut args = args.iter().map(|s| s.to_string());
+    let (pipe, command, _) = parse_args(&mut args);
+    assert_eq!(pipe, "/tmp/pipe");
+    command
+}
+
+#[test]
+fn test_parse_args_ssh() {
+    let command = parse_test_args(&[
+        "ssh",
+        "-x",
+        "",
+        "5",
+        "/tmp/pipe",
+        "host",
+        "user",
+        "22",
+        "",
+        "",
+    ]);
+    assert_eq!(
+        command,
+        "ssh -x -l user -p 22 host ; echo Sleeping for 5 seconds; sleep 5"
+    );
+}
+
+#[test]
+fn test_parse_args_passthrough() {
+    // tcssh web1 -- -o StrictHostKeyChecking=accept-new, see Comms::append_args()
+    let command = parse_test_args(&[
+        "ssh",
+        "-x '-o' 'StrictHostKeyChecking=accept-new'",
+        "",
+        "5",
+        "/tmp/pipe",
+        "web1",
+        "user",
+        "22",
+        "",
+        "",
+    ]);
+    assert_eq!(
+        command,
+        "ssh -x '-o' 'StrictHostKeyChecking=accept-new' -l user -p 22 web1 ; echo Sleeping for 5 seconds; sleep 5"
+    );
+}
+
+#[test]
+fn test_parse_args_mosh() {
+    let command = parse_test_args(&[
+        "mosh",
+        "",
+        "",
+        "0",
+        "/tmp/pipe",
+        "host",
+        "",
+        "",
+        "/opt/bin/mosh-server",
+        "60000:60010",
+    ]);
+    assert_eq!(
+        command,
+        "mosh  --server=/opt/bin/mosh-server --port=60000:60010 host ; echo Press RETURN to continue; read IGNORE"
+    );
+}
+
+#[test]
+fn test_parse_args_telnet() {
+    let mut args = [
+        "telnet",
+        "",
+        "",
+        "0",
+        "/tmp/pipe",
+        "host",
+        "user",
+        "23",
+        "",
+        "",
+        "",
+    ];
+    // perl cssh never passed the username to telnet
+    assert_eq!(
+        parse_test_args(&args),
+        "telnet  host 23 ; echo Press RETURN to continue; read IGNORE"
+    );
+
+    args[10] = "-l";
+    assert_eq!(
+        parse_test_args(&args),
+        "telnet  -l user host 23 ; echo Press RETURN to continue; read IGNORE"
+    );
+}
+
+#[test]
+fn test_parse_args_dynamic_titles() {
+    let command = parse_test_args(&[
+        "ssh",
+        "-x",
+        "",
+        "0",
+        "/tmp/pipe",
+        "host",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "TCSSH: bob's host",
+    ]);
+    assert_eq!(
+        command,
+        concat!(
+            r"printf '\033]0;%s\007' 'TCSSH: bob'\''s host' ; ",
+            "ssh -x host ; ",
+            r"printf '\033]0;%s\007' '[DEAD] TCSSH: bob'\''s host' ; ",
+            "echo Press RETURN to continue; read IGNORE"
+        )
+    );
+}
+
+#[test]
+fn test_expand_custom_command() {
+    let tests = [
+        (
+            "kubectl exec -it %h -- bash",
+            "kubectl exec -it web-0 -- bash",
+        ),
+        ("docker exec -it %h sh", "docker exec -it web-0 sh"),
+        ("ssh -l %u -p %p %h", "ssh -l bob -p 2222 web-0"),
+        ("echo 100%% %x %", "echo 100% %x %"),
+        ("", ""),
+    ];
+    for (template, expected) in tests.iter() {
+        assert_eq!(
+            &expand_custom_command(template, "web-0", "bob", "2222"),
+            expected
+        );
+    }
+}
+
+#[test]
+fn test_parse_args_custom() {
+    let command = parse_test_args(&[
+        "custom",
+        "kubectl exec -it %h -- bash",
+        "",
+        "0",
+        "/tmp/pipe",
+        "web-0",
+        "",
+        "",
+    ]);
+    assert_eq!(
+        command,
+        "kubectl exec -it web-0 -- bash ; echo Press RETURN to continue; read IGNORE"
+    );
+}
+
+#[test]
+fn test_parse_args_console_command() {
+    // console_command, get_script_args() passes it like a custom_command
+    let command = parse_test_args(&[
+        "custom",
+        "ipmitool -I lanplus -H %h-ipmi -U %u sol activate",
+        "",
+        "5",
+        "/tmp/pipe",
+        "db1",
+        "admin",
+        "",
+    ]);
+    assert_eq!(
+        command,
+        "ipmitool -I lanplus -H db1-ipmi -U admin sol activate ; \
+         echo Sleeping for 5 seconds; sleep 5"
+    );
+}
+
+#[test]
+fn test_parse_args_wait_all() {
+    let mut args = [
+        "ssh",
+        "",
+        "",
+        "0",
+        "/tmp/pipe",
+        "host",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "yes",
+    ]
+    .iter()
+    .map(|s| s.to_string());
+    let (_, command, wait_all) = parse_args(&mut args);
+    assert!(wait_all);
+    assert_eq!(
+        command,
+        "echo STARTED >&3 ; exec 3>&- ; ssh  host ; echo Press RETURN to continue; read IGNORE"
+    );
+}
+
+#[test]
+fn test_parse_args_session_timeout() {
+    let command = parse_test_args(&[
+        "ssh",
+        "-x",
+        "",
+        "5",
+        "/tmp/pipe",
+        "host",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "30m",
+    ]);
+    assert_eq!(
+        command,
+        concat!(
+            "$(command -v timeout >/dev/null && echo timeout --foreground 30m) ",
+            "ssh -x host ; echo Sleeping for 5 seconds; sleep 5"
+        )
+    );
+}
+
+#[test]
+fn test_parse_args_session_timeout_fallback() {
+    use std::process::{Command, Stdio};
+
+    // echo stands in for ssh, and with no timeout(1) on the PATH it still runs.
+    let command = parse_test_args(&[
+        "echo",
+        "",
+        "",
+        "0",
+        "/tmp/pipe",
+        "host",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "",
+        "30m",
+    ]);
+    let output = Command::new("/bin/sh")
+        .arg("-c")
+        .arg(&command)
+        .env("PATH", "/nonexistent")
+        .stdin(Stdio::null())
+        .output()
+        .unwrap();
+    assert_eq!(
+        String::from_utf8_lossy(&output.stdout),
+        "host\nPress RETURN to continue\n"
+    );
+}
+
+#[test]
+fn test_parse_args_port_opt() {
+    let args = |comms: &'static str, port_opt: &'static str| -> Vec<&'static str> {
+        vec![
+            comms,
+            "",
+            "",
+            "0",
+            "/tmp/pipe",
+            "host",
+            "",
+            "2222",
+            "",
+            "",
+            "",
+            "",
+            "",
+            "",
+            port_opt,
+        ]
+    };
+    let tail = " ; echo Press RETURN to continue; read IGNORE";
+    assert_eq!(
+        parse_test_args(&args("ssh", "-p ")),
+        format!("ssh  -p 2222 host{}", tail)
+    );
+    assert_eq!(
+        parse_test_args(&args("mosh", r"--ssh=ssh\ -p\ ")),
+        format!(r"mosh  --ssh=ssh\ -p\ 2222 host{}", tail)
+    );
+    assert_eq!(
+        parse_test_args(&args("sftp", "-P ")),
+        format!("sftp  -P 2222 host{}", tail)
+    );
+    // telnet takes the port after the host, whatever port_opt is.
+    assert_eq!(
+        parse_test_args(&args("telnet", "")),
+        format!("telnet  host 2222{}", tail)
+    );
+}
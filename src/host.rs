@@ -1,5 +1,6 @@
 // parse host strings, extracting username, hostname, port, geometry
 // perl cssh accepted, but dropped geometry.  We do the same.
+// tcssh only, a trailing %-delimited list of ssh options, e.g. web1%-oFoo=bar%-4
 
 use regex::Regex;
 use std::ops::Range;
@@ -53,9 +54,45 @@ pub struct Host<'a> {
     pub hostname: &'a str,
     pub port: Option<&'a str>,
     pub geometry: Option<&'a str>,
+    pub ssh_options: Option<&'a str>, // still % delimited, see ssh_options()
 }
 
-pub fn parse(host: &str) -> Option<Host<'_>> {
+impl<'a> Host<'a> {
+    // All of parse_string as the hostname, for on_parse_error=literal.
+    pub fn literal(parse_string: &'a str) -> Self {
+        Host {
+            parse_string,
+            username: None,
+            hostname: parse_string,
+            port: None,
+            geometry: None,
+            ssh_options: None,
+        }
+    }
+
+    // Each of the ssh options given after a %, e.g. web1%-oFoo=bar%-4
+    pub fn ssh_options(&self) -> impl Iterator<Item = &'a str> {
+        self.ssh_options
+            .unwrap_or("")
+            .split('%')
+            .filter(|o| !o.is_empty())
+    }
+}
+
+pub fn parse(parse_string: &str) -> Option<Host<'_>> {
+    // % isn't valid in any of the below, so everything after the first is options.
+    let (host, ssh_options) = match parse_string.find('%') {
+        Some(i) => (&parse_string[..i], Some(&parse_string[i + 1..])),
+        None => (parse_string, None),
+    };
+    let ssh_options = ssh_options.filter(|o| !o.is_empty());
+    let mut host = parse_host(host)?;
+    host.parse_string = parse_string;
+    host.ssh_options = ssh_options;
+    Some(host)
+}
+
+fn parse_host(host: &str) -> Option<Host<'_>> {
     // the parsing logic is right out of perl cssh.
     if let Some(cap) = HOST_IPV6.captures(host) {
         if let Some(c_hostname) = cap.get(2) {
@@ -67,6 +104,7 @@ pub fn parse(host: &str) -> Option<Host<'_>> {
                     hostname: s_hostname,
                     port: cap.get(3).and_then(|m| Some(m.as_str())),
                     geometry: cap.get(4).and_then(|m| Some(m.as_str())),
+                    ssh_options: None,
                 });
             }
         }
@@ -83,6 +121,7 @@ pub fn parse(host: &str) -> Option<Host<'_>> {
                     hostname: s_hostname,
                     port: cap.get(3).and_then(|m| Some(m.as_str())),
                     geometry: cap.get(4).and_then(|m| Some(m.as_str())),
+                    ssh_options: None,
                 });
             }
         }
@@ -179,9 +218,78 @@ pub fn parse(host: &str) -> Option<Host<'_>> {
             Some(r) => Some(&host[r]),
             _ => None,
         },
+        ssh_options: None,
     })
 }
 
+// "tcssh 'web1,web2 web3'" is one CLI arg, so split it into individual hosts.
+// Split on whitespace and commas, but not on commas within [ipv6] brackets,
+// nor within =geometry (the geometry regexes allow any separator).
+pub fn split_host_args(args: Vec<String>) -> Vec<String> {
+    let mut out = Vec::with_capacity(args.len());
+    for arg in args {
+        if !arg.contains(|c: char| c == ',' || c.is_whitespace()) {
+            out.push(arg); // most common case, nothing to split.
+            continue;
+        }
+        let mut in_brackets = false;
+        let mut in_geometry = false;
+        let mut start = 0;
+        for (i, c) in arg.char_indices() {
+            let split = if c.is_whitespace() {
+                true
+            } else if c == ',' {
+                !(in_brackets || in_geometry)
+            } else {
+                match c {
+                    '[' => in_brackets = true,
+                    ']' => in_brackets = false,
+                    '=' if !in_brackets => in_geometry = true,
+                    _ => {}
+                }
+                false
+            };
+            if split {
+                if start < i {
+                    out.push(arg[start..i].to_string());
+                }
+                start = i + c.len_utf8();
+                in_brackets = false;
+                in_geometry = false;
+            }
+        }
+        if start < arg.len() {
+            out.push(arg[start..].to_string());
+        }
+    }
+    out
+}
+
+#[test]
+fn test_split_host_args() {
+    let tests: [(&[&str], &[&str]); 8] = [
+        (&["a,b"], &["a", "b"]),
+        (&["web1,web2 web3"], &["web1", "web2", "web3"]),
+        (&["user@[::1],c"], &["user@[::1]", "c"]),
+        (&["user@[fe80::1,x]:22"], &["user@[fe80::1,x]:22"]),
+        (&["a=1,2,3,4,b"], &["a=1,2,3,4,b"]), // geometry swallows the rest
+        (&["a=1x2+3+4 b,c"], &["a=1x2+3+4", "b", "c"]),
+        (&[",a,,b,", "  ", "c"], &["a", "b", "c"]),
+        (&["untouched", "also"], &["untouched", "also"]),
+    ];
+    for (args, expected) in tests.iter() {
+        let args = args.iter().map(|s| s.to_string()).collect();
+        assert_eq!(split_host_args(args), *expected);
+    }
+
+    // a lone literal with a comma in an unusual place still parses
+    let split = split_host_args(vec!["luser@host.example.com,".to_string()]);
+    assert_eq!(split, ["luser@host.example.com"]);
+    let host = parse(&split[0]).expect("Expected to parse luser@host.example.com");
+    assert_eq!(host.username, Some("luser"));
+    assert_eq!(host.hostname, "host.example.com");
+}
+
 #[test]
 fn test_parse() {
     {
@@ -197,6 +305,7 @@ fn test_parse() {
                     hostname: h,
                     port: None,
                     geometry: None,
+                    ssh_options: None,
                 }
             );
         }
@@ -213,6 +322,7 @@ fn test_parse() {
                 hostname: "fe80::c3cf:9c90:59b5:3d0b",
                 port: None,
                 geometry: None,
+                ssh_options: None,
             }
         );
     }
@@ -228,6 +338,7 @@ fn test_parse() {
                 hostname: "fe80::c3cf:9c90:59b5:3d0b",
                 port: Some("1234"),
                 geometry: Some("640x480+10+11"),
+                ssh_options: None,
             }
         );
     }
@@ -243,6 +354,7 @@ fn test_parse() {
                 hostname: "123.234.12.34",
                 port: Some("4321"),
                 geometry: Some("1024x768+20+21"),
+                ssh_options: None,
             }
         );
     }
@@ -258,6 +370,7 @@ fn test_parse() {
                 hostname: "box-001.internal.xn--foo.computing",
                 port: Some("4321"),
                 geometry: Some("320x240+34+45"),
+                ssh_options: None,
             }
         );
     }
@@ -273,6 +386,7 @@ fn test_parse() {
                 hostname: "abc::def",
                 port: Some("321"),
                 geometry: Some("1920x1080+56+67"),
+                ssh_options: None,
             }
         );
     }
@@ -288,6 +402,7 @@ fn test_parse() {
                 hostname: "1:2:3:4:5:6:7:8",
                 port: Some("321"),
                 geometry: Some("1920x1080+56+67"),
+                ssh_options: None,
             }
         );
     }
@@ -305,6 +420,7 @@ fn test_parse() {
                 hostname: "1:2:3:4:5:6:7:8",
                 port: Some("321"),
                 geometry: Some("1920x1080+56+67"),
+                ssh_options: None,
             }
         );
     }
@@ -345,8 +461,45 @@ fn test_parse() {
                     hostname: h,
                     port: None,
                     geometry: None,
+                    ssh_options: None,
                 }
             );
         }
     }
 }
+
+#[test]
+fn test_parse_ssh_options() {
+    let h = "web1%-oFoo=bar";
+    let host = parse(h).expect(&format!("Expected to parse {}", h));
+    assert_eq!(
+        host,
+        Host {
+            parse_string: h,
+            username: None,
+            hostname: "web1",
+            port: None,
+            geometry: None,
+            ssh_options: Some("-oFoo=bar"),
+        }
+    );
+    assert_eq!(host.ssh_options().collect::<Vec<_>>(), vec!["-oFoo=bar"]);
+
+    let h = "bob@[::1]:2222=80x24+0+0%-oStrictHostKeyChecking=no%%-4";
+    let host = parse(h).expect(&format!("Expected to parse {}", h));
+    assert_eq!(host.username, Some("bob"));
+    assert_eq!(host.hostname, "::1");
+    assert_eq!(host.port, Some("2222"));
+    assert_eq!(host.geometry, Some("80x24+0+0"));
+    assert_eq!(
+        host.ssh_options().collect::<Vec<_>>(),
+        vec!["-oStrictHostKeyChecking=no", "-4"]
+    );
+
+    let host = parse("web1%").expect("Expected to parse web1%");
+    assert_eq!(host.hostname, "web1");
+    assert_eq!(host.ssh_options, None);
+    assert_eq!(host.ssh_options().count(), 0);
+
+    assert_eq!(parse("%-oFoo=bar"), None);
+}
@@ -31,6 +31,16 @@ pub fn is_executable_file<P: AsRef<Path>>(p: P) -> bool {
     }
 }
 
+// For config files, is this ours, and not writable by anyone else?
+// Anything else (e.g. root's, or a group/world writable file) could
+// have been set up by someone else to run their own commands as us.
+pub fn is_trusted_file<P: AsRef<Path>>(p: P) -> bool {
+    match p.as_ref().metadata() {
+        Ok(md) => md.st_uid() == *EUID && (md.st_mode() & 0o022) == 0,
+        Err(_) => false,
+    }
+}
+
 pub trait IsExecutableFile {
     fn is_executable_file(&self) -> bool;
 }
@@ -41,3 +51,21 @@ impl IsExecutableFile for Path {
         is_executable_file(self)
     }
 }
+
+#[test]
+fn test_is_trusted_file() {
+    use std::fs;
+    use std::os::unix::fs::PermissionsExt;
+
+    let p = crate::tmpnam::tmpnam().unwrap();
+    fs::write(&p, "ssh=ssh\n").unwrap();
+    fs::set_permissions(&p, fs::Permissions::from_mode(0o600)).unwrap();
+    let ours = is_trusted_file(&p);
+    fs::set_permissions(&p, fs::Permissions::from_mode(0o666)).unwrap();
+    let world_writable = is_trusted_file(&p);
+    fs::remove_file(&p).unwrap();
+
+    assert!(ours);
+    assert!(!world_writable);
+    assert!(!is_trusted_file(&p)); // gone
+}
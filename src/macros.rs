@@ -8,12 +8,102 @@ use libc::getpwuid;
 use regex::Regex;
 use std::borrow::Cow;
 use std::ffi::CStr;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
 use crate::config::Macros;
+use crate::er::Result;
 use crate::is_xfile;
 
 pub static VERSION_JUST_NUMBER: &'static str = "0.2.0";
-static VERSION_LONG: &'static str = "Transparent Cluster SSH 0.2.0";
+pub static VERSION_LONG: &'static str = "Transparent Cluster SSH 0.2.0";
+
+// What we were built for and against, set by build.rs
+pub static BUILD_TARGET: &'static str = env!("TCSSH_BUILD_TARGET");
+pub static BUILD_X11: &'static str = env!("TCSSH_BUILD_X11");
+pub static BUILD_X11_CRATE: &'static str = env!("TCSSH_BUILD_X11_CRATE");
+pub static BUILD_GTK: &'static str = env!("TCSSH_BUILD_GTK");
+pub static BUILD_GTK_CRATE: &'static str = env!("TCSSH_BUILD_GTK_CRATE");
+pub static BUILD_TRUST_DNS: &'static str = env!("TCSSH_BUILD_TRUST_DNS");
+
+// --debug categories, see log!()
+pub const DEBUG_X: usize = 1; // talking to X11
+pub const DEBUG_RETILE: usize = 1 << 1; // window placement
+pub const DEBUG_CLUSTER: usize = 1 << 2; // tag expansion
+pub const DEBUG_DNS: usize = 1 << 3; // --use-all-a-records lookups
+pub const DEBUG_SEND: usize = 1 << 4; // text and keys sent to the xterms
+pub const DEBUG_FORK: usize = 1 << 5; // child processes, and what they exec
+pub const DEBUG_ALL: usize = (1 << 6) - 1;
+
+static DEBUG_NAMES: [(&str, usize); 6] = [
+    ("x", DEBUG_X),
+    ("retile", DEBUG_RETILE),
+    ("cluster", DEBUG_CLUSTER),
+    ("dns", DEBUG_DNS),
+    ("send", DEBUG_SEND),
+    ("fork", DEBUG_FORK),
+];
+
+// A global, rather than on App, so modules without an App (x.rs, cluster.rs) can log.
+static DEBUG: AtomicUsize = AtomicUsize::new(0);
+
+pub fn set_debug(categories: usize) {
+    DEBUG.store(categories, Ordering::Relaxed);
+}
+
+pub fn debug_enabled(category: usize) -> bool {
+    DEBUG.load(Ordering::Relaxed) & category != 0
+}
+
+pub fn debug_name(category: usize) -> &'static str {
+    DEBUG_NAMES
+        .iter()
+        .find(|(_, bits)| *bits == category)
+        .map_or("debug", |(name, _)| name)
+}
+
+// e.g. ["x,retile", "dns"] from --debug=x,retile --debug=dns
+// "all", or a number (perl cssh's debug level) turns everything on.
+pub fn parse_debug(args: &[String]) -> Result<usize> {
+    let mut categories = 0;
+    for name in args.iter().flat_map(|arg| arg.split(',')) {
+        let name = name.trim();
+        if name.is_empty() {
+            continue;
+        }
+        if name == "all" || name.chars().all(|c| c.is_ascii_digit()) {
+            categories |= DEBUG_ALL;
+            continue;
+        }
+        match DEBUG_NAMES.iter().find(|(n, _)| *n == name) {
+            Some((_, bits)) => categories |= bits,
+            None => return Err(format!(
+                "Unknown --debug category {}, expected x, retile, cluster, dns, send, fork or all",
+                name
+            )
+            .into()),
+        }
+    }
+    Ok(categories)
+}
+
+// log!(macros::DEBUG_RETILE, "moved {} to {},{}", wid, x, y);
+// Costs a load and a test when the category is off.
+#[macro_export]
+macro_rules! log {
+    ($category:expr, $($arg:tt)+) => {
+        if $crate::macros::debug_enabled($category) {
+            eprintln!("[{}] {}", $crate::macros::debug_name($category), format!($($arg)+));
+        }
+    };
+}
+
+// For the About dialog
+pub fn build_info() -> String {
+    format!(
+        "Built for {}\nX11 {} (x11 crate {})\nGTK {} (gtk crate {})\ntrust-dns-resolver {}",
+        BUILD_TARGET, BUILD_X11, BUILD_X11_CRATE, BUILD_GTK, BUILD_GTK_CRATE, BUILD_TRUST_DNS
+    )
+}
 
 lazy_static! {
     static ref USERNAME: String = unsafe {
@@ -36,8 +126,44 @@ pub enum Subst {
     Diff { text: String }, // subst may differ for different xterms
 }
 
+// %% is a literal %, so "%%s" sends "%s" instead of the servername.
+// The %% are hidden from the macros behind a placeholder, then put back as %.
+pub fn substitute(
+    text: &str,
+    macros: &Macros,
+    servername: &str,
+    hostname: &str,
+    username: &Option<String>,
+) -> Subst {
+    if !text.contains("%%") {
+        return substitute_macros(text, macros, servername, hostname, username);
+    }
+    let placeholder = placeholder_for(text);
+    let hidden = text.replace("%%", &placeholder.to_string());
+    match substitute_macros(&hidden, macros, servername, hostname, username) {
+        // no macros, but the %% still became %
+        Subst::None => Subst::Same {
+            text: hidden.replace(placeholder, "%"),
+        },
+        Subst::Same { text } => Subst::Same {
+            text: text.replace(placeholder, "%"),
+        },
+        Subst::Diff { text } => Subst::Diff {
+            text: text.replace(placeholder, "%"),
+        },
+    }
+}
+
+// Some char not in text, from the unicode private use area.
+fn placeholder_for(text: &str) -> char {
+    (0xE000..=0xF8FF)
+        .filter_map(std::char::from_u32)
+        .find(|c| !text.contains(*c))
+        .unwrap_or('\u{0}')
+}
+
 // This could be simpler if we didn't care about Diff/Same and always returned .to_owned()
-pub fn substitute<'a>(
+fn substitute_macros<'a>(
     text: &'a str,
     macros: &Macros,
     servername: &str,
@@ -218,4 +344,60 @@ mod macros_tests {
             }
         }
     }
+
+    #[test]
+    fn test_macros_escaped_percent() {
+        let macros: Macros = Default::default();
+        let subst = |text: &str| -> Subst {
+            substitute(
+                text,
+                &macros,
+                &"the_servername",
+                &"the_hostname",
+                &Some(String::from("the_username")),
+            )
+        };
+
+        match subst("100%% done") {
+            Subst::Same { text: got } => assert_eq!(got, "100% done"),
+            _ => assert!(false),
+        }
+        match subst("%%h") {
+            Subst::Same { text: got } => assert_eq!(got, "%h"),
+            _ => assert!(false),
+        }
+        match subst("printf '%%s on %s at 100%%%n'") {
+            Subst::Diff { text: got } => {
+                assert_eq!(got, "printf '%s on the_servername at 100%\n'")
+            }
+            _ => assert!(false),
+        }
+        match subst("%%%s") {
+            Subst::Diff { text: got } => assert_eq!(got, "%the_servername"),
+            _ => assert!(false),
+        }
+        match subst("50% off") {
+            Subst::None => {}
+            _ => assert!(false),
+        }
+    }
+}
+
+#[test]
+fn test_parse_debug() {
+    let args = |a: &[&str]| -> Vec<String> { a.iter().map(|s| s.to_string()).collect() };
+    assert_eq!(parse_debug(&args(&[])).unwrap(), 0);
+    assert_eq!(
+        parse_debug(&args(&["x,retile"])).unwrap(),
+        DEBUG_X | DEBUG_RETILE
+    );
+    assert_eq!(
+        parse_debug(&args(&["dns", "fork"])).unwrap(),
+        DEBUG_DNS | DEBUG_FORK
+    );
+    assert_eq!(parse_debug(&args(&["all"])).unwrap(), DEBUG_ALL);
+    assert_eq!(parse_debug(&args(&["2"])).unwrap(), DEBUG_ALL); // perl cssh level
+    assert!(parse_debug(&args(&["bogus"])).is_err());
+
+    assert_eq!(debug_name(DEBUG_SEND), "send");
 }
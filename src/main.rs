@@ -16,12 +16,16 @@ mod helper;
 mod host;
 mod is_xfile;
 mod macros;
+mod probe;
 mod reader;
+mod recent;
 mod resolver;
 mod retile;
+mod secret;
 mod send_text;
 mod server;
 mod text2x11;
+mod title;
 mod tk2gtk;
 mod tmpnam;
 mod wait_children;
@@ -74,6 +78,10 @@ fn main() {
             println!("Error: {}", e);
             return;
         };
+        if app.is_headless() {
+            // --batch or --no-console, the terminals were forked off and carry on without us.
+            return;
+        }
     }
     wait_children::setup_poll_children(&rapp);
 
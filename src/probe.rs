@@ -0,0 +1,177 @@
+// --probe, a quick TCP connect to each host before opening its terminal,
+// so hosts which are down don't each get an xterm waiting for ssh to time out.
+//
+// No subprocesses, so it's fine to run before wait_children's SIGCHLD handler.
+
+use std::net::{TcpStream, ToSocketAddrs};
+use std::thread;
+use std::time::Duration;
+
+use crate::child;
+use crate::config;
+use crate::host;
+
+// Opens a TCP connection to host:port within a timeout, and closes it again.
+pub trait Connect {
+    // true if host:port accepted a connection within timeout
+    fn connect(&self, host: &str, port: u16, timeout: Duration) -> bool;
+}
+
+#[derive(Clone)]
+pub struct TcpConnector;
+
+impl Connect for TcpConnector {
+    fn connect(&self, host: &str, port: u16, timeout: Duration) -> bool {
+        // any of its addresses will do, ssh tries them all too.
+        match (host, port).to_socket_addrs() {
+            Ok(addrs) => addrs
+                .into_iter()
+                .any(|addr| TcpStream::connect_timeout(&addr, timeout).is_ok()),
+            Err(_) => false,
+        }
+    }
+}
+
+// The hostname and port to probe for a host string, e.g. "bob@web1:2222=80x24"
+// The port is the one ssh would be given, see child::effective_port(), else ssh's 22.
+// None if it doesn't parse, those are left for server.rs to complain about.
+fn target<'a>(host_str: &'a str, config: &config::Config) -> Option<(&'a str, u16)> {
+    // a failed --use-all-a-records lookup, see helper.rs
+    let host_str = host_str.trim_end_matches("==");
+    let host = host::parse(host_str)?;
+    let port = match child::effective_port(&host, config) {
+        Some(port) => port.parse().ok()?,
+        None => 22,
+    };
+    Some((host.hostname, port))
+}
+
+// How many probes run at once, each is a thread.
+const MAX_PROBES: usize = 32;
+
+// Drops the hosts which didn't accept a connection, and says which.
+// Like the DNS lookups in cluster.rs the probes go at once, a thread each, but
+// MAX_PROBES at a time, so it takes about one timeout per MAX_PROBES hosts.
+// Each hostname and port is only probed once, e.g. for web1 and bob@web1.
+pub fn probe_hosts<C: Connect + Clone + Send + 'static>(
+    hosts: &mut Vec<String>,
+    config: &config::Config,
+    timeout: Duration,
+    connector: &C,
+) {
+    let mut targets: Vec<(String, u16)> = hosts
+        .iter()
+        .filter_map(|host_str| target(host_str, config))
+        .map(|(hostname, port)| (hostname.to_string(), port))
+        .collect();
+    targets.sort();
+    targets.dedup();
+    let mut up: Vec<(String, u16)> = Vec::new();
+    for batch in targets.chunks(MAX_PROBES) {
+        let probes: Vec<_> = batch
+            .iter()
+            .map(|(hostname, port)| {
+                let connector = connector.clone();
+                let (hostname, port) = (hostname.clone(), *port);
+                thread::spawn(move || connector.connect(&hostname, port, timeout))
+            })
+            .collect();
+        // a probe which panicked counts as down
+        for (target, probe) in batch.iter().zip(probes) {
+            if let Ok(true) = probe.join() {
+                up.push(target.clone());
+            }
+        }
+    }
+
+    hosts.retain(|host_str| match target(host_str, config) {
+        Some((hostname, port)) => {
+            if up.iter().any(|(h, p)| h == hostname && *p == port) {
+                return true;
+            }
+            eprintln!("Skipping {}, nothing answered on port {}", host_str, port);
+            false
+        }
+        None => true,
+    });
+}
+
+#[cfg(test)]
+#[derive(Clone)]
+struct MockConnector {
+    up: Vec<(&'static str, u16)>,
+    tried: std::sync::Arc<std::sync::Mutex<Vec<(String, u16)>>>,
+}
+
+#[cfg(test)]
+impl Connect for MockConnector {
+    fn connect(&self, host: &str, port: u16, timeout: Duration) -> bool {
+        assert_eq!(timeout, Duration::from_millis(500));
+        self.tried.lock().unwrap().push((host.to_string(), port));
+        self.up.contains(&(host, port))
+    }
+}
+
+#[test]
+fn test_target() {
+    let mut config = config::Config::default();
+    assert_eq!(target("web1", &config), Some(("web1", 22)));
+    assert_eq!(target("bob@web1:2222=80x24", &config), Some(("web1", 2222)));
+    assert_eq!(target("10.0.0.1==", &config), Some(("10.0.0.1", 22)));
+
+    // --port, or the config's port, unless the host has its own
+    config.misc.port = Some("2200".to_string());
+    assert_eq!(target("web1%-4", &config), Some(("web1", 2200)));
+    assert_eq!(target("web1:2222", &config), Some(("web1", 2222)));
+    config.misc.port = Some("ssh".to_string());
+    assert_eq!(target("web1", &config), None);
+}
+
+#[test]
+fn test_probe_hosts() {
+    let connector = MockConnector {
+        up: vec![("web1", 22), ("web2", 2222)],
+        tried: Default::default(),
+    };
+    let mut hosts: Vec<String> = [
+        "web1",
+        "web2",
+        "web2:2222",
+        "bob@db1",
+        "web1:2222",
+        "bob@web1",
+    ]
+    .iter()
+    .map(|h| h.to_string())
+    .collect();
+    let config = config::Config::default();
+    probe_hosts(&mut hosts, &config, Duration::from_millis(500), &connector);
+    assert_eq!(hosts, vec!["web1", "web2:2222", "bob@web1"]);
+    // in no particular order, and web1:22 just the once
+    let mut tried = connector.tried.lock().unwrap().clone();
+    tried.sort();
+    assert_eq!(
+        tried,
+        vec![
+            ("db1".to_string(), 22),
+            ("web1".to_string(), 22),
+            ("web1".to_string(), 2222),
+            ("web2".to_string(), 22),
+            ("web2".to_string(), 2222),
+        ]
+    );
+}
+
+#[test]
+fn test_probe_hosts_batches() {
+    let connector = MockConnector {
+        up: vec![("h1", 22), ("h40", 22)],
+        tried: Default::default(),
+    };
+    // more than one batch of MAX_PROBES
+    let mut hosts: Vec<String> = (0..MAX_PROBES + 10).map(|i| format!("h{}", i)).collect();
+    let config = config::Config::default();
+    probe_hosts(&mut hosts, &config, Duration::from_millis(500), &connector);
+    assert_eq!(hosts, vec!["h1", "h40"]);
+    assert_eq!(connector.tried.lock().unwrap().len(), MAX_PROBES + 10);
+}
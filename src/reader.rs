@@ -4,6 +4,8 @@
 //     ignore blank lines
 //     either entire file has lines like "key=value" or is "key value"
 //     and if a line ends with \ then continue on the next line
+//     key=value files may have [section] lines, the section is passed along
+//     with every key after it (None before the first one).
 
 use regex::Regex;
 use std::fs::OpenOptions;
@@ -19,7 +21,7 @@ lazy_static! {
 
 pub fn read_file<F>(p: &Path, is_key_eq_value: bool, f: F) -> Result<()>
 where
-    F: FnMut(&str, &str),
+    F: FnMut(Option<&str>, &str, &str),
 {
     let file = OpenOptions::new().read(true).create_new(false).open(p)?;
 
@@ -31,9 +33,10 @@ where
 fn read_buf<R, F>(mut buf_reader: R, is_key_eq_value: bool, mut f: F) -> Result<()>
 where
     R: BufRead,
-    F: FnMut(&str, &str),
+    F: FnMut(Option<&str>, &str, &str),
 {
     let mut line_string = String::with_capacity(256); // read_line grows line as needed
+    let mut section: Option<String> = None;
     let mut continuation = true; // line (kind of) ended with \
     let mut continuation_start = 0;
     loop {
@@ -87,6 +90,11 @@ where
             continue;
         }
 
+        if is_key_eq_value && line.starts_with('[') && line.ends_with(']') {
+            section = Some(line[1..line.len() - 1].trim().to_string());
+            continue;
+        }
+
         if is_key_eq_value {
             // key=value
             // \s*(\S+)\s*=\s*(.*)
@@ -97,7 +105,7 @@ where
                 let key = key.unwrap().trim_end();
                 let value = value.unwrap().trim_start();
                 if !key.is_empty() {
-                    f(key, value);
+                    f(section.as_ref().map(String::as_str), key, value);
                 }
             }
         } else {
@@ -105,7 +113,7 @@ where
             // \s*(\S+)\s+(.*)
             if let Some(cap) = SPLIT_FIRST_WHITESPACE.captures(line) {
                 if cap.len() == 3 {
-                    f(&cap[1], &cap[2]);
+                    f(None, &cap[1], &cap[2]);
                 }
             }
         }
@@ -153,7 +161,7 @@ more data # ipsum
     let mut i = 0;
 
     let br = BufReader::new(data);
-    let ret = read_buf(br, true, |x, y| {
+    let ret = read_buf(br, true, |_, x, y| {
         println!("{}=>{}", x, y);
         assert_eq!(x, expected[i][0]);
         assert_eq!(y, expected[i][1]);
@@ -196,7 +204,7 @@ more data # ipsum
     let mut i = 0;
 
     let br = BufReader::new(data);
-    let ret = read_buf(br, false, |x, y| {
+    let ret = read_buf(br, false, |_, x, y| {
         println!("{}={}", x, y);
         assert_eq!(x, expected[i][0]);
         assert_eq!(y, expected[i][1]);
@@ -204,3 +212,42 @@ more data # ipsum
     });
     assert_eq!(ret, Ok(()));
 }
+
+#[test]
+fn test_reader_sections() {
+    let data = r"global=1
+		[display :0]
+		panel=left # ...
+		[ display :1 ]  # spaces are trimmed
+		panel=right
+		[]
+		empty=section
+	"
+    .as_bytes();
+
+    let expected = [
+        [None, Some("global"), Some("1")],
+        [Some("display :0"), Some("panel"), Some("left")],
+        [Some("display :1"), Some("panel"), Some("right")],
+        [Some(""), Some("empty"), Some("section")],
+    ];
+    let mut i = 0;
+
+    let br = BufReader::new(data);
+    let ret = read_buf(br, true, |section, x, y| {
+        assert_eq!(section, expected[i][0]);
+        assert_eq!(Some(x), expected[i][1]);
+        assert_eq!(Some(y), expected[i][2]);
+        i += 1;
+    });
+    assert_eq!(ret, Ok(()));
+    assert_eq!(i, expected.len());
+
+    // not in key value files, e.g. a tag file's host
+    let br = BufReader::new("[::1] web\n".as_bytes());
+    let ret = read_buf(br, false, |section, x, y| {
+        assert_eq!(section, None);
+        assert_eq!((x, y), ("[::1]", "web"));
+    });
+    assert_eq!(ret, Ok(()));
+}
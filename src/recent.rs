@@ -0,0 +1,70 @@
+// The most recently used tags, for the top of the Add Host(s) dialog.
+//
+// Kept in $CONFIG_DIR/recent as one cluster file style line,
+//     recent web db staging
+// most recent first, so reader.rs reads it like any other.
+
+use std::fs;
+use std::path::Path;
+
+use crate::er::Result;
+use crate::reader;
+
+pub fn read(filename: &Path) -> Vec<String> {
+    let mut recent = Vec::new();
+    if filename.exists() {
+        let read = reader::read_file(filename, false, |_, key, value| {
+            if key == "recent" {
+                recent = value
+                    .split_whitespace()
+                    .map(std::string::ToString::to_string)
+                    .collect();
+            }
+        });
+        if let Err(e) = read {
+            eprintln!("Warn: Ignoring {}. {}", filename.to_string_lossy(), e);
+        }
+    }
+    recent
+}
+
+pub fn write(filename: &Path, recent: &[String]) -> Result<()> {
+    fs::write(filename, format!("recent {}\n", recent.join(" ")))?;
+    Ok(())
+}
+
+// Moves the used tags to the front, keeping at most max.
+// true if that changed anything, so there's no need to write the same again.
+pub fn record(recent: &mut Vec<String>, used: &[&str], max: usize) -> bool {
+    let before = recent.clone();
+    for tag in used.iter().rev() {
+        recent.retain(|r| r != tag);
+        recent.insert(0, tag.to_string());
+    }
+    recent.truncate(max);
+    *recent != before
+}
+
+#[test]
+fn test_record() {
+    let mut recent = Vec::new();
+    assert!(record(&mut recent, &["web", "db"], 3));
+    assert_eq!(recent, vec!["web", "db"]);
+    assert!(!record(&mut recent, &["web"], 3));
+    assert!(record(&mut recent, &["db"], 3));
+    assert_eq!(recent, vec!["db", "web"]);
+    assert!(record(&mut recent, &["a", "b"], 3));
+    assert_eq!(recent, vec!["a", "b", "db"]);
+    assert!(record(&mut recent, &[], 1));
+    assert_eq!(recent, vec!["a"]);
+}
+
+#[test]
+fn test_read_write() {
+    let filename = crate::tmpnam::tmpnam().unwrap();
+    assert!(read(&filename).is_empty());
+    let recent = vec!["web".to_string(), "db".to_string()];
+    write(&filename, &recent).unwrap();
+    assert_eq!(read(&filename), recent);
+    std::fs::remove_file(&filename).unwrap();
+}
@@ -13,9 +13,40 @@
 // That proved to me that this works, but a unit test for that, isn't happening.
 
 use futures::future;
+use std::net::ToSocketAddrs;
 use tokio::runtime::current_thread::Runtime;
 use trust_dns_resolver::AsyncResolver;
 
+// Looks up the IPs of many hosts at once, with libc's resolver for the stragglers.
+pub trait Resolve {
+    // see ResolverWrapper::resolve()
+    fn resolve<F, G>(&mut self, hosts: Vec<String>, f: F, g: G)
+    where
+        F: FnMut(String, Vec<String>),
+        G: FnMut(String, String);
+
+    // For resolver_use_libc_fallback, when trust-dns came up empty.
+    fn resolve_libc(&mut self, host: &str) -> Vec<String> {
+        resolve_libc(host)
+    }
+}
+
+// libc's getaddrinfo() (via std), which honors /etc/hosts and nsswitch.conf,
+// unlike trust-dns which only asks the nameservers in /etc/resolv.conf.
+// One at a time, it blocks, but only hosts trust-dns failed on get here.
+pub fn resolve_libc(host: &str) -> Vec<String> {
+    let mut ips: Vec<String> = Vec::new();
+    if let Ok(addrs) = (host, 0).to_socket_addrs() {
+        for addr in addrs {
+            let ip = addr.ip().to_string();
+            if !ips.contains(&ip) {
+                ips.push(ip);
+            }
+        }
+    }
+    ips
+}
+
 pub struct ResolverWrapper {
     async_resolver: AsyncResolver,
     runtime: Runtime,
@@ -77,3 +108,19 @@ impl ResolverWrapper {
         }
     }
 }
+
+impl Resolve for ResolverWrapper {
+    fn resolve<F, G>(&mut self, hosts: Vec<String>, f: F, g: G)
+    where
+        F: FnMut(String, Vec<String>),
+        G: FnMut(String, String),
+    {
+        ResolverWrapper::resolve(self, hosts, f, g)
+    }
+}
+
+#[test]
+fn test_resolve_libc() {
+    assert_eq!(resolve_libc("127.0.0.1"), vec!["127.0.0.1"]);
+    assert_eq!(resolve_libc("::1"), vec!["::1"]);
+}
@@ -19,6 +19,7 @@ pub trait RetileXDisplay {
 pub trait RetileApp<X: RetileXDisplay> {
     fn get_config(&self) -> &config::Config;
     fn get_servers(&self) -> &BTreeMap<String, server::Server>;
+    fn get_servers_mut(&mut self) -> &mut BTreeMap<String, server::Server>;
     fn get_font_wh(&self) -> (u32, u32);
 
     fn show_console(&mut self) -> Result<()>;
@@ -27,9 +28,40 @@ pub trait RetileApp<X: RetileXDisplay> {
     fn get_xdisplay(&self) -> &X;
 }
 
+// Which servers a retile places, the rest are left where they are.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RetileScope {
+    All,
+    Active,
+    Inactive,
+}
+
+impl RetileScope {
+    fn matches(self, server: &server::Server) -> bool {
+        match self {
+            RetileScope::All => true,
+            RetileScope::Active => server.active,
+            RetileScope::Inactive => !server.active,
+        }
+    }
+
+    // pinned servers were placed by their host=geometry, so they are never tiled.
+    fn tiles(self, server: &server::Server) -> bool {
+        !server.pinned && self.matches(server)
+    }
+}
+
 pub fn retile_hosts<X: RetileXDisplay, T: RetileApp<X>>(
     app: &mut T,
     raise: bool,
+) -> Result<(bool)> {
+    retile_hosts_in(app, raise, RetileScope::All)
+}
+
+pub fn retile_hosts_in<X: RetileXDisplay, T: RetileApp<X>>(
+    app: &mut T,
+    raise: bool,
+    scope: RetileScope,
 ) -> Result<(bool)> {
     // rust release mode will panic on overflow.
     // Even though that's unlikely, we really should check, instead of crash.
@@ -37,7 +69,11 @@ pub fn retile_hosts<X: RetileXDisplay, T: RetileApp<X>>(
     // followed by all the checks.
     // FWIW: Code is auto-formatted via 'rust fmt'.
 
-    let n_servers = app.get_servers().len() as u32;
+    let n_servers = app
+        .get_servers()
+        .values()
+        .filter(|s| scope.tiles(s))
+        .count() as u32;
     if n_servers == 0 {
         app.show_console()?;
         return Ok(true);
@@ -78,10 +114,11 @@ pub fn retile_hosts<X: RetileXDisplay, T: RetileApp<X>>(
     //let columns = (screen_w - c.screen.reserve_left - c.screen.reserve_right)
     //    / (w + c.terminal.reserve_left + c.terminal.reserve_right);
     // First compute denominator (it's re-used later).
-    // let w_reserve = w + c.terminal.reserve_left + c.terminal.reserve_right;
+    // let w_reserve = w + c.terminal.reserve_left + c.terminal.reserve_right + c.terminal.grid_gap_x;
     let w_reserve = match w
         .checked_add(c.terminal.reserve_left)
         .and_then(|tmp| tmp.checked_add(c.terminal.reserve_right))
+        .and_then(|tmp| tmp.checked_add(c.terminal.grid_gap_x))
     {
         Some(tmp) if tmp > 0 => tmp,
         _ => return Err("retile overflow".into()),
@@ -109,7 +146,8 @@ pub fn retile_hosts<X: RetileXDisplay, T: RetileApp<X>>(
     // or to get everything on screen
     //let h = {
     //    let height = ((screen_h - c.screen.reserve_top - c.screen.reserve_bottom)
-    //        - (rows * (c.terminal.reserve_top + c.terminal.reserve_bottom)))
+    //        - (rows * (c.terminal.reserve_top + c.terminal.reserve_bottom))
+    //        - ((rows - 1) * c.terminal.grid_gap_y))
     //        / rows;
     //
     //    if height > h {
@@ -121,7 +159,7 @@ pub fn retile_hosts<X: RetileXDisplay, T: RetileApp<X>>(
     let h = {
         let height = {
             screen_h
-                .checked_sub(c.screen.reserve_top)
+                .checked_sub(c.screen.effective_reserve_top())
                 .and_then(|tmp| tmp.checked_sub(c.screen.reserve_bottom))
                 .and_then(|a| {
                     c.terminal
@@ -129,6 +167,11 @@ pub fn retile_hosts<X: RetileXDisplay, T: RetileApp<X>>(
                         .checked_add(c.terminal.reserve_bottom)
                         .and_then(|tmp| rows.checked_mul(tmp))
                         .and_then(|tmp| a.checked_sub(tmp))
+                        .and_then(|tmp| {
+                            (rows - 1) // rows > 0, checked above
+                                .checked_mul(c.terminal.grid_gap_y)
+                                .and_then(|gaps| tmp.checked_sub(gaps))
+                        })
                         .and_then(|tmp| tmp.checked_div(rows))
                 })
         };
@@ -141,30 +184,70 @@ pub fn retile_hosts<X: RetileXDisplay, T: RetileApp<X>>(
 
     // now we have the info, plot window positions
     if c.misc.window_tiling_right {
-        tile_right(app, w, h, columns, w_reserve)?;
+        tile_right(app, w, h, columns, w_reserve, raise, scope)?;
     } else {
-        tile_left(app, w, h, screen_w, screen_h)?;
+        tile_left(app, w, h, screen_w, screen_h, raise, scope)?;
     }
 
     // Now remap in right order to get overlaps correct
-    for (_, ref mut server) in app.get_servers().iter().rev() {
+    let servers = server::in_order(app.get_servers(), c.misc.order_by_given);
+    for (_, server) in servers.iter().rev().filter(|(_, s)| scope.matches(s)) {
         xdisplay.map_window(server.wid);
         if raise {
             xdisplay.raise_window(server.wid);
         }
         xdisplay.flush();
-        app.sleep(100); // sleep for a moment for the WM (if --sleep)
+        app.sleep(c.tcssh.map_sleep_ms); // sleep for a moment for the WM (if --sleep)
     }
 
+    let order_by_given = c.misc.order_by_given;
+    let columns = if c.misc.window_tiling_right {
+        Some(columns)
+    } else {
+        None
+    };
+    set_grid_positions(app.get_servers_mut(), order_by_given, columns, scope);
+
     Ok(false)
 }
 
+// Record where tile_right() put each server, for App::activate_grid_position().
+// tile_left() stacks them all in one place, so there is no grid (columns is None).
+// Those outside the scope may well be under the new grid, so they lose theirs.
+fn set_grid_positions(
+    servers: &mut BTreeMap<String, server::Server>,
+    order_by_given: bool,
+    columns: Option<u32>,
+    scope: RetileScope,
+) {
+    let mut i = 0;
+    for (_, server) in server::in_order_mut(servers, order_by_given) {
+        server.grid_position = match columns {
+            Some(columns) if scope.tiles(server) => {
+                i += 1;
+                Some(((i - 1) / columns, (i - 1) % columns))
+            }
+            _ => None,
+        };
+    }
+}
+
+// Raise and Retile has its own unmap_on_raise, if set.
+fn unmap_before_move(misc: &config::Misc, raise: bool) -> bool {
+    match misc.unmap_on_raise {
+        Some(unmap_on_raise) if raise => unmap_on_raise,
+        _ => misc.unmap_on_redraw,
+    }
+}
+
 fn tile_right<X: RetileXDisplay, T: RetileApp<X>>(
     app: &T,
     width: u32,
     height: u32,
     columns: u32,
     w_reserve: u32,
+    raise: bool,
+    scope: RetileScope,
 ) -> Result<()> {
     let c = &app.get_config();
 
@@ -175,32 +258,34 @@ fn tile_right<X: RetileXDisplay, T: RetileApp<X>>(
         .checked_add(c.terminal.reserve_left)
         .unwrap_or(c.screen.reserve_left);
     let mut x = default_x;
-    //let mut y = c.screen.reserve_top + c.terminal.reserve_top;
-    let mut y = c
-        .screen
-        .reserve_top
+    //let mut y = c.screen.reserve_top + c.screen.reserve_for_console + c.terminal.reserve_top;
+    let reserve_top = c.screen.effective_reserve_top();
+    let mut y = reserve_top
         .checked_add(c.terminal.reserve_top)
-        .unwrap_or(c.screen.reserve_top);
+        .unwrap_or(reserve_top);
     let mut column = 0;
-    //let h_reserve = c.terminal.reserve_top + c.terminal.reserve_bottom + height;
+    //let h_reserve = c.terminal.reserve_top + c.terminal.reserve_bottom + height + c.terminal.grid_gap_y;
     let h_reserve = c
         .terminal
         .reserve_top
         .checked_add(c.terminal.reserve_bottom)
         .and_then(|tmp| tmp.checked_add(height))
+        .and_then(|tmp| tmp.checked_add(c.terminal.grid_gap_y))
         .unwrap_or(height);
 
     // Unmap windows (hide them)
     // Move windows to new locatation
     // Remap all windows in correct order
+    let unmap = unmap_before_move(&c.misc, raise);
     let xdisplay = app.get_xdisplay();
-    for (_, ref server) in app.get_servers().iter() {
-        if c.misc.unmap_on_redraw {
+    let servers = server::in_order(app.get_servers(), c.misc.order_by_given);
+    for (_, server) in servers.iter().filter(|(_, s)| scope.tiles(s)) {
+        if unmap {
             xdisplay.unmap_window(server.wid);
         }
         app.send_resizemove(server.wid, x, y, width, height)?;
         xdisplay.flush();
-        app.sleep(100); // sleep for a moment for the WM (if --sleep)
+        app.sleep(c.tcssh.retile_sleep_ms); // sleep for a moment for the WM (if --sleep)
 
         // starting top left, and move right and down
         column += 1;
@@ -229,6 +314,8 @@ fn tile_left<X: RetileXDisplay, T: RetileApp<X>>(
     height: u32,
     screen_w: u32,
     screen_h: u32,
+    raise: bool,
+    scope: RetileScope,
 ) -> Result<()> {
     let c = &app.get_config();
     // perl cssh left tiling seems buggy.
@@ -261,14 +348,16 @@ fn tile_left<X: RetileXDisplay, T: RetileApp<X>>(
         .and_then(|tmp| tmp.checked_sub(height))
         .unwrap_or(0);
 
+    let unmap = unmap_before_move(&c.misc, raise);
     let xdisplay = app.get_xdisplay();
-    for (_, ref server) in app.get_servers().iter().rev() {
-        if c.misc.unmap_on_redraw {
+    let servers = server::in_order(app.get_servers(), c.misc.order_by_given);
+    for (_, server) in servers.iter().rev().filter(|(_, s)| scope.tiles(s)) {
+        if unmap {
             xdisplay.unmap_window(server.wid);
         }
         app.send_resizemove(server.wid, x, y, width, height)?;
         xdisplay.flush();
-        app.sleep(100); // sleep for a moment for the WM (if --sleep)
+        app.sleep(c.tcssh.retile_sleep_ms); // sleep for a moment for the WM (if --sleep)
     }
     Ok(())
 }
@@ -352,6 +441,9 @@ mod retile_tests {
         fn get_servers(&self) -> &BTreeMap<String, server::Server> {
             &self.servers
         }
+        fn get_servers_mut(&mut self) -> &mut BTreeMap<String, server::Server> {
+            &mut self.servers
+        }
         fn get_font_wh(&self) -> (u32, u32) {
             (self.font_w, self.font_h)
         }
@@ -387,6 +479,10 @@ mod retile_tests {
             username: None,
             pipenm: None,
             menu_item: None,
+            pinned: false,
+            seq: 0,
+            grid_position: None,
+            geometry: Default::default(),
         }
     }
 
@@ -591,6 +687,18 @@ mod retile_tests {
         expected.push(TestEvent::Map { wid: 1 });
 
         assert_eq!(got, expected);
+
+        let positions: Vec<_> = scenario
+            .app
+            .servers
+            .values()
+            .map(|s| s.grid_position)
+            .collect();
+        assert_eq!(positions, vec![Some((0, 0)), Some((0, 1)), Some((1, 0))]);
+        let at = |row, col| server::at_grid_position(&scenario.app.servers, row, col);
+        assert_eq!(at(1, 0).map(String::as_str), Some("30"));
+        assert_eq!(at(1, 1), None); // the gap in the "r"
+        assert_eq!(at(0, 2), None); // beyond the grid
     }
 
     #[test]
@@ -701,6 +809,45 @@ mod retile_tests {
         assert_eq!(got, expected);
     }
 
+    #[test]
+    fn test_pinned_not_moved() {
+        // pin the middle server, the other two tile as if it wasn't there.
+        let mut scenario = new_scenario();
+        if let Some(server) = scenario.app.servers.get_mut("20") {
+            server.pinned = true;
+        }
+
+        let result = retile_hosts(&mut scenario.app, false);
+        assert_eq!(result, Ok(false));
+
+        let got = filter_test_events(&scenario);
+
+        let mut expected = Vec::new();
+        //        #[rustfmt::skip]
+        {
+            expected.push(TestEvent::Move {
+                wid: 1,
+                x: 7,
+                y: 4,
+                w: 648,
+                h: 394,
+            });
+            expected.push(TestEvent::Move {
+                wid: 3,
+                x: 7,
+                y: 402,
+                w: 648,
+                h: 394,
+            });
+        }
+        // pinned windows are still mapped, just not moved.
+        expected.push(TestEvent::Map { wid: 3 });
+        expected.push(TestEvent::Map { wid: 2 });
+        expected.push(TestEvent::Map { wid: 1 });
+
+        assert_eq!(got, expected);
+    }
+
     #[test]
     fn test_overflow() {
         // Trigger the last overflow (subtracting right screen padding)
@@ -717,4 +864,238 @@ mod retile_tests {
         let result = retile_hosts(&mut scenario.app, false);
         assert!(result.is_err());
     }
+
+    #[test]
+    fn test_sleep_ms_from_config() {
+        let mut scenario = new_scenario();
+        scenario.app.config.tcssh.retile_sleep_ms = 7;
+        scenario.app.config.tcssh.map_sleep_ms = 3;
+
+        let result = retile_hosts(&mut scenario.app, false);
+        assert_eq!(result, Ok(false));
+
+        let got: Vec<u64> = scenario
+            .app
+            .test_events
+            .borrow()
+            .iter()
+            .filter_map(|e| match e {
+                TestEvent::Sleep { ms } => Some(*ms),
+                _ => None,
+            })
+            .collect();
+        // one sleep per move, then one per map
+        assert_eq!(got, vec![7, 7, 7, 3, 3, 3]);
+    }
+
+    #[test]
+    fn test_retile_order_given() {
+        // hosts given as "20 30 10"
+        let mut scenario = new_scenario();
+        for (key, seq) in [("20", 1), ("30", 2), ("10", 3)].iter() {
+            scenario.app.servers.get_mut(*key).unwrap().seq = *seq;
+        }
+        scenario.app.config.misc.order_by_given = true;
+
+        let result = retile_hosts(&mut scenario.app, false);
+        assert_eq!(result, Ok(false));
+
+        let got: Vec<Wid> = filter_test_events(&scenario)
+            .iter()
+            .filter_map(|e| match e {
+                TestEvent::Move { wid, .. } => Some(*wid),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(got, vec![2, 3, 1]);
+
+        // and by name (the default) ignores seq
+        scenario.app.config.misc.order_by_given = false;
+        retile_hosts(&mut scenario.app, false).unwrap();
+        let got: Vec<Wid> = filter_test_events(&scenario)
+            .iter()
+            .filter_map(|e| match e {
+                TestEvent::Move { wid, .. } => Some(*wid),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(got, vec![1, 2, 3]);
+    }
+
+    fn get_moves(scenario: &Scenario) -> Vec<(u32, u32, u32, u32)> {
+        filter_test_events(scenario)
+            .iter()
+            .filter_map(|e| match e {
+                TestEvent::Move { x, y, w, h, .. } => Some((*x, *y, *w, *h)),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_grid_gap_x() {
+        // narrow terminals, side by side, as in test_retile_3_horizontal
+        let mut scenario = new_scenario();
+        scenario.app.config.terminal.terminal_size_x = 8; // columns
+        retile_hosts(&mut scenario.app, false).unwrap();
+        let without_gap = get_moves(&scenario);
+
+        scenario.app.config.terminal.grid_gap_x = 20;
+        retile_hosts(&mut scenario.app, false).unwrap();
+        let with_gap = get_moves(&scenario);
+
+        assert_eq!(without_gap.len(), 3);
+        for (i, (before, after)) in without_gap.iter().zip(with_gap.iter()).enumerate() {
+            assert_eq!(after.0, before.0 + 20 * i as u32); // spread apart
+            assert_eq!(after.1, before.1);
+            assert_eq!((after.2, after.3), (before.2, before.3)); // same size
+        }
+    }
+
+    #[test]
+    fn test_grid_gap_y() {
+        // short terminals, which fit on screen stacked, even with the gap.
+        let mut scenario = new_scenario();
+        scenario.app.config.terminal.terminal_size_y = 10; // rows
+        retile_hosts(&mut scenario.app, false).unwrap();
+        let without_gap = get_moves(&scenario);
+
+        scenario.app.config.terminal.grid_gap_y = 30;
+        retile_hosts(&mut scenario.app, false).unwrap();
+        let with_gap = get_moves(&scenario);
+
+        assert_eq!(without_gap.len(), 3);
+        for (i, (before, after)) in without_gap.iter().zip(with_gap.iter()).enumerate() {
+            assert_eq!(after.0, before.0);
+            assert_eq!(after.1, before.1 + 30 * i as u32); // spread apart
+            assert_eq!((after.2, after.3), (before.2, before.3)); // same size
+        }
+
+        // whereas terminal reserves also spread them, but are part of the decoration math
+        // so squeeze the windows when the screen is full (see test_retile_3_vertical)
+    }
+
+    #[test]
+    fn test_reserve_for_console() {
+        let mut scenario = new_scenario();
+        assert_eq!(scenario.app.config.screen.effective_reserve_top(), 1);
+        scenario.app.config.terminal.terminal_size_y = 10; // rows, so there's room to move down
+        retile_hosts(&mut scenario.app, false).unwrap();
+        let without = get_moves(&scenario);
+
+        scenario.app.config.screen.reserve_for_console = 100;
+        assert_eq!(scenario.app.config.screen.effective_reserve_top(), 101);
+        retile_hosts(&mut scenario.app, false).unwrap();
+        let with = get_moves(&scenario);
+
+        assert_eq!(without.len(), 3);
+        for (before, after) in without.iter().zip(with.iter()) {
+            assert_eq!(after.1, before.1 + 100); // all moved down, below the console
+            assert_eq!((after.0, after.2, after.3), (before.0, before.2, before.3));
+        }
+    }
+
+    #[test]
+    fn test_unmap_on_raise() {
+        fn unmaps(scenario: &mut Scenario, raise: bool) -> usize {
+            retile_hosts(&mut scenario.app, raise).unwrap();
+            let mut test_events = scenario.app.test_events.borrow_mut();
+            test_events
+                .drain(..)
+                .filter(|e| match e {
+                    TestEvent::Unmap { wid: _ } => true,
+                    _ => false,
+                })
+                .count()
+        }
+
+        for &right in &[true, false] {
+            let mut scenario = new_scenario();
+            scenario.app.config.misc.window_tiling_right = right;
+
+            // unset, raise follows unmap_on_redraw
+            assert_eq!(unmaps(&mut scenario, false), 0);
+            assert_eq!(unmaps(&mut scenario, true), 0);
+            scenario.app.config.misc.unmap_on_redraw = true;
+            assert_eq!(unmaps(&mut scenario, false), 3);
+            assert_eq!(unmaps(&mut scenario, true), 3);
+
+            // unmap on retile, but not on raise
+            scenario.app.config.misc.unmap_on_raise = Some(false);
+            assert_eq!(unmaps(&mut scenario, false), 3);
+            assert_eq!(unmaps(&mut scenario, true), 0);
+
+            // and vice versa
+            scenario.app.config.misc.unmap_on_redraw = false;
+            scenario.app.config.misc.unmap_on_raise = Some(true);
+            assert_eq!(unmaps(&mut scenario, false), 0);
+            assert_eq!(unmaps(&mut scenario, true), 3);
+        }
+    }
+
+    #[test]
+    fn test_retile_active_subset() {
+        // 2 of 3 active, so only those two are tiled (as if there were only two)
+        let mut scenario = new_scenario();
+        scenario.app.servers.get_mut("20").unwrap().active = false;
+
+        let result = retile_hosts_in(&mut scenario.app, false, RetileScope::Active);
+        assert_eq!(result, Ok(false));
+
+        let got = filter_test_events(&scenario);
+        let expected = vec![
+            TestEvent::Move {
+                wid: 1,
+                x: 7,
+                y: 4,
+                w: 648,
+                h: 394,
+            },
+            TestEvent::Move {
+                wid: 3,
+                x: 7,
+                y: 402,
+                w: 648,
+                h: 394,
+            },
+            TestEvent::Map { wid: 3 },
+            TestEvent::Map { wid: 1 },
+        ];
+        assert_eq!(got, expected);
+        let positions: Vec<_> = scenario
+            .app
+            .servers
+            .values()
+            .map(|s| s.grid_position)
+            .collect();
+        assert_eq!(positions, vec![Some((0, 0)), None, Some((1, 0))]);
+
+        // and the other one by itself
+        retile_hosts_in(&mut scenario.app, false, RetileScope::Inactive).unwrap();
+        let got = filter_test_events(&scenario);
+        assert_eq!(
+            got,
+            vec![
+                TestEvent::Move {
+                    wid: 2,
+                    x: 7,
+                    y: 4,
+                    w: 648,
+                    h: 394,
+                },
+                TestEvent::Map { wid: 2 },
+            ]
+        );
+
+        // nothing matches, so just the console
+        scenario.app.servers.get_mut("20").unwrap().active = true;
+        assert_eq!(
+            retile_hosts_in(&mut scenario.app, false, RetileScope::Inactive),
+            Ok(true)
+        );
+        assert_eq!(
+            filter_test_events(&scenario),
+            vec![TestEvent::ShowConsole {}]
+        );
+    }
 }
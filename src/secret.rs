@@ -0,0 +1,155 @@
+// Send -> Password, types the first line password_command prints into every
+// active session, followed by Return, without it going through the console.
+//
+// e.g. password_command=/home/bob/bin/tcssh-pass
+//     #!/bin/sh
+//     exec pass show work/ssh
+//
+// The trade-offs, for deciding whether to set password_command at all:
+// - It goes to every active session, whatever each is doing. One that isn't
+//   sitting at a password prompt echoes it, into its scrollback and maybe its
+//   shell history. Check what's active first.
+// - It's sent as synthetic key events, which any X client on the display can
+//   watch, like it can watch real typing. Each xterm must allow send events.
+// - The command is run with our privileges, so like external_cluster_command
+//   it's only read from config files which are ours (config.rs COMMAND_KEYS).
+// - Our copies are zeroed once sent. The command's pipe, the X server's event
+//   queue and the xterms' own copies are out of our hands.
+// - It isn't logged, not even with DEBUG_SEND, and macros aren't applied.
+
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::sync::atomic::{compiler_fence, Ordering};
+
+use crate::er::Result;
+use crate::is_xfile::IsExecutableFile;
+use crate::wait_children;
+
+// Runs a command and returns what it printed on stdout.
+pub trait Capture {
+    fn capture(&self, cmd: &Path) -> Result<Vec<u8>>;
+}
+
+pub struct CommandCapture;
+
+impl Capture for CommandCapture {
+    fn capture(&self, cmd: &Path) -> Result<Vec<u8>> {
+        if !cmd.is_executable_file() {
+            return Err("password_command is not executable".into());
+        }
+        // App::send_secret() calls us via wait_children::without_sig_chld_handler()
+        if wait_children::is_our_sig_handler_installed() {
+            return Err(
+                "assertion failure. sig handler will interfere with spawned commands".into(),
+            );
+        }
+        // stderr is left alone, for the command's own complaints and prompts.
+        let mut output = Command::new(cmd).stderr(Stdio::inherit()).output()?;
+        if let Some(status) = output.status.code() {
+            if status != 0 {
+                wipe_bytes(&mut output.stdout);
+                return Err(format!(
+                    "password_command {} failed, Return Code: [{}]",
+                    cmd.to_string_lossy(),
+                    status
+                )
+                .into());
+            }
+        }
+        Ok(output.stdout)
+    }
+}
+
+// The first line of the command's output, without its newline.
+pub fn read_secret<C: Capture>(cmd: &Path, capturer: &C) -> Result<String> {
+    let mut output = capturer.capture(cmd)?;
+    let mut len = output
+        .iter()
+        .position(|&b| b == b'\n')
+        .unwrap_or(output.len());
+    if len > 0 && output[len - 1] == b'\r' {
+        len -= 1;
+    }
+    // the errors don't say what was read, it could be most of the password.
+    let secret = match std::str::from_utf8(&output[..len]) {
+        Ok("") => Err("password_command printed no password".into()),
+        Ok(secret) => Ok(String::from(secret)),
+        Err(_) => Err("password_command's output is not valid utf8".into()),
+    };
+    wipe_bytes(&mut output);
+    secret
+}
+
+// Zeroes the String's bytes before emptying it, so the password doesn't
+// linger in freed memory. Volatile so the writes aren't optimized away.
+pub fn wipe(secret: &mut String) {
+    // all zeros is valid utf8
+    wipe_bytes(unsafe { secret.as_mut_vec() });
+    secret.clear();
+}
+
+fn wipe_bytes(bytes: &mut Vec<u8>) {
+    zero_bytes(bytes);
+    bytes.clear();
+}
+
+fn zero_bytes(bytes: &mut [u8]) {
+    for b in bytes.iter_mut() {
+        unsafe { std::ptr::write_volatile(b, 0) };
+    }
+    compiler_fence(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+struct MockCapture {
+    output: std::result::Result<&'static [u8], &'static str>,
+}
+
+#[cfg(test)]
+impl Capture for MockCapture {
+    fn capture(&self, cmd: &Path) -> Result<Vec<u8>> {
+        assert_eq!(cmd, Path::new("/usr/bin/pass-for-tcssh"));
+        match self.output {
+            Ok(output) => Ok(output.to_vec()),
+            Err(e) => Err(e.into()),
+        }
+    }
+}
+
+#[test]
+fn test_read_secret() {
+    fn read(output: std::result::Result<&'static [u8], &'static str>) -> Result<String> {
+        read_secret(
+            Path::new("/usr/bin/pass-for-tcssh"),
+            &MockCapture { output },
+        )
+    }
+    assert_eq!(read(Ok(b"hunter2\n")), Ok("hunter2".to_string()));
+    assert_eq!(read(Ok(b"hunter2")), Ok("hunter2".to_string()));
+    assert_eq!(read(Ok(b"hunter2\r\n")), Ok("hunter2".to_string()));
+    assert_eq!(read(Ok(b"p%h ss\nsecond line\n")), Ok("p%h ss".to_string()));
+    assert_eq!(
+        read(Ok(b"\nhunter2\n")),
+        Err("password_command printed no password".into())
+    );
+    assert_eq!(
+        read(Ok(b"")),
+        Err("password_command printed no password".into())
+    );
+    assert_eq!(
+        read(Ok(b"hunter\xff\n")),
+        Err("password_command's output is not valid utf8".into())
+    );
+    assert_eq!(read(Err("no agent")), Err("no agent".into()));
+}
+
+#[test]
+fn test_wipe() {
+    let mut secret = String::from("hunter2");
+    wipe(&mut secret);
+    assert!(secret.is_empty());
+
+    let mut bytes = b"hunter2".to_vec();
+    zero_bytes(&mut bytes);
+    assert_eq!(bytes, [0u8; 7]);
+}
@@ -1,9 +1,22 @@
 // This sends text to all servers which are flagged as active.
 use libc;
+use std::collections::BTreeMap;
+use x11::keysym;
 
 use crate::app;
 use crate::app::Wid;
+use crate::config::{InputEncoding, Macros};
+use crate::er::Result;
+use crate::log;
 use crate::macros;
+use crate::server::Server;
+use crate::text2x11::{StateCode, Text2X11};
+
+// Sends key events to our windows, XDisplay's XSendEvent in x.rs.
+pub trait SendSink {
+    fn send_event(&self, wid: Wid, state: u32, keycode: u32) -> Result<()>;
+    fn flush(&self);
+}
 
 enum SendTo {
     All {},
@@ -26,20 +39,21 @@ pub fn send_variable_text(app: &mut app::App) {
         let rand = unsafe { libc::rand() };
         let rand_1024 = rand / ((libc::RAND_MAX / 1024) + 1);
         let text = format!("{}", rand_1024);
-        translate_and_send(&text, app, SendTo::One { wid: server.wid });
+        send(&text, app, SendTo::One { wid: server.wid });
     }
-    app.xdisplay.flush();
 }
 
-pub fn send_text(app: &mut app::App, text: &str) {
+// returns the number of active servers the text was sent to.
+pub fn send_text(app: &mut app::App, text: &str) -> usize {
     let macros_enabled = app.config.macros.enabled;
+    let n_targets = app.servers.values().filter(|s| s.active).count();
 
     for (ref server_key, ref server) in app.servers.iter() {
         if !server.active {
             continue;
         }
         if !macros_enabled {
-            translate_and_send(&text, app, SendTo::All {});
+            send(&text, app, SendTo::All {});
             break;
         }
 
@@ -51,46 +65,619 @@ pub fn send_text(app: &mut app::App, text: &str) {
             &server.username,
         ) {
             macros::Subst::None => {
-                translate_and_send(text, app, SendTo::All {});
+                send(text, app, SendTo::All {});
                 break;
             }
             macros::Subst::Same { text } => {
-                translate_and_send(&text, app, SendTo::All {});
+                send(&text, app, SendTo::All {});
                 break;
             }
             macros::Subst::Diff { text } => {
-                translate_and_send(&text, app, SendTo::One { wid: server.wid });
+                send(&text, app, SendTo::One { wid: server.wid });
             }
         }
     }
-    app.xdisplay.flush();
+    n_targets
+}
+
+// Like send_text, but to just the one server with this wid, active or not.
+// returns the number of servers the text was sent to (0 or 1).
+pub fn send_text_to(app: &app::App, wid: Wid, text: &str) -> usize {
+    let (server_key, server) = match app.servers.iter().find(|(_, s)| s.wid == wid) {
+        Some(found) => found,
+        None => return 0,
+    };
+    let to = SendTo::One { wid };
+    if !app.config.macros.enabled {
+        send(text, app, to);
+        return 1;
+    }
+    match macros::substitute(
+        text,
+        &app.config.macros,
+        server_key,
+        &server.givenname,
+        &server.username,
+    ) {
+        macros::Subst::None => send(text, app, to),
+        macros::Subst::Same { text } => send(&text, app, to),
+        macros::Subst::Diff { text } => send(&text, app, to),
+    }
+    1
+}
+
+// For Send -> Password, the secret then Return to every active server.
+// No macros (a % in a password is just a %) and no DEBUG_SEND logging, see secret.rs
+// returns the number of active servers it was sent to.
+pub fn send_secret(app: &app::App, secret: &str) -> Result<usize> {
+    let text2x11 = match app.text2x11 {
+        Some(ref text2x11) => text2x11,
+        None => return Ok(0),
+    };
+    let encoding = app.config.misc.input_encoding;
+    // translate_and_send() names the characters it can't type, so refuse those up front.
+    let typeable = |c| {
+        encode(c, encoding)
+            .and_then(|c| translate(c, text2x11))
+            .is_some()
+    };
+    if !secret.chars().all(typeable) {
+        return Err("the password has characters this keyboard can't type".into());
+    }
+    for text in &[secret, "\n"] {
+        translate_and_send(
+            text,
+            encoding,
+            text2x11,
+            &app.servers,
+            &app.xdisplay,
+            SendTo::All {},
+        );
+    }
+    Ok(app.servers.values().filter(|s| s.active).count())
+}
+
+// For Send -> Special Keys, e.g. Ctrl-C is the keysym 'c' with ControlMask.
+// returns the number of active servers it was sent to.
+pub fn send_keysym(app: &app::App, keysym: u32, state: u32) -> usize {
+    match app.text2x11 {
+        Some(ref text2x11) => keysym_and_send(keysym, state, text2x11, &app.servers, &app.xdisplay),
+        None => 0,
+    }
+}
+
+fn keysym_and_send<S: SendSink>(
+    keysym: u32,
+    state: u32,
+    text2x11: &Text2X11,
+    servers: &BTreeMap<String, Server>,
+    sink: &S,
+) -> usize {
+    let sc = match text2x11.keysym_to_statecode(keysym) {
+        Some(sc) => sc,
+        None => {
+            eprintln!("Unknown keysym in xmodmap keytable: {:x}", keysym);
+            return 0;
+        }
+    };
+    log!(
+        macros::DEBUG_SEND,
+        "keysym {:x} state {:x} as keycode {} state {:x}",
+        keysym,
+        state,
+        sc.code,
+        sc.state | state
+    );
+    let mut n_targets = 0;
+    for server in servers.values().filter(|s| s.active) {
+        // e.g. shift, if that's the only way to get the keysym on this keyboard.
+        send_event(sink, server.wid, sc.state | state, sc.code);
+        n_targets += 1;
+    }
+    sink.flush();
+    n_targets
 }
 
-fn translate_and_send(text: &str, app: &app::App, to: SendTo) {
+fn send(text: &str, app: &app::App, to: SendTo) {
+    match to {
+        SendTo::All {} => log!(macros::DEBUG_SEND, "{:?} to all active", text),
+        SendTo::One { wid } => log!(macros::DEBUG_SEND, "{:?} to wid {}", text, wid),
+    }
     if let Some(ref text2x11) = app.text2x11 {
-        for c in text.chars() {
-            match text2x11.translate(c as u32) {
-                None => {
-                    eprintln!(
-                        "Unknown character in xmodmap keytable: {:x} {}",
-                        u32::from(c),
-                        c
-                    );
+        translate_and_send(
+            text,
+            app.config.misc.input_encoding,
+            text2x11,
+            &app.servers,
+            &app.xdisplay,
+            to,
+        );
+    }
+}
+
+// For sanitize_paste, drop escape sequences and control characters
+// (except newline and tab) from clipboard text, so a paste from a colored
+// terminal can't recolor, retitle or otherwise drive every xterm.
+pub fn sanitize(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let csi = match c {
+            '\x1b' => match chars.peek() {
+                Some('[') => {
+                    chars.next();
+                    true
+                }
+                Some(']') => {
+                    chars.next();
+                    false
+                }
+                Some(_) => {
+                    chars.next(); // two char sequence, e.g. ESC c
+                    continue;
+                }
+                None => continue,
+            },
+            '\u{9b}' => true,
+            '\u{9d}' => false,
+            '\n' | '\t' => {
+                out.push(c);
+                continue;
+            }
+            c if c.is_control() => continue,
+            c => {
+                out.push(c);
+                continue;
+            }
+        };
+        if csi {
+            // parameters and intermediates are space to ?, then the final byte.
+            for c in &mut chars {
+                if !(' '..='?').contains(&c) {
+                    break;
                 }
-                Some(sc) => match to {
-                    SendTo::One { wid } => {
-                        app.send_event(wid, sc.state as u32, sc.code);
+            }
+        } else {
+            // OSC, up to BEL or ST (ESC \)
+            while let Some(c) = chars.next() {
+                if c == '\x07' || c == '\u{9c}' {
+                    break;
+                }
+                if c == '\x1b' {
+                    if chars.peek() == Some(&'\\') {
+                        chars.next();
                     }
-                    SendTo::All {} => {
-                        for (_, ref server) in app.servers.iter() {
-                            if !server.active {
-                                continue;
-                            }
-                            app.send_event(server.wid, sc.state as u32, sc.code);
+                    break;
+                }
+            }
+        }
+    }
+    out
+}
+
+// For bracketed_paste, a shell which understands these takes what's between
+// as one paste, instead of running each line as it arrives.
+const PASTE_START: &str = "\x1b[200~";
+const PASTE_END: &str = "\x1b[201~";
+
+// Any markers already in the text are dropped, even with sanitize_paste=no,
+// else an ESC[201~ in the clipboard ends the paste early and the rest runs as typed.
+// Again until none are left, as dropping one can join the pieces of another.
+pub fn bracket_paste(text: &str) -> String {
+    let mut text = text.to_string();
+    while text.contains(PASTE_START) || text.contains(PASTE_END) {
+        text = text.replace(PASTE_START, "").replace(PASTE_END, "");
+    }
+    format!("{}{}{}", PASTE_START, text, PASTE_END)
+}
+
+// For preview_send, what send_text() would type into each active server, a line each.
+// Control chars are escaped, so a trailing Return shows as \n.
+pub fn preview(servers: &BTreeMap<String, Server>, macros: &Macros, text: &str) -> String {
+    preview_servers(servers.iter().filter(|(_, s)| s.active), macros, text)
+}
+
+// Like preview, but for send_text_to()'s one server, active or not.
+pub fn preview_to(
+    servers: &BTreeMap<String, Server>,
+    macros: &Macros,
+    wid: Wid,
+    text: &str,
+) -> String {
+    preview_servers(servers.iter().filter(|(_, s)| s.wid == wid), macros, text)
+}
+
+// For preview_send, what isn't known until it's sent, e.g. a special key or the password.
+// So just the label, after each active server.
+pub fn preview_label(servers: &BTreeMap<String, Server>, label: &str) -> String {
+    let mut out = String::new();
+    for server_key in servers.iter().filter(|(_, s)| s.active).map(|(k, _)| k) {
+        out.push_str(server_key);
+        out.push_str(": ");
+        out.push_str(label);
+        out.push('\n');
+    }
+    out
+}
+
+fn preview_servers<'a, I>(servers: I, macros: &Macros, text: &str) -> String
+where
+    I: Iterator<Item = (&'a String, &'a Server)>,
+{
+    let mut out = String::new();
+    for (server_key, server) in servers {
+        let substituted = if macros.enabled {
+            match macros::substitute(
+                text,
+                macros,
+                server_key,
+                &server.givenname,
+                &server.username,
+            ) {
+                macros::Subst::None => None,
+                macros::Subst::Same { text } => Some(text),
+                macros::Subst::Diff { text } => Some(text),
+            }
+        } else {
+            None
+        };
+        out.push_str(server_key);
+        out.push_str(": ");
+        for c in substituted.as_ref().map_or(text, String::as_str).chars() {
+            if c.is_control() {
+                out.extend(c.escape_default());
+            } else {
+                out.push(c);
+            }
+        }
+        out.push('\n');
+    }
+    out
+}
+
+// Restrict c to what the remote end expects.
+// None means it isn't representable, so it's dropped.
+fn encode(c: char, encoding: InputEncoding) -> Option<char> {
+    let max = match encoding {
+        InputEncoding::Utf8 => return Some(c),
+        InputEncoding::Latin1 => 0xff,
+        InputEncoding::Ascii => 0x7f,
+    };
+    if u32::from(c) <= max {
+        Some(c)
+    } else {
+        None
+    }
+}
+
+fn translate_and_send<S: SendSink>(
+    text: &str,
+    encoding: InputEncoding,
+    text2x11: &Text2X11,
+    servers: &BTreeMap<String, Server>,
+    sink: &S,
+    to: SendTo,
+) {
+    for c in text.chars() {
+        let c = match encode(c, encoding) {
+            Some(c) => c,
+            None => {
+                eprintln!(
+                    "Dropping character not in {}: {:x} {}",
+                    encoding.as_str(),
+                    u32::from(c),
+                    c
+                );
+                continue;
+            }
+        };
+        match translate(c, text2x11) {
+            None => {
+                eprintln!(
+                    "Unknown character in xmodmap keytable: {:x} {}",
+                    u32::from(c),
+                    c
+                );
+            }
+            Some(sc) => match to {
+                SendTo::One { wid } => {
+                    send_event(sink, wid, sc.state as u32, sc.code);
+                }
+                SendTo::All {} => {
+                    for (_, ref server) in servers.iter() {
+                        if !server.active {
+                            continue;
                         }
+                        send_event(sink, server.wid, sc.state as u32, sc.code);
                     }
+                }
+            },
+        }
+    }
+    sink.flush();
+}
+
+// translate() goes via the char's keysym, but ESC (e.g. from bracket_paste())
+// has none, it's the Escape key.
+fn translate(c: char, text2x11: &Text2X11) -> Option<StateCode> {
+    if c == '\x1b' {
+        text2x11.keysym_to_statecode(keysym::XK_Escape)
+    } else {
+        text2x11.translate(c as u32)
+    }
+}
+
+fn send_event<S: SendSink>(sink: &S, wid: Wid, state: u32, keycode: u32) {
+    if sink.send_event(wid, state, keycode).is_err() {
+        eprintln!("Error sending event to {}", wid);
+    }
+}
+
+#[cfg(test)]
+mod send_text_tests {
+    use super::*; // so we can access non pub stuff in the send_text mod.
+
+    use std::cell::RefCell;
+    use std::collections::HashMap;
+    use x11::xlib::ShiftMask;
+
+    // records (wid, state, keycode) for every event sent.
+    #[derive(Default)]
+    struct TestSink {
+        events: RefCell<Vec<(Wid, u32, u32)>>,
+    }
+
+    impl SendSink for TestSink {
+        fn send_event(&self, wid: Wid, state: u32, keycode: u32) -> Result<()> {
+            self.events.borrow_mut().push((wid, state, keycode));
+            Ok(())
+        }
+        fn flush(&self) {}
+    }
+
+    const KEYCODE_A: u32 = 38;
+    const KEYCODE_B: u32 = 56;
+    const KEYCODE_E_ACUTE: u32 = 60;
+
+    fn make_text2x11() -> Text2X11 {
+        let mut keysym2code = HashMap::new();
+        let plain = |code| StateCode { state: 0, code };
+        let shift = |code| StateCode {
+            state: ShiftMask,
+            code,
+        };
+        keysym2code.insert('a' as u32, plain(KEYCODE_A));
+        keysym2code.insert('A' as u32, shift(KEYCODE_A));
+        keysym2code.insert('b' as u32, plain(KEYCODE_B));
+        keysym2code.insert('B' as u32, shift(KEYCODE_B));
+        keysym2code.insert(0xe9, plain(KEYCODE_E_ACUTE)); // é keysym is its latin1 codepoint
+        Text2X11::from_keysyms(8, 255, keysym2code)
+    }
+
+    fn make_servers() -> BTreeMap<String, Server> {
+        let mut servers = BTreeMap::new();
+        for (key, wid, active) in [("a", 1, true), ("b", 2, false), ("c", 3, true)].iter() {
+            servers.insert(
+                key.to_string(),
+                Server {
+                    wid: *wid,
+                    active: *active,
+                    ..Default::default()
                 },
-            }
+            );
+        }
+        servers
+    }
+
+    #[test]
+    fn test_translate_and_send_one() {
+        let sink = TestSink::default();
+        let servers = make_servers();
+        translate_and_send(
+            "Ab",
+            InputEncoding::Utf8,
+            &make_text2x11(),
+            &servers,
+            &sink,
+            SendTo::One { wid: 2 },
+        );
+        let expected = vec![(2, ShiftMask, KEYCODE_A), (2, 0, KEYCODE_B)];
+        assert_eq!(*sink.events.borrow(), expected);
+    }
+
+    #[test]
+    fn test_translate_and_send_all() {
+        // only active servers get events, and each char goes to all before the next char.
+        let sink = TestSink::default();
+        let servers = make_servers();
+        translate_and_send(
+            "Ab",
+            InputEncoding::Utf8,
+            &make_text2x11(),
+            &servers,
+            &sink,
+            SendTo::All {},
+        );
+        let expected = vec![
+            (1, ShiftMask, KEYCODE_A),
+            (3, ShiftMask, KEYCODE_A),
+            (1, 0, KEYCODE_B),
+            (3, 0, KEYCODE_B),
+        ];
+        assert_eq!(*sink.events.borrow(), expected);
+    }
+
+    #[test]
+    fn test_translate_and_send_unknown() {
+        // unmapped chars are skipped, the rest still go through
+        let sink = TestSink::default();
+        let servers = make_servers();
+        translate_and_send(
+            "a?B",
+            InputEncoding::Utf8,
+            &make_text2x11(),
+            &servers,
+            &sink,
+            SendTo::One { wid: 1 },
+        );
+        let expected = vec![(1, 0, KEYCODE_A), (1, ShiftMask, KEYCODE_B)];
+        assert_eq!(*sink.events.borrow(), expected);
+    }
+
+    #[test]
+    fn test_encode() {
+        // (char, utf8, latin1, ascii)
+        let tests = [
+            ('a', Some('a'), Some('a'), Some('a')),
+            ('é', Some('é'), Some('é'), None),
+            ('€', Some('€'), None, None),
+        ];
+        for (c, utf8, latin1, ascii) in tests.iter() {
+            assert_eq!(encode(*c, InputEncoding::Utf8), *utf8);
+            assert_eq!(encode(*c, InputEncoding::Latin1), *latin1);
+            assert_eq!(encode(*c, InputEncoding::Ascii), *ascii);
+        }
+    }
+
+    #[test]
+    fn test_translate_and_send_encodings() {
+        let text2x11 = make_text2x11();
+        let servers = make_servers();
+        let tests = [
+            (
+                InputEncoding::Utf8,
+                vec![(1, 0, KEYCODE_E_ACUTE), (1, 0, KEYCODE_A)],
+            ),
+            (
+                InputEncoding::Latin1,
+                vec![(1, 0, KEYCODE_E_ACUTE), (1, 0, KEYCODE_A)],
+            ),
+            (InputEncoding::Ascii, vec![(1, 0, KEYCODE_A)]),
+        ];
+        for (encoding, expected) in tests.iter() {
+            let sink = TestSink::default();
+            translate_and_send(
+                "éa",
+                *encoding,
+                &text2x11,
+                &servers,
+                &sink,
+                SendTo::One { wid: 1 },
+            );
+            assert_eq!(*sink.events.borrow(), *expected);
         }
     }
+
+    #[test]
+    fn test_keysym_and_send() {
+        use x11::xlib::ControlMask;
+
+        let sink = TestSink::default();
+        let servers = make_servers();
+        let n = keysym_and_send('b' as u32, ControlMask, &make_text2x11(), &servers, &sink);
+        assert_eq!(n, 2);
+        let expected = vec![(1, ControlMask, KEYCODE_B), (3, ControlMask, KEYCODE_B)];
+        assert_eq!(*sink.events.borrow(), expected);
+
+        let sink = TestSink::default();
+        let n = keysym_and_send('A' as u32, 0, &make_text2x11(), &servers, &sink);
+        assert_eq!(n, 2);
+        let expected = vec![(1, ShiftMask, KEYCODE_A), (3, ShiftMask, KEYCODE_A)];
+        assert_eq!(*sink.events.borrow(), expected);
+
+        let sink = TestSink::default();
+        let n = keysym_and_send('z' as u32, 0, &make_text2x11(), &servers, &sink);
+        assert_eq!(n, 0);
+        assert!(sink.events.borrow().is_empty());
+    }
+
+    #[test]
+    fn test_bracket_paste() {
+        assert_eq!(bracket_paste("ls\nrm"), "\x1b[200~ls\nrm\x1b[201~");
+        assert_eq!(
+            bracket_paste("ls\x1b[201~\nrm -rf ~\n"),
+            "\x1b[200~ls\nrm -rf ~\n\x1b[201~"
+        );
+        assert_eq!(
+            bracket_paste("\x1b[200~a\x1b[20\x1b[201~1~b"),
+            "\x1b[200~ab\x1b[201~"
+        );
+
+        let mut keysym2code = HashMap::new();
+        let plain = |code| StateCode { state: 0, code };
+        keysym2code.insert(x11::keysym::XK_Escape, plain(9));
+        keysym2code.insert('[' as u32, plain(34));
+        keysym2code.insert('0' as u32, plain(19));
+        keysym2code.insert('1' as u32, plain(10));
+        keysym2code.insert('2' as u32, plain(11));
+        keysym2code.insert('~' as u32, plain(49));
+        keysym2code.insert('a' as u32, plain(KEYCODE_A));
+        let text2x11 = Text2X11::from_keysyms(8, 255, keysym2code);
+
+        let sink = TestSink::default();
+        translate_and_send(
+            &bracket_paste("a"),
+            InputEncoding::Utf8,
+            &text2x11,
+            &make_servers(),
+            &sink,
+            SendTo::One { wid: 1 },
+        );
+        let keycodes: Vec<u32> = sink.events.borrow().iter().map(|e| e.2).collect();
+        // ESC [ 2 0 0 ~ a ESC [ 2 0 1 ~
+        let expected = vec![9, 34, 11, 19, 19, 49, KEYCODE_A, 9, 34, 11, 19, 10, 49];
+        assert_eq!(keycodes, expected);
+    }
+
+    #[test]
+    fn test_preview() {
+        let mut config = crate::config::Config::default();
+        // only the active a and c
+        assert_eq!(
+            preview(&make_servers(), &config.macros, "echo %s\n"),
+            "a: echo a\\n\nc: echo c\\n\n"
+        );
+        config.macros.enabled = false;
+        assert_eq!(
+            preview(&make_servers(), &config.macros, "echo %s"),
+            "a: echo %s\nc: echo %s\n"
+        );
+        // b isn't active, but it's the one asked for
+        assert_eq!(
+            preview_to(&make_servers(), &config.macros, 2, "ls\n"),
+            "b: ls\\n\n"
+        );
+        assert_eq!(preview_to(&make_servers(), &config.macros, 4, "ls\n"), "");
+        assert_eq!(
+            preview_label(&make_servers(), "<Ctrl-C>"),
+            "a: <Ctrl-C>\nc: <Ctrl-C>\n"
+        );
+    }
+
+    #[test]
+    fn test_sanitize() {
+        assert_eq!(sanitize("ls -l\n"), "ls -l\n");
+        assert_eq!(sanitize("a\tb"), "a\tb");
+        assert_eq!(sanitize("caf\u{e9}"), "caf\u{e9}");
+
+        // CSI
+        assert_eq!(sanitize("\x1b[31mred\x1b[0m"), "red");
+        assert_eq!(sanitize("\x1b[1;32mok\x1b[m done"), "ok done");
+        assert_eq!(sanitize("\x1b[?25lhidden"), "hidden");
+        assert_eq!(sanitize("\u{9b}31mred"), "red");
+        assert_eq!(sanitize("\x1b[31"), ""); // truncated
+
+        // OSC, ended by BEL or ST
+        assert_eq!(sanitize("\x1b]0;title\x07ls"), "ls");
+        assert_eq!(sanitize("\x1b]2;title\x1b\\ls"), "ls");
+        assert_eq!(sanitize("\x1b]52;c;cm0gLXJm\x07ls"), "ls");
+        assert_eq!(sanitize("\u{9d}0;title\u{9c}ls"), "ls");
+        assert_eq!(sanitize("\x1b]0;never ends"), "");
+
+        // other escapes, and bare control bytes
+        assert_eq!(sanitize("\x1bcreset"), "reset");
+        assert_eq!(sanitize("end\x1b"), "end");
+        assert_eq!(sanitize("a\x00b\x03c\x7fd\re\x08f"), "abcdef");
+        assert_eq!(sanitize("line1\r\nline2\n"), "line1\nline2\n");
+    }
 }
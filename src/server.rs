@@ -7,24 +7,39 @@ use gtk::{
     CheckMenuItemExt, // for set_active()
     ContainerExt,     // for menu.remove()
     Menu,
+    MenuItemExt, // for set_label()
+    WidgetExt,   // for set_sensitive()
 };
+use libc;
 use nix::sys::signal;
 use nix::unistd::{fork, ForkResult, Pid};
+use std::cell::Cell;
 use std::collections::BTreeMap;
+use std::env;
+use std::ffi::{CString, OsStr};
 use std::fs;
 use std::io::{BufRead, BufReader};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
 use std::path::{Path, PathBuf};
+use std::ptr;
 use std::str::FromStr;
 
 use crate::app::Wid;
 use crate::child;
 use crate::config;
 use crate::er::Result;
+use crate::helper;
 use crate::host;
+use crate::host::STRICT_GEOMETRY;
+use crate::is_xfile::IsExecutableFile;
+use crate::log;
+use crate::macros;
 use crate::tmpnam;
 
 pub type BumpType = u8;
 
+pub type Geometry = (u32, u32, u32, u32); // x, y, w, h
+
 #[derive(Debug, Default)]
 pub struct Server {
     pub wid: Wid,
@@ -36,6 +51,13 @@ pub struct Server {
     pub username: Option<String>,
     pub pipenm: Option<PathBuf>,
     pub menu_item: Option<CheckMenuItem>,
+    pub pinned: bool, // placed via host=geometry, so retile leaves it alone
+    pub seq: u64,     // order created, for order=given
+    // (row, col) from the last retile, None if pinned or not tiled into a grid
+    pub grid_position: Option<(u32, u32)>,
+    // what App::send_resizemove() last sent, for Hosts -> Save layout…
+    // A Cell, as retiling only borrows the servers.
+    pub geometry: Cell<Option<Geometry>>,
 }
 
 impl Server {
@@ -56,6 +78,14 @@ impl Server {
             hosts_menu.remove(menu_item);
         }
     }
+    // keep_dead_in_menu, grey out instead of remove_menu_item()
+    // Hands back the menu item so it can be revived by revive_menu_items()
+    pub fn mark_menu_item_dead(&mut self, server_key: &str) -> Option<CheckMenuItem> {
+        let menu_item = self.menu_item.take()?;
+        menu_item.set_label(&format!("\u{2717} {}", server_key));
+        menu_item.set_sensitive(false);
+        Some(menu_item)
+    }
     pub fn set_active(&mut self, active: bool) {
         self.active = active;
         if let Some(ref m) = self.menu_item {
@@ -114,28 +144,39 @@ pub fn open_client_windows(
     config: &config::Config,
     internal_activate_autoquit: &mut bool,
     me: &str,
+    progress: bool,
+    run_command: bool, // false to skip the action command (-a)
+    added: bool,       // true for Hosts -> Add Host(s), see activate_added_hosts
 ) -> Result<()> {
     let (comms, comms_args, command, auto_close) = config.get_script_args();
 
+    let mut seq = servers.values().map(|s| s.seq).max().unwrap_or(0);
+
+    let on_parse_error = config.misc.on_parse_error;
+    if let config::OnParseError::Abort = on_parse_error {
+        // before fork()ing any, so there's nothing to clean up.
+        for host_str in host_strs.iter().filter(|h| !h.is_empty()) {
+            parse_host(host_str, on_parse_error)?;
+        }
+    }
+
+    let fifo_dir = tmpnam::get_fifo_dir(&config.tcssh.fifo_dir);
+    tmpnam::create_fifo_dir(&fifo_dir)?;
+
     for host_str in host_strs {
         if host_str.is_empty() {
             continue;
         }
 
-        let host = match host::parse(&host_str) {
+        let host = match parse_host(&host_str, on_parse_error)? {
             Some(host) => host,
-            None => {
-                eprintln!("Could not parse host_str {}", host_str);
-                // perl cssh would die if any host failed to parse.
-                //  cssh 127.0.0.1 user@ # terminates with 'hostname is undefined'
-                // tcssh 127.0.0.1 user@ # prints above & opens an xterm to 127.0.0.1
-                continue;
-            }
+            None => continue,
         };
 
-        let pipenm = tmpnam::tmpnam_and_mkfifo()?;
+        let pipenm = tmpnam::tmpnam_and_mkfifo(&fifo_dir)?;
 
         let given_server_name = host.hostname;
+        let geometry = host_geometry(config, &host);
 
         let server_key = match get_server_key(servers, given_server_name) {
             Some(server_key) => server_key,
@@ -153,13 +194,17 @@ pub fn open_client_windows(
                     host_str: &host_str,
                     host: &host,
                     given_server_name,
+                    geometry,
                     pipenm: &pipenm,
                     server_key: &server_key,
                     me,
+                    run_command,
                 };
                 child.handle_fork();
             }
             Ok(ForkResult::Parent { child }) => {
+                log!(macros::DEBUG_FORK, "forked {} for {}", child, host_str);
+                seq += 1;
                 let server = Server {
                     wid: 0,
                     pid: Some(child),
@@ -170,6 +215,10 @@ pub fn open_client_windows(
                     username: host.username.and_then(|u| Some(String::from(u))),
                     pipenm: Some(pipenm),
                     menu_item: None,
+                    pinned: geometry.is_some(),
+                    seq,
+                    grid_position: None,
+                    geometry: Cell::new(None),
                 };
 
                 servers.insert(server_key, server);
@@ -181,6 +230,7 @@ pub fn open_client_windows(
         }
     }
 
+    let mut opened = Vec::new();
     let mut err_servers = Vec::new();
     for (ref server_key, ref mut server) in servers.iter_mut() {
         if let Some(ref mut pipenm) = server.pipenm {
@@ -198,19 +248,35 @@ pub fn open_client_windows(
             // read timeout for it to be rock solid).  And again perl cssh
             // did not have a timeout, and after many years deployed in the
             // field.. never needed one.
-            if let Err(e) = read_pipe(&pipenm, &mut server.pid, &mut server.wid) {
+            if let Err(e) = read_pipe(
+                &pipenm,
+                &mut server.pid,
+                &mut server.wid,
+                config.dynamic.wait_all,
+            ) {
                 eprintln!("Error reading pipe {} {}", pipenm.to_string_lossy(), e);
+                if progress {
+                    println!("{}", progress_fail(server_key, &e));
+                }
                 // perl just printed to stderr, then marked as active (no pid, no wid).
                 // which seems odd, so lets remove this server since we don't know it's pid or wid.
                 err_servers.push(server_key.to_string());
             } else {
-                server.active = true;
+                log!(macros::DEBUG_FORK, "{}", progress_ok(server_key, server));
+                if progress {
+                    println!("{}", progress_ok(server_key, server));
+                }
+                opened.push(server_key.to_string());
                 *internal_activate_autoquit = true;
+                if let Some(ref hook) = config.tcssh.post_connect_hook {
+                    run_post_connect_hook(hook, server_key, server);
+                }
             }
             fs::remove_file(&pipenm).ok(); // ignore error
         }
         server.pipenm = None;
     }
+    let n_failed = err_servers.len();
     // if we couldn't read the pipe, no pid, no wid, then remove them.
     if !err_servers.is_empty() {
         for server_key in err_servers.iter() {
@@ -218,9 +284,278 @@ pub fn open_client_windows(
         }
     }
 
+    set_initial_active(servers, &mut opened, config.misc.initial_active(added));
+
+    if config.dynamic.wait_all && n_failed > 0 {
+        return Err(format!("--wait-all: {} session(s) failed to start", n_failed).into());
+    }
     Ok(())
 }
 
+// perl cssh parsed but dropped =geometry, so only use it if asked to.
+fn host_geometry<'a>(config: &config::Config, host: &host::Host<'a>) -> Option<&'a str> {
+    if config.misc.respect_host_geometry {
+        host.geometry.filter(|g| STRICT_GEOMETRY.is_match(g))
+    } else {
+        None
+    }
+}
+
+// For --print-command, what open_client_windows() would have the child exec() for host_str.
+// No fork(), no fifo, and no X, so FIFO stands in for the fifo's name.
+pub fn print_command(host_str: &str, config: &config::Config, me: &str) -> Result<String> {
+    let (comms, comms_args, command, auto_close) = config.get_script_args();
+    let host = match parse_host(host_str, config.misc.on_parse_error)? {
+        Some(host) => host,
+        None => return Err(format!("Nothing to print for {}", host_str).into()),
+    };
+    let pipenm = tmpnam::get_fifo_dir(&config.tcssh.fifo_dir).join("FIFO");
+    let child = child::Child {
+        config,
+        comms,
+        comms_args,
+        command,
+        auto_close,
+        host_str,
+        host: &host,
+        given_server_name: host.hostname,
+        geometry: host_geometry(config, &host),
+        pipenm: &pipenm,
+        server_key: host.hostname,
+        me,
+        run_command: true,
+    };
+    Ok(child.build_command())
+}
+
+// opened is the keys of the servers just opened, in any order.
+// Only the active ones (per initial_active) will receive keystrokes.
+fn set_initial_active(
+    servers: &mut BTreeMap<String, Server>,
+    opened: &mut Vec<String>,
+    initial_active: config::InitialActive,
+) {
+    opened.sort_by_key(|server_key| servers.get(server_key).map_or(0, |s| s.seq));
+    for (i, server_key) in opened.iter().enumerate() {
+        if let Some(server) = servers.get_mut(server_key) {
+            server.active = initial_active.is_active(i);
+        }
+    }
+}
+
+// Ok(None) when the host should be skipped.
+fn parse_host(
+    host_str: &str,
+    on_parse_error: config::OnParseError,
+) -> Result<Option<host::Host<'_>>> {
+    if let Some(host) = host::parse(host_str) {
+        return Ok(Some(host));
+    }
+    // perl cssh would die if any host failed to parse.
+    //  cssh 127.0.0.1 user@ # terminates with 'hostname is undefined'
+    // tcssh 127.0.0.1 user@ # prints below & opens an xterm to 127.0.0.1 (on_parse_error=skip)
+    match on_parse_error {
+        config::OnParseError::Skip => {
+            eprintln!("Could not parse host_str {}", host_str);
+            Ok(None)
+        }
+        config::OnParseError::Abort => Err(format!("Could not parse host_str {}", host_str).into()),
+        config::OnParseError::Literal => {
+            eprintln!("Could not parse host_str {}, using it as is", host_str);
+            Ok(Some(host::Host::literal(host_str)))
+        }
+    }
+}
+
+// Re-added servers take back the greyed out menu item left by their dead
+// session (see keep_dead_in_menu), matched on connect_string.
+// build_host_menu() then relabels it and makes it sensitive again.
+pub fn revive_menu_items(
+    servers: &mut BTreeMap<String, Server>,
+    dead_menu_items: &mut Vec<(String, CheckMenuItem)>,
+) {
+    if dead_menu_items.is_empty() {
+        return;
+    }
+    for server in servers.values_mut().filter(|s| s.menu_item.is_none()) {
+        if let Some(i) = dead_menu_items
+            .iter()
+            .position(|(connect_string, _)| *connect_string == server.connect_string)
+        {
+            server.menu_item = Some(dead_menu_items.remove(i).1);
+        }
+    }
+}
+
+pub fn find_by_menu_item_mut<'a>(
+    servers: &'a mut BTreeMap<String, Server>,
+    menu_item: &CheckMenuItem,
+) -> Option<&'a mut Server> {
+    servers
+        .values_mut()
+        .find(|s| s.menu_item.as_ref() == Some(menu_item))
+}
+
+// Activate the servers given (as either host or [user@]host[:port]) in members,
+// and deactivate the rest. Returns how many are active.
+pub fn set_active_by_members(servers: &mut BTreeMap<String, Server>, members: &[String]) -> usize {
+    let mut n_active = 0;
+    for server in servers.values_mut() {
+        let active = members
+            .iter()
+            .any(|m| *m == server.givenname || *m == server.connect_string);
+        server.set_active(active);
+        if active {
+            n_active += 1;
+        }
+    }
+    n_active
+}
+
+// The server_key of the server the last retile put at (row, col), if any.
+pub fn at_grid_position(servers: &BTreeMap<String, Server>, row: u32, col: u32) -> Option<&String> {
+    servers
+        .iter()
+        .find(|(_, s)| s.grid_position == Some((row, col)))
+        .map(|(server_key, _)| server_key)
+}
+
+pub fn note_geometry(servers: &BTreeMap<String, Server>, wid: Wid, geometry: Geometry) {
+    if let Some(server) = servers.values().find(|s| s.wid == wid) {
+        server.geometry.set(Some(geometry));
+    }
+}
+
+// Hosts -> Save layout…, a "server_key x y w h" line for each server placed so far.
+// server_keys may have a space (e.g. "web1 1"), so parse_layout() splits from the right.
+pub fn export_layout(servers: &BTreeMap<String, Server>) -> String {
+    let mut layout = String::new();
+    for (server_key, server) in servers {
+        if let Some((x, y, w, h)) = server.geometry.get() {
+            layout += &format!("{} {} {} {} {}\n", server_key, x, y, w, h);
+        }
+    }
+    layout
+}
+
+pub fn parse_layout(layout: &str) -> Vec<(String, Geometry)> {
+    let mut parsed = Vec::new();
+    for line in layout.lines().filter(|l| !l.trim().is_empty()) {
+        let mut fields = line.trim_end().rsplitn(5, ' ');
+        let mut n = || fields.next().and_then(|f| u32::from_str(f).ok());
+        let (h, w, y, x) = (n(), n(), n(), n());
+        match (fields.next(), x, y, w, h) {
+            (Some(server_key), Some(x), Some(y), Some(w), Some(h)) if w > 0 && h > 0 => {
+                parsed.push((server_key.to_string(), (x, y, w, h)))
+            }
+            _ => eprintln!("Warn: Ignoring layout line {}", line),
+        }
+    }
+    parsed
+}
+
+// servers is keyed (and so sorted) by server_key.
+// With order=given we want the order hosts were given instead.
+pub fn in_order(
+    servers: &BTreeMap<String, Server>,
+    order_by_given: bool,
+) -> Vec<(&String, &Server)> {
+    let mut v: Vec<_> = servers.iter().collect();
+    if order_by_given {
+        v.sort_by_key(|(_, s)| s.seq);
+    }
+    v
+}
+
+pub fn in_order_mut(
+    servers: &mut BTreeMap<String, Server>,
+    order_by_given: bool,
+) -> Vec<(&String, &mut Server)> {
+    let mut v: Vec<_> = servers.iter_mut().collect();
+    if order_by_given {
+        v.sort_by_key(|(_, s)| s.seq);
+    }
+    v
+}
+
+// Environment given to post_connect_hook.
+fn post_connect_hook_env(server_key: &str, server: &Server) -> [(&'static str, String); 4] {
+    let pid = server.pid.map_or(0, |p| p.as_raw());
+    [
+        ("TCSSH_HOST", server.givenname.clone()),
+        ("TCSSH_SERVER_KEY", server_key.to_string()),
+        ("TCSSH_PID", format!("{}", pid)),
+        ("TCSSH_WID", format!("{}", server.wid)),
+    ]
+}
+
+// Our SIGCHLD handler is installed by now, and it interferes with std::process::Command.
+// So fork()/exec() the hook ourselves, just like the xterms,
+// and don't wait for it. The SIGCHLD handler reaps it.
+// The child may only make async-signal-safe calls (setenv isn't), so its argv
+// and environment are all made before the fork, for it to just execve().
+// Returns the hook's pid, for the tests.
+fn run_post_connect_hook(hook: &Path, server_key: &str, server: &Server) -> Option<Pid> {
+    if !hook.is_executable_file() {
+        eprintln!(
+            "post_connect_hook {} is not executable",
+            hook.to_string_lossy()
+        );
+        return None;
+    }
+    let hook_c = match CString::new(hook.as_os_str().as_bytes()) {
+        Ok(hook_c) => hook_c,
+        Err(e) => {
+            eprintln!("Bad post_connect_hook {} {}", hook.to_string_lossy(), e);
+            return None;
+        }
+    };
+    let argv = [hook_c.as_ptr(), ptr::null()];
+    let hook_env = post_connect_hook_env(server_key, server);
+    let env_c: Vec<CString> = env::vars_os()
+        .filter(|(key, _)| {
+            hook_env
+                .iter()
+                .all(|(k, _)| key.as_os_str() != OsStr::new(k))
+        })
+        .map(|(key, value)| (key.into_vec(), value.into_vec()))
+        .chain(
+            hook_env
+                .iter()
+                .map(|(key, value)| (key.as_bytes().to_vec(), value.as_bytes().to_vec())),
+        )
+        .filter_map(|(mut key, value)| {
+            key.push(b'=');
+            key.extend(value);
+            CString::new(key).ok()
+        })
+        .collect();
+    let mut envp: Vec<*const libc::c_char> = env_c.iter().map(|e| e.as_ptr()).collect();
+    envp.push(ptr::null());
+
+    match fork() {
+        Ok(ForkResult::Child) => unsafe {
+            libc::execve(hook_c.as_ptr(), argv.as_ptr(), envp.as_ptr());
+            libc::_exit(127)
+        },
+        Ok(ForkResult::Parent { child }) => Some(child),
+        Err(e) => {
+            eprintln!("fork() error for post_connect_hook {:?}", e);
+            None
+        }
+    }
+}
+
+// --progress output, one line per server, for scripts watching stdout.
+fn progress_ok(server_key: &str, server: &Server) -> String {
+    let pid = server.pid.map_or(0, |p| p.as_raw());
+    format!("OK {} pid={} wid={}", server_key, pid, server.wid)
+}
+
+fn progress_fail(server_key: &str, reason: &dyn std::fmt::Display) -> String {
+    format!("FAIL {} {}", server_key, reason)
+}
+
 // Parent makes a pipe/mkfifo per child,
 // and passes the pipe's name to each child.
 // The child writes back PID:WINDOWID
@@ -229,15 +564,32 @@ pub fn open_client_windows(
 // This is not part of the impl block because the caller already has
 // an immutable reference to self.config, and a mutable reference to self.servers,
 // so it cannot create another reference (of any kind) to self.
-fn read_pipe(pipenm: &Path, pid_out: &mut Option<Pid>, wid_out: &mut Wid) -> Result<()> {
+//
+// With --wait-all the child then writes a second line, helper::WAIT_ALL_STARTED,
+// just before it runs comms (ssh etc). So we block until then.
+fn read_pipe(
+    pipenm: &Path,
+    pid_out: &mut Option<Pid>,
+    wid_out: &mut Wid,
+    wait_all: bool,
+) -> Result<()> {
     let file = fs::OpenOptions::new()
         .read(true)
         .create_new(false)
         .open(pipenm)?;
 
+    let mut reader = BufReader::new(file);
+    parse_pipe(&mut reader, pid_out, wid_out, wait_all)
+}
+
+fn parse_pipe<R: BufRead>(
+    reader: &mut R,
+    pid_out: &mut Option<Pid>,
+    wid_out: &mut Wid,
+    wait_all: bool,
+) -> Result<()> {
     let mut buf = String::with_capacity(46); // pid:windowid+4 ~ len(2^64)*2+5
                                              // 4 is just padding. 5 includes the :
-    let mut reader = BufReader::new(file);
     reader.read_line(&mut buf)?;
     let mut i = buf.trim_end().split(':');
 
@@ -247,6 +599,18 @@ fn read_pipe(pipenm: &Path, pid_out: &mut Option<Pid>, wid_out: &mut Wid) -> Res
                 if let Ok(wid) = u64::from_str(wid_str) {
                     *wid_out = wid as Wid;
                     *pid_out = Some(Pid::from_raw(pid as i32));
+                    if wait_all {
+                        buf.clear();
+                        reader.read_line(&mut buf)?;
+                        if buf.trim_end() != helper::WAIT_ALL_STARTED {
+                            return Err(format!(
+                                "Expected {} got {:?}",
+                                helper::WAIT_ALL_STARTED,
+                                buf.trim_end()
+                            )
+                            .into());
+                        }
+                    }
                     return Ok(());
                 }
             }
@@ -254,3 +618,195 @@ fn read_pipe(pipenm: &Path, pid_out: &mut Option<Pid>, wid_out: &mut Wid) -> Res
     }
     Err("Expected PID:WINDOWID".into())
 }
+
+#[test]
+fn test_layout_round_trip() {
+    let mut servers = BTreeMap::new();
+    for (i, server_key) in ["web1", "web1 1", "db1"].iter().enumerate() {
+        let server = Server {
+            wid: i as Wid + 1,
+            ..Default::default()
+        };
+        servers.insert(server_key.to_string(), server);
+    }
+    note_geometry(&servers, 1, (7, 4, 648, 298));
+    note_geometry(&servers, 2, (7, 306, 648, 298));
+    note_geometry(&servers, 99, (1, 1, 1, 1)); // not ours
+
+    // db1 was never placed, so it isn't in the layout
+
+    let layout = export_layout(&servers);
+    assert_eq!(layout, "web1 7 4 648 298\nweb1 1 7 306 648 298\n");
+    assert_eq!(
+        parse_layout(&layout),
+        vec![
+            ("web1".to_string(), (7, 4, 648, 298)),
+            ("web1 1".to_string(), (7, 306, 648, 298)),
+        ]
+    );
+
+    let parsed = parse_layout("web1 1 2 3\n\ndb1 0 0 0 10\nweb2 x 1 2 3\nweb3 0 0 80 24\n");
+    assert_eq!(parsed, vec![("web3".to_string(), (0, 0, 80, 24))]);
+}
+
+#[test]
+fn test_progress_lines() {
+    let server = Server {
+        wid: 56_623_107,
+        pid: Some(Pid::from_raw(1234)),
+        ..Default::default()
+    };
+    assert_eq!(
+        progress_ok("web1", &server),
+        "OK web1 pid=1234 wid=56623107"
+    );
+
+    let e: crate::er::Error = "Expected PID:WINDOWID".into();
+    assert_eq!(progress_fail("web2", &e), "FAIL web2 Expected PID:WINDOWID");
+}
+
+#[test]
+fn test_post_connect_hook_env() {
+    let server = Server {
+        wid: 56_623_107,
+        pid: Some(Pid::from_raw(1234)),
+        givenname: "web1".to_owned(),
+        ..Default::default()
+    };
+    let env = post_connect_hook_env("web1 1", &server);
+    assert_eq!(
+        env,
+        [
+            ("TCSSH_HOST", "web1".to_string()),
+            ("TCSSH_SERVER_KEY", "web1 1".to_string()),
+            ("TCSSH_PID", "1234".to_string()),
+            ("TCSSH_WID", "56623107".to_string()),
+        ]
+    );
+}
+
+#[test]
+fn test_run_post_connect_hook() {
+    use nix::sys::wait::{waitpid, WaitStatus};
+    use std::os::unix::fs::PermissionsExt;
+
+    let hook = tmpnam::tmpnam().unwrap();
+    let out = tmpnam::tmpnam().unwrap();
+    fs::write(
+        &hook,
+        format!(
+            "#!/bin/sh\necho \"$TCSSH_HOST:$TCSSH_SERVER_KEY:$TCSSH_PID:$TCSSH_WID:$HOME\" > '{}'\n",
+            out.to_string_lossy()
+        ),
+    )
+    .unwrap();
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o700)).unwrap();
+    let server = Server {
+        wid: 42,
+        pid: Some(Pid::from_raw(1234)),
+        givenname: "web1".to_owned(),
+        ..Default::default()
+    };
+    let pid = run_post_connect_hook(&hook, "web1 1", &server).unwrap();
+    assert_eq!(waitpid(pid, None).unwrap(), WaitStatus::Exited(pid, 0));
+    // ours, and the rest of the environment is passed on.
+    assert_eq!(
+        fs::read_to_string(&out).unwrap(),
+        format!(
+            "web1:web1 1:1234:42:{}\n",
+            env::var("HOME").unwrap_or_default()
+        )
+    );
+    fs::remove_file(&out).unwrap();
+
+    fs::set_permissions(&hook, fs::Permissions::from_mode(0o600)).unwrap();
+    assert_eq!(run_post_connect_hook(&hook, "web1 1", &server), None);
+    fs::remove_file(&hook).unwrap();
+}
+
+#[test]
+fn test_set_initial_active() {
+    let mut servers = BTreeMap::new();
+    // "c" was given first
+    for (key, seq) in [("a", 2), ("b", 3), ("c", 1)].iter() {
+        let server = Server {
+            seq: *seq,
+            ..Default::default()
+        };
+        servers.insert(key.to_string(), server);
+    }
+    let active = |servers: &BTreeMap<String, Server>| -> Vec<bool> {
+        servers.values().map(|s| s.active).collect()
+    };
+    let mut opened: Vec<String> = vec!["a".into(), "b".into(), "c".into()];
+
+    set_initial_active(&mut servers, &mut opened, config::InitialActive::First);
+    assert_eq!(active(&servers), vec![false, false, true]);
+
+    set_initial_active(&mut servers, &mut opened, config::InitialActive::All);
+    assert_eq!(active(&servers), vec![true, true, true]);
+
+    set_initial_active(&mut servers, &mut opened, config::InitialActive::None);
+    assert_eq!(active(&servers), vec![false, false, false]);
+}
+
+#[test]
+fn test_parse_pipe() {
+    use std::io::Cursor;
+
+    let tests = [
+        ("1234:5678\n", false, Some((1234, 5678))),
+        ("1234:5678\n", true, None), // child died before its command started
+        ("1234:5678\nSTARTED\n", true, Some((1234, 5678))),
+        ("1234:5678\nnonsense\n", true, None),
+        ("1234\n", false, None),
+        ("", false, None),
+    ];
+    for (input, wait_all, expected) in tests.iter() {
+        let mut pid = None;
+        let mut wid = 0;
+        let result = parse_pipe(&mut Cursor::new(input), &mut pid, &mut wid, *wait_all);
+        match expected {
+            Some((expected_pid, expected_wid)) => {
+                assert_eq!(result, Ok(()), "{:?}", input);
+                assert_eq!(pid, Some(Pid::from_raw(*expected_pid)));
+                assert_eq!(wid, *expected_wid);
+            }
+            None => assert!(result.is_err(), "{:?}", input),
+        }
+    }
+}
+
+#[test]
+fn test_parse_host() {
+    use config::OnParseError;
+
+    for mode in [
+        OnParseError::Skip,
+        OnParseError::Abort,
+        OnParseError::Literal,
+    ]
+    .iter()
+    {
+        let host = parse_host("bob@web1:2222", *mode).unwrap().unwrap();
+        assert_eq!(host.hostname, "web1");
+    }
+
+    assert!(parse_host("user@", OnParseError::Skip).unwrap().is_none());
+    assert!(parse_host("user@", OnParseError::Abort).is_err());
+    let host = parse_host("user@", OnParseError::Literal).unwrap().unwrap();
+    assert_eq!(host.hostname, "user@");
+    assert_eq!(host.username, None);
+}
+
+#[test]
+fn test_print_command() {
+    let mut config = config::Config::default();
+    config.comms.comms = config::CommsE::Ssh;
+    config.tcssh.fifo_dir = Some(PathBuf::from("/run/tcssh"));
+    let command = print_command("bob@web1:2222", &config, "tcssh").unwrap();
+    assert!(command.starts_with("xterm "));
+    assert!(command.contains(" --helper  ssh '' '' '5' /run/tcssh/FIFO web1 'bob' '2222' "));
+
+    assert!(print_command("user@", &config, "tcssh").is_err());
+}
@@ -178,6 +178,25 @@ impl Text2X11 {
         })
     }
 
+    // So tests can build a keyboard mapping without an X display.
+    #[cfg(test)]
+    pub fn from_keysyms(
+        min_keycode: u32,
+        max_keycode: u32,
+        keysym2code: HashMap<Keysym, StateCode>,
+    ) -> Self {
+        Self {
+            min_keycode,
+            max_keycode,
+            keysym2code,
+        }
+    }
+
+    // For keys which aren't text, e.g. F5, Up, or the 'c' of Ctrl-C.
+    pub fn keysym_to_statecode(&self, sym: Keysym) -> Option<StateCode> {
+        self.keysym2code.get(&sym).cloned()
+    }
+
     pub fn translate(&self, wc: u32) -> Option<StateCode> {
         if wc < self.min_keycode || wc > self.max_keycode {
             return None;
@@ -206,3 +225,25 @@ fn test_constant_order() {
     assert!(Mod5Mask < Mod5Mask | ShiftMask);
     // check relative-ordering is what we expect.
 }
+
+#[test]
+fn test_keysym_to_statecode() {
+    use x11::keysym::{XK_Up, XK_C, XK_F5};
+
+    let mut keysym2code = HashMap::new();
+    keysym2code.insert(XK_F5, StateCode { state: 0, code: 71 });
+    keysym2code.insert(
+        XK_C,
+        StateCode {
+            state: ShiftMask,
+            code: 54,
+        },
+    );
+    let text2x11 = Text2X11::from_keysyms(8, 255, keysym2code);
+
+    let sc = text2x11.keysym_to_statecode(XK_F5).unwrap();
+    assert_eq!((sc.state, sc.code), (0, 71));
+    let sc = text2x11.keysym_to_statecode(XK_C).unwrap();
+    assert_eq!((sc.state, sc.code), (ShiftMask, 54));
+    assert!(text2x11.keysym_to_statecode(XK_Up).is_none());
+}
@@ -0,0 +1,96 @@
+// Renders the console's window title from the config value title_format.
+//
+// perl cssh had a fixed "title [n]" style title, and that's still our
+// default, but title_format allows e.g. "%t %a/%n %H" which expands to
+//     "TCSSH 2/3 web1,web2,web3"
+//
+// Tokens are
+//     %t  base title (from arg0 or --title)
+//     %n  number of servers
+//     %a  number of active servers
+//     %H  comma joined hostnames
+//     %%  a literal %
+// Anything else (including an unknown %x) is passed through untouched.
+//
+// This lives outside of g.rs so it can be tested without gtk.
+
+pub static DEFAULT_TITLE_FORMAT: &'static str = "%t [%n]";
+
+pub struct TitleInfo<'a> {
+    pub title: &'a str,
+    pub n_servers: usize,
+    pub n_active: usize,
+    pub hostnames: &'a [&'a str],
+}
+
+// %H is the only token which costs much to compute,
+// so callers can check this before gathering hostnames.
+pub fn wants_hostnames(format: &str) -> bool {
+    format.contains("%H")
+}
+
+pub fn format_title(format: &str, info: &TitleInfo<'_>) -> String {
+    expand_tokens(format, |token| match token {
+        't' => Some(info.title.to_string()),
+        'n' => Some(format!("{}", info.n_servers)),
+        'a' => Some(format!("{}", info.n_active)),
+        'H' => Some(info.hostnames.join(",")),
+        _ => None,
+    })
+}
+
+// The %x loop behind format_title(), and helper::expand_custom_command()'s %h etc.
+// expand('x') is what %x stands for, None if x isn't a token.
+// %% is always a literal %, and unknown tokens, or a trailing %, are left as is.
+pub fn expand_tokens<F: FnMut(char) -> Option<String>>(template: &str, mut expand: F) -> String {
+    let mut out = String::with_capacity(template.len() + 16);
+    let mut chars = template.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('%') => out.push('%'),
+            Some(token) => match expand(token) {
+                Some(value) => out += &value,
+                None => {
+                    out.push('%');
+                    out.push(token);
+                }
+            },
+            None => out.push('%'),
+        }
+    }
+    out
+}
+
+#[test]
+fn test_format_title() {
+    let hostnames = ["web1", "web2", "db1"];
+    let info = TitleInfo {
+        title: "TCSSH",
+        n_servers: 3,
+        n_active: 2,
+        hostnames: &hostnames,
+    };
+
+    let tests = [
+        (DEFAULT_TITLE_FORMAT, "TCSSH [3]"),
+        ("%t %a/%n", "TCSSH 2/3"),
+        ("%t: %H", "TCSSH: web1,web2,db1"),
+        ("100%% %t", "100% TCSSH"),
+        ("%x %t", "%x TCSSH"), // unknown tokens pass through
+        ("trailing %", "trailing %"),
+        ("", ""),
+    ];
+    for (format, expected) in tests.iter() {
+        assert_eq!(&format_title(format, &info), expected);
+    }
+}
+
+#[test]
+fn test_wants_hostnames() {
+    assert!(wants_hostnames("%t %H"));
+    assert!(!wants_hostnames(DEFAULT_TITLE_FORMAT));
+}
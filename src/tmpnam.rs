@@ -1,9 +1,12 @@
 use libc;
 use nix::sys::stat;
 use nix::unistd::mkfifo;
+use std::env;
 use std::error::Error;
 use std::ffi::CStr;
-use std::path::PathBuf;
+use std::fs::DirBuilder;
+use std::os::unix::fs::DirBuilderExt;
+use std::path::{Path, PathBuf};
 
 use crate::er::Result;
 
@@ -90,8 +93,42 @@ fn _mkfifo(pipenm: &PathBuf) -> Result<()> {
     }
 }
 
-pub fn tmpnam_and_mkfifo() -> Result<PathBuf> {
-    let pipenm = tmpnam()?;
+// Where do the fifos go?
+// config fifo_dir if set, else $XDG_RUNTIME_DIR (which is private to the user)
+// else fall back to /tmp like perl cssh.
+pub fn get_fifo_dir(fifo_dir: &Option<PathBuf>) -> PathBuf {
+    if let Some(dir) = fifo_dir {
+        return dir.clone();
+    }
+    if let Some(dir) = env::var_os("XDG_RUNTIME_DIR") {
+        let dir = PathBuf::from(dir);
+        if dir.is_dir() {
+            return dir;
+        }
+    }
+    PathBuf::from("/tmp")
+}
+
+// Create the fifo_dir (mode 700) if it does not exist yet.
+// An existing dir is left as is, it's the user's choice.
+pub fn create_fifo_dir(dir: &Path) -> Result<()> {
+    if dir.is_dir() {
+        return Ok(());
+    }
+    match DirBuilder::new().recursive(true).mode(0o700).create(dir) {
+        Ok(()) => Ok(()),
+        Err(e) => Err(format!("Failed to create fifo_dir {} {}", dir.display(), e).into()),
+    }
+}
+
+// tmpnam() only picks names within P_tmpdir, so borrow its file name
+// and move it to 'dir'.  Like perl cssh, if the name is already taken
+// then mkfifo fails, and so do we, see test_mkfifo_exists.
+pub fn tmpnam_and_mkfifo(dir: &Path) -> Result<PathBuf> {
+    let pipenm = match tmpnam()?.file_name() {
+        Some(file_name) => dir.join(file_name),
+        None => return Err("tmpnam returned no file name".into()),
+    };
     _mkfifo(&pipenm)?;
     Ok(pipenm)
 }
@@ -148,6 +185,58 @@ fn test_mkfifo_mode() {
     assert_eq!(x, Ok(()));
 }
 
+#[test]
+fn test_mkfifo_in_fifo_dir() {
+    // re-use tmpnam() as a dir name, nested one deeper to check it's created recursively
+    let top = tmpnam().unwrap();
+    let dir = top.join("fifos");
+    assert_eq!(create_fifo_dir(&dir), Ok(()));
+
+    let x: Result<()> = match dir.metadata() {
+        Ok(v) => {
+            use std::os::linux::fs::MetadataExt;
+            let mode = v.st_mode() & 0o777;
+            if mode == 0o700 {
+                Ok(())
+            } else {
+                Err(format!("fifo_dir mode expected 700 got {:o}", mode).into())
+            }
+        }
+        Err(e) => Err(format!("Failed to get metadata for dir {:?} {}", dir, e).into()),
+    };
+    assert_eq!(x, Ok(()));
+
+    let pipenm = tmpnam_and_mkfifo(&dir).unwrap();
+    assert_eq!(pipenm.parent(), Some(dir.as_path()));
+
+    let x: Result<()> = match pipenm.metadata() {
+        Ok(v) => {
+            use std::os::linux::fs::MetadataExt;
+            let mode = v.st_mode();
+            if 0o600 != (0o777 & mode) {
+                Err(format!("mkfifo mode expected 600 got {:o}", mode & 0o777).into())
+            } else if stat::SFlag::S_IFIFO.bits() != (stat::SFlag::S_IFIFO.bits() & mode) {
+                Err(format!("mode {:o} indicates {:?} is not a fifo", mode, pipenm).into())
+            } else {
+                Ok(())
+            }
+        }
+        Err(e) => Err(format!("Failed to get metadata for pipenm {:?} {}", pipenm, e).into()),
+    };
+    use std::fs::{remove_dir, remove_file};
+    remove_file(&pipenm).unwrap();
+    remove_dir(&dir).unwrap();
+    remove_dir(&top).unwrap();
+    assert_eq!(x, Ok(()));
+}
+
+#[test]
+fn test_get_fifo_dir() {
+    let configured = Some(PathBuf::from("/some/where"));
+    assert_eq!(get_fifo_dir(&configured), PathBuf::from("/some/where"));
+    assert!(get_fifo_dir(&None).is_dir());
+}
+
 #[test]
 fn test_mkfifo_stickybit() {
     let pipenm = tmpnam().unwrap();
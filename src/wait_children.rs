@@ -8,6 +8,7 @@ use std::sync::atomic::{AtomicBool, Ordering};
 
 use crate::app;
 use crate::er::Result;
+use crate::retile;
 
 // perl cssh installs this handler for SIGCHLD
 //    $SIG{CHLD} = sub {
@@ -80,6 +81,26 @@ pub fn setup_sig_chld_handler() -> Result<()> {
     }
 }
 
+// For running a command once we're up, e.g. external_cluster_command from
+// Hosts -> Add Host(s). Children which exit meanwhile are reaped afterwards.
+pub fn without_sig_chld_handler<T, F: FnOnce() -> T>(f: F) -> Result<T> {
+    let sig_action = signal::SigAction::new(
+        signal::SigHandler::SigDfl,
+        signal::SaFlags::empty(),
+        signal::SigSet::empty(),
+    );
+    unsafe {
+        if let Err(e) = signal::sigaction(signal::SIGCHLD, &sig_action) {
+            return Err(format!("Error resetting SIGCHLD handler {}", e.description()).into());
+        }
+    }
+    INSTALLED.store(false, Ordering::Relaxed);
+    let t = f();
+    setup_sig_chld_handler()?;
+    handle_sigchld(0);
+    Ok(t)
+}
+
 pub fn setup_poll_children(rapp: &app::Rapp) {
     let rapp = rapp.clone();
     gtk::timeout_add(500, move || poll_children_once(&rapp));
@@ -110,9 +131,14 @@ fn poll_children_once(rapp: &app::Rapp) -> gtk::Continue {
 
     if !dead_keys.is_empty() {
         for server_key in dead_keys.iter() {
-            if let Some(server) = app.servers.remove(server_key) {
+            if let Some(mut server) = app.servers.remove(server_key) {
                 server.terminate_host();
-                if let Some(ref g) = app.gtkstuff {
+                if app.config.misc.keep_dead_in_menu {
+                    if let Some(menu_item) = server.mark_menu_item_dead(server_key) {
+                        let connect_string = server.connect_string.clone();
+                        app.dead_menu_items.push((connect_string, menu_item));
+                    }
+                } else if let Some(ref g) = app.gtkstuff {
                     server.remove_menu_item(&g.hosts_menu);
                 }
                 app.dead_servers.push(server.connect_string);
@@ -124,6 +150,14 @@ fn poll_children_once(rapp: &app::Rapp) -> gtk::Continue {
         if let Some(ref g) = app.gtkstuff {
             g.change_main_window_title(&app);
         }
+        // Once per poll, however many closed since the last one.
+        if app.config.misc.auto_retile_on_close {
+            if n_servers == 0 {
+                let _ = app.show_console();
+            } else {
+                let _ = app.retile_hosts(false, false, retile::RetileScope::All);
+            }
+        }
     }
 
     // if no servers are left, maybe we quit
@@ -2,25 +2,61 @@
 use std::env;
 use std::ffi::CString;
 use std::os::raw::{c_int, c_uint, c_ulong};
+use std::thread;
+use std::time::Duration;
 use x11::xlib;
 
 use crate::app::Wid;
 use crate::candstr::CandStr;
+use crate::config::WmSizeHints;
 use crate::er::Result;
+use crate::log;
+use crate::macros;
 use crate::retile;
+use crate::send_text;
 
 #[derive(Debug, Default)]
 pub struct XDisplay {
     pub display: Option<*mut xlib::Display>,
     root: Wid,
+    screen_number: c_int,
     pub width_in_pixels: u32,
     pub height_in_pixels: u32,
     wm_normal_hints: xlib::Atom,
     wm_size_hints: xlib::Atom,
 }
 
+const DEFAULT_X_RETRIES: u32 = 3;
+
+// --x-retries, else $TCSSH_X_RETRIES, else a few.
+pub fn x_retries(from_args: Option<u32>) -> u32 {
+    from_args
+        .or_else(|| env::var("TCSSH_X_RETRIES").ok()?.parse().ok())
+        .unwrap_or(DEFAULT_X_RETRIES)
+}
+
+// Try open() once, then up to 'retries' more times, sleeping a little longer before each.
+// e.g. when started by a session script before the X server is quite ready.
+fn open_with_retries<T, O, S>(retries: u32, mut open: O, mut sleep: S) -> Option<T>
+where
+    O: FnMut() -> Option<T>,
+    S: FnMut(Duration),
+{
+    let mut delay = Duration::from_millis(250);
+    for attempt in 0..=retries {
+        if attempt > 0 {
+            sleep(delay);
+            delay = std::cmp::min(delay * 2, Duration::from_secs(2));
+        }
+        if let Some(opened) = open() {
+            return Some(opened);
+        }
+    }
+    None
+}
+
 impl XDisplay {
-    pub fn new() -> Result<XDisplay> {
+    pub fn new(retries: u32) -> Result<XDisplay> {
         let display_c = match env::var("DISPLAY") {
             Ok(e) => {
                 match CString::new(e) {
@@ -40,15 +76,31 @@ impl XDisplay {
         };
 
         let display_cptr = display_c.as_ptr();
-        let display_p = unsafe { xlib::XOpenDisplay(display_cptr) };
-        if display_p.is_null() {
-            return Err("Failed to get X connection".into());
-        }
+        let open = || {
+            let display_p = unsafe { xlib::XOpenDisplay(display_cptr) };
+            if display_p.is_null() {
+                None
+            } else {
+                Some(display_p)
+            }
+        };
+        let sleep = |delay: Duration| {
+            eprintln!(
+                "Failed to get X connection, retrying in {}ms",
+                delay.as_millis()
+            );
+            thread::sleep(delay);
+        };
+        let display_p = match open_with_retries(retries, open, sleep) {
+            Some(display_p) => display_p,
+            None => return Err("Failed to get X connection".into()),
+        };
 
         let screen = unsafe { xlib::XDefaultScreenOfDisplay(display_p) };
         if screen.is_null() {
             return Err("Failed to get screen".into());
         }
+        let screen_number = unsafe { xlib::XDefaultScreen(display_p) };
         let r = unsafe { (*screen).root };
         let w: i32 = unsafe { (*screen).width };
         let h: i32 = unsafe { (*screen).height };
@@ -58,6 +110,7 @@ impl XDisplay {
         Ok(XDisplay {
             display: Some(display_p),
             root: r as Wid,
+            screen_number,
             width_in_pixels: w as u32,
             height_in_pixels: h as u32,
             wm_normal_hints: get_atom(display_p, &CandStr::new(b"WM_NORMAL_HINTS\0"), false)?,
@@ -128,12 +181,95 @@ impl XDisplay {
         }
     }
 
+    pub fn iconify_window(&self, wid: Wid) {
+        if let Some(display) = self.display {
+            let _ = unsafe { xlib::XIconifyWindow(display, wid, self.screen_number) };
+        }
+    }
+
+    // iconified windows are restored by mapping them again.
+    pub fn deiconify_window(&self, wid: Wid) {
+        self.map_window(wid);
+    }
+
     pub fn raise_window(&self, wid: Wid) {
         if let Some(display) = self.display {
             let _ = unsafe { xlib::XRaiseWindow(display, wid) };
         }
     }
 
+    // For key_show_console, have the keysym (plus modifiers) come to us from anywhere on screen.
+    // Also grabbed with CapsLock and NumLock, which would otherwise stop it matching.
+    pub fn grab_key(&self, keysym: u32, modifiers: c_uint) -> Result<()> {
+        if let Some(display) = self.display {
+            let keycode = unsafe { xlib::XKeysymToKeycode(display, keysym.into()) };
+            if keycode == 0 {
+                return Err(format!("No keycode for keysym {}", keysym).into());
+            }
+            let locks = [
+                0,
+                xlib::LockMask,
+                xlib::Mod2Mask,
+                xlib::LockMask | xlib::Mod2Mask,
+            ];
+            for lock in &locks {
+                unsafe {
+                    xlib::XGrabKey(
+                        display,
+                        c_int::from(keycode),
+                        modifiers | lock,
+                        self.root,
+                        xlib::True,
+                        xlib::GrabModeAsync,
+                        xlib::GrabModeAsync,
+                    );
+                }
+            }
+            self.flush();
+        }
+        Ok(())
+    }
+
+    // Was a key from grab_key() pressed since we last looked?
+    // Nothing else is selected on our connection, so any KeyPress is one of those.
+    pub fn grabbed_key_pressed(&self) -> bool {
+        let mut pressed = false;
+        if let Some(display) = self.display {
+            let mut event: xlib::XEvent = unsafe { std::mem::zeroed() };
+            unsafe {
+                while xlib::XCheckTypedEvent(display, xlib::KeyPress, &mut event) != 0 {
+                    pressed = true;
+                }
+                while xlib::XCheckTypedEvent(display, xlib::KeyRelease, &mut event) != 0 {}
+            }
+        }
+        pressed
+    }
+
+    // Is the pointer touching an edge of the screen, see console_autohide_ms
+    pub fn pointer_at_screen_edge(&self) -> bool {
+        let display = match self.display {
+            Some(display) => display,
+            None => return false,
+        };
+        let (mut root, mut child): (xlib::Window, xlib::Window) = (0, 0);
+        let (mut x, mut y, mut win_x, mut win_y): (c_int, c_int, c_int, c_int) = (0, 0, 0, 0);
+        let mut mask: c_uint = 0;
+        let on_screen = unsafe {
+            xlib::XQueryPointer(
+                display, self.root, &mut root, &mut child, &mut x, &mut y, &mut win_x, &mut win_y,
+                &mut mask,
+            )
+        };
+        if on_screen == 0 {
+            return false;
+        }
+        x <= 0
+            || y <= 0
+            || x as u32 + 1 >= self.width_in_pixels
+            || y as u32 + 1 >= self.height_in_pixels
+    }
+
     pub fn close_display(&mut self) {
         if let Some(ptr) = self.display {
             unsafe {
@@ -143,14 +279,22 @@ impl XDisplay {
         }
     }
 
-    pub fn change_property(&self, wid: Wid, x: u32, y: u32, w: u32, h: u32) -> Result<()> {
+    pub fn change_property(
+        &self,
+        wid: Wid,
+        x: u32,
+        y: u32,
+        w: u32,
+        h: u32,
+        hints: WmSizeHints,
+    ) -> Result<()> {
         if let Some(display) = self.display {
             // A helper struct containing the size info we send to X11.
             // Re: perl cssh packs with 'L' so native endian-ness.
             #[derive(Debug, Clone, Copy)]
             #[repr(C)]
             struct MessageStruct {
-                three: u32,
+                flags: u32,
                 xywh: [u32; 4],
                 zeros: [u32; 12],
             };
@@ -162,11 +306,22 @@ impl XDisplay {
                 as_struct: MessageStruct,
             };
 
+            // zeros starts with min_width, min_height, max_width, max_height
+            let mut zeros = [0; 12];
+            if hints.has(WmSizeHints::P_MIN_SIZE) {
+                zeros[0] = w;
+                zeros[1] = h;
+            }
+            if hints.has(WmSizeHints::P_MAX_SIZE) {
+                zeros[2] = w;
+                zeros[3] = h;
+            }
+
             let message_union = MessageUnion {
                 as_struct: MessageStruct {
-                    three: 3, // perl cssh has a '1 | 2' hard coded. No idea what it stands for.
+                    flags: hints.flags, // see config::WmSizeHints
                     xywh: [x, y, w, h],
-                    zeros: [0; 12],
+                    zeros,
                 },
             };
 
@@ -227,46 +382,55 @@ impl XDisplay {
                 }
                 let result = unsafe {
                     // each *font and *p deref is unsafe.. so one block.
-                    match (*font).n_properties {
-                        n if n <= 0 => Err("XLoadQueryFont returned no properties".into()),
-                        n => {
-                            let n: isize = n as isize;
-                            let prop = (*font).properties;
-                            if prop.is_null() {
-                                Err("XLoadQueryFont returned null properties".into())
+                    let mut width = 0u32;
+                    let mut height = 0u32;
+                    let n = (*font).n_properties as isize;
+                    let prop = (*font).properties;
+                    if n > 0 && !prop.is_null() {
+                        for i in 0..n {
+                            let p = prop.offset(i);
+                            let name = (*p).name;
+                            if name == quad_width {
+                                // The member is called card32, and the X11 wire
+                                // protocol seems to be 32 bits (at least that's
+                                // what perl unpacks the binary as)
+                                // but for some odd reason the rust interface has
+                                // a c_ulong which is 64 bits.
+                                width = (*p).card32 as u32;
+                            } else if name == pixel_size {
+                                height = (*p).card32 as u32;
                             } else {
-                                let mut width = 0u32;
-                                let mut height = 0u32;
-                                for i in 0..n {
-                                    let p = prop.offset(i);
-                                    let name = (*p).name;
-                                    if name == quad_width {
-                                        // The member is called card32, and the X11 wire
-                                        // protocol seems to be 32 bits (at least that's
-                                        // what perl unpacks the binary as)
-                                        // but for some odd reason the rust interface has
-                                        // a c_ulong which is 64 bits.
-                                        width = (*p).card32 as u32;
-                                    } else if name == pixel_size {
-                                        height = (*p).card32 as u32;
-                                    } else {
-                                        continue;
-                                    }
-                                    if width > 0 && height > 0 {
-                                        break;
-                                    }
-                                }
-                                if width > 0 && height > 0 {
-                                    Ok((width, height))
-                                } else {
-                                    Err(format!("Fatal: Unrecognised font used ({}).\n\
-										Please amend $HOME/.tcssh/config with a valid font (see man page).\n\
-										XLoadQueryFont did not return font width/height", terminal_font)
-                                    .into())
-                                }
+                                continue;
+                            }
+                            if width > 0 && height > 0 {
+                                break;
                             }
                         }
                     }
+                    // Some server side fonts lack QUAD_WIDTH or PIXEL_SIZE,
+                    // so fall back to the font's own metrics.
+                    if width == 0 {
+                        width = i32::from((*font).max_bounds.width).max(0) as u32;
+                    }
+                    if width == 0 {
+                        let m = CandStr::new(b"M\0");
+                        width = xlib::XTextWidth(font, m.as_cstr.as_ptr(), 1).max(0) as u32;
+                    }
+                    if height == 0 {
+                        height = ((*font).ascent + (*font).descent).max(0) as u32;
+                    }
+                    if width > 0 && height > 0 {
+                        Ok((width, height))
+                    } else {
+                        Err(format!(
+                            "Fatal: Unrecognised font used ({}).\n\
+                            Please amend $HOME/.tcssh/config with a valid font (see man page).\n\
+                            XLoadQueryFont did not return font width/height, \
+                            not even via max_bounds, XTextWidth or ascent+descent",
+                            terminal_font
+                        )
+                        .into())
+                    }
                 };
                 // perl cssh didn't unload the font.. but since we don't need it (just sub process xterms)
                 let _ = unsafe { xlib::XFreeFont(display_p, font) }; // ignore Free's result
@@ -298,7 +462,10 @@ fn get_atom(
         atom if atom == u64::from(xlib::BadValue) => {
             Err(format!("XInternAtom returned BadValue for {}", name.as_str).into())
         }
-        atom => Ok(atom),
+        atom => {
+            log!(macros::DEBUG_X, "XInternAtom {} = {}", name.as_str, atom);
+            Ok(atom)
+        }
     }
 }
 
@@ -320,3 +487,62 @@ impl retile::RetileXDisplay for XDisplay {
         self.unmap_window(wid);
     }
 }
+
+// send_text.rs' key events, as XSendEvent()s
+impl send_text::SendSink for XDisplay {
+    fn send_event(&self, wid: Wid, state: u32, keycode: u32) -> Result<()> {
+        self.send_event(wid, state, keycode)
+    }
+    fn flush(&self) {
+        self.flush();
+    }
+}
+
+#[test]
+fn test_open_with_retries() {
+    let mut slept = Vec::new();
+    let mut attempts = 0;
+    // fast path, no sleeping
+    let opened = open_with_retries(
+        3,
+        || {
+            attempts += 1;
+            Some(attempts)
+        },
+        |delay| slept.push(delay.as_millis()),
+    );
+    assert_eq!(opened, Some(1));
+    assert!(slept.is_empty());
+
+    // ready on the third attempt
+    let mut attempts = 0;
+    let opened = open_with_retries(
+        3,
+        || {
+            attempts += 1;
+            if attempts == 3 {
+                Some(attempts)
+            } else {
+                None
+            }
+        },
+        |delay| slept.push(delay.as_millis()),
+    );
+    assert_eq!(opened, Some(3));
+    assert_eq!(slept, vec![250, 500]);
+
+    // never ready, 1 + 5 attempts, the delays capped at 2s
+    let mut slept = Vec::new();
+    let mut attempts = 0;
+    let opened: Option<()> = open_with_retries(
+        5,
+        || {
+            attempts += 1;
+            None
+        },
+        |delay| slept.push(delay.as_millis()),
+    );
+    assert_eq!(opened, None);
+    assert_eq!(attempts, 6);
+    assert_eq!(slept, vec![250, 500, 1000, 2000, 2000]);
+}